@@ -0,0 +1,262 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Builds a cpio archive in the newc format, which is what the kernel expects as initramfs
+#[derive(Default)]
+pub struct CpioWriter {
+    data: Vec<u8>,
+    next_ino: u32,
+    /// inode numbers handed out to files with multiple links, keyed by their device and inode on the host
+    links: HashMap<(u64, u64), u32>,
+}
+
+impl CpioWriter {
+    /// Appends the contents of `dir` recursively, the names in the archive are prefixed with `prefix`
+    ///
+    /// The owner of every entry is root, the permissions and modification times are taken from the host.
+    pub fn append_tree(&mut self, dir: &Path, prefix: &str) -> Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        // sorted so the archive doesn't depend on the order of the host file system
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+            let metadata = fs::symlink_metadata(entry.path())?;
+            let mtime = metadata.mtime() as u32;
+
+            match metadata.mode() & S_IFMT {
+                S_IFDIR => {
+                    let ino = self.next_ino();
+                    self.append(&name, ino, metadata.mode(), 2, mtime, &[]);
+                    self.append_tree(&entry.path(), &format!("{name}/"))?;
+                }
+                S_IFLNK => {
+                    let target = fs::read_link(entry.path())?;
+                    let ino = self.next_ino();
+                    self.append(
+                        &name,
+                        ino,
+                        metadata.mode(),
+                        1,
+                        mtime,
+                        target.to_string_lossy().as_bytes(),
+                    );
+                }
+                _ if metadata.nlink() > 1 => {
+                    let key = (metadata.dev(), metadata.ino());
+                    let nlink = metadata.nlink() as u32;
+
+                    // only the first name of a hard linked file carries the data
+                    if let Some(&ino) = self.links.get(&key) {
+                        self.append(&name, ino, metadata.mode(), nlink, mtime, &[]);
+                    } else {
+                        let ino = self.next_ino();
+                        self.links.insert(key, ino);
+                        self.append(&name, ino, metadata.mode(), nlink, mtime, &fs::read(entry.path())?);
+                    }
+                }
+                _ => {
+                    let ino = self.next_ino();
+                    self.append(&name, ino, metadata.mode(), 1, mtime, &fs::read(entry.path())?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends a regular file with the permissions `perm`
+    pub fn append_file(&mut self, name: &str, perm: u32, data: &[u8]) {
+        let ino = self.next_ino();
+        self.append(name, ino, 0o100000 | perm, 1, 0, data);
+    }
+
+    /// Appends the trailer and returns the archive
+    pub fn finish(mut self) -> Vec<u8> {
+        self.append("TRAILER!!!", 0, 0, 1, 0, &[]);
+        self.data
+    }
+
+    fn next_ino(&mut self) -> u32 {
+        self.next_ino += 1;
+        self.next_ino
+    }
+
+    fn append(&mut self, name: &str, ino: u32, mode: u32, nlink: u32, mtime: u32, data: &[u8]) {
+        // ino, mode, uid, gid, nlink, mtime, filesize, devmajor, devminor, rdevmajor, rdevminor, namesize, check
+        let fields = [
+            ino,
+            mode,
+            0,
+            0,
+            nlink,
+            mtime,
+            data.len() as u32,
+            0,
+            0,
+            0,
+            0,
+            name.len() as u32 + 1,
+            0,
+        ];
+
+        self.data.extend(b"070701");
+
+        for field in fields {
+            self.data.extend(format!("{field:08x}").as_bytes());
+        }
+
+        self.data.extend(name.as_bytes());
+        self.data.push(0);
+        self.pad();
+
+        self.data.extend(data);
+        self.pad();
+    }
+
+    /// The header together with the name and the data are aligned to 4 bytes
+    fn pad(&mut self) {
+        let len = self.data.len().next_multiple_of(4);
+        self.data.resize(len, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    const HEADER_SIZE: usize = 110;
+
+    struct Entry {
+        /// the header fields after the magic, in the order they are written
+        fields: [u32; 13],
+        name: String,
+        data: Vec<u8>,
+    }
+
+    /// Splits the archive into its entries and checks the framing on the way
+    fn parse(archive: &[u8]) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        loop {
+            let header = &archive[offset..offset + HEADER_SIZE];
+            assert_eq!(&header[..6], b"070701");
+
+            let fields = std::array::from_fn(|index| {
+                let digits = std::str::from_utf8(&header[6 + index * 8..14 + index * 8]).unwrap();
+                u32::from_str_radix(digits, 16).unwrap()
+            });
+
+            let name_start = offset + HEADER_SIZE;
+            let name_end = name_start + fields[11] as usize;
+            assert_eq!(archive[name_end - 1], 0);
+
+            let data_start = name_end.next_multiple_of(4);
+            let data_end = data_start + fields[6] as usize;
+            let next = data_end.next_multiple_of(4);
+
+            assert!(archive[name_end..data_start].iter().all(|byte| *byte == 0));
+            assert!(archive[data_end..next].iter().all(|byte| *byte == 0));
+
+            let entry = Entry {
+                fields,
+                name: String::from_utf8(archive[name_start..name_end - 1].to_vec()).unwrap(),
+                data: archive[data_start..data_end].to_vec(),
+            };
+
+            offset = next;
+
+            let last = entry.name == "TRAILER!!!";
+            entries.push(entry);
+
+            if last {
+                break;
+            }
+        }
+
+        assert_eq!(offset, archive.len());
+
+        entries
+    }
+
+    #[test]
+    fn file_round_trips() {
+        let mut writer = CpioWriter::default();
+        writer.append_file("init", 0o755, b"hello");
+        let archive = writer.finish();
+
+        // "init" with its NUL and "hello" both end 1 byte after a multiple of 4 and are padded by 3 bytes
+        assert_eq!(archive.len(), 124 + 124);
+        assert_eq!(&archive[..14], b"07070100000001");
+
+        let entries = parse(&archive);
+        assert_eq!(entries.len(), 2);
+
+        let file = &entries[0];
+        assert_eq!(file.fields, [1, 0o100755, 0, 0, 1, 0, 5, 0, 0, 0, 0, 5, 0]);
+        assert_eq!(file.name, "init");
+        assert_eq!(file.data, b"hello");
+
+        let trailer = &entries[1];
+        assert_eq!(trailer.fields, [0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 11, 0]);
+        assert!(trailer.data.is_empty());
+    }
+
+    #[test]
+    fn tree_round_trips() {
+        let dir = std::env::temp_dir().join(format!("xtask-cpio-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        fs::write(dir.join("bin/sh"), b"shell").unwrap();
+        fs::write(dir.join("first"), b"linked data").unwrap();
+        fs::hard_link(dir.join("first"), dir.join("second")).unwrap();
+        symlink("bin/sh", dir.join("sh")).unwrap();
+
+        let mut writer = CpioWriter::default();
+        let result = writer.append_tree(&dir, "./");
+        fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+
+        let entries = parse(&writer.finish());
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["./bin", "./bin/sh", "./first", "./second", "./sh", "TRAILER!!!"]
+        );
+
+        let [bin, sh, first, second, link, _] = &entries[..] else {
+            unreachable!();
+        };
+
+        assert_eq!(bin.fields[1] & S_IFMT, S_IFDIR);
+        assert_eq!(bin.fields[4], 2);
+        assert!(bin.data.is_empty());
+
+        assert_eq!(sh.fields[1] & S_IFMT, 0o100000);
+        assert_eq!(sh.data, b"shell");
+
+        // both names of the hard linked file share the inode, only the first one carries the data
+        assert_eq!(first.fields[0], second.fields[0]);
+        assert_eq!((first.fields[4], second.fields[4]), (2, 2));
+        assert_eq!(first.data, b"linked data");
+        assert!(second.data.is_empty());
+
+        assert_eq!(link.fields[1] & S_IFMT, S_IFLNK);
+        assert_eq!(link.data, b"bin/sh");
+
+        let mut inodes: Vec<u32> = [bin, sh, first, link].iter().map(|entry| entry.fields[0]).collect();
+        inodes.sort();
+        inodes.dedup();
+        assert_eq!(inodes.len(), 4);
+    }
+}
@@ -1,4 +1,7 @@
+mod cpio;
+
 use anyhow::{Result, bail};
+use cpio::CpioWriter;
 use dotenv::dotenv;
 use fatfs::{FormatVolumeOptions, format_volume};
 use pico_args::Arguments;
@@ -20,12 +23,16 @@ FLAGS:
     --wsl-qemu      If you use wsl but got a X server installed like GWSL you can use this flag to say you want to use the qemu you've got installed with your wsl distro and not on windows (also possible to use a env variable called qemu_in_wsl and setting it to true)
     --kvm           Use KVM for QEMU (default: false).
     --monitor       Enable QEMU monitor 
+    --disk          Attach the given disk image, which the kernel provides as RAM disk /dev/ram0 (Can only be used with the build or run subcommand)
+    --boot-disk     Attach a FAT32 copy of the boot files, which the kernel mounts from the RAM disk /dev/ram1 on /boot (Can only be used with the build or run subcommand)
+    --ext2          Generate an ext2 disk image from the given directory, which the kernel mounts from the RAM disk /dev/ram2 on /mnt (Can only be used with the build or run subcommand)
 SUBCOMMANDS:
     build           Build the kernel without running it.
     run             Build and run the kernel using QEMU.
     fmt             Run cargo fmt
     clippy          Run clippy
     lint            Run clippy and cargo fmt
+    test            Run the unit tests on the host
     clean           Cleans the limine clone and runs cargo clean
 ";
 
@@ -72,6 +79,9 @@ fn main() -> Result<()> {
         Some("clippy") => {
             clippy(&sh)?;
         }
+        Some("test") => {
+            test(&sh)?;
+        }
 
         Some("help") => {
             print!("{}", HELP);
@@ -109,6 +119,10 @@ fn build(sh: &Shell, rl: bool, mut args: Arguments) -> Result<()> {
         sh.change_dir(root());
     }
 
+    let disk = args.opt_value_from_str::<_, String>("--disk")?;
+    let boot_disk = args.contains("--boot-disk");
+    let ext2_dir = args.opt_value_from_str::<_, String>("--ext2")?;
+
     let release = if rl { &["--release"] } else { &[][..] };
 
     cmd!(
@@ -144,8 +158,31 @@ fn build(sh: &Shell, rl: bool, mut args: Arguments) -> Result<()> {
 
     create_initramfs()?;
 
+    let kernel_path = format!("{xernel_dir}/target/{target}/{build_dir}/xernel");
+    let logo_path = format!("{xernel_dir}/logo.bmp");
+    let initramfs_path = format!("{xernel_dir}/target/initramfs");
+
+    // the disk images are loaded as limine modules, so they have to fit into the boot image as well
+    let disk_data = disk.as_ref().map(fs::read).transpose()?;
+
+    let boot_volume = if boot_disk {
+        Some(create_boot_volume(&[
+            (&kernel_path, "xernel"),
+            (&logo_path, "logo.bmp"),
+            (&initramfs_path, "initramfs"),
+        ])?)
+    } else {
+        None
+    };
+
+    let ext2_image = ext2_dir.map(|dir| create_ext2_image(sh, &dir)).transpose()?;
+
     let diskname = "xernel.hdd";
-    let disksize = 64 * 1024 * 1024; // 64 MB
+    // 64 MB and the disk images
+    let disksize = 64 * 1024 * 1024
+        + disk_data.as_ref().map_or(0, Vec::len)
+        + boot_volume.as_ref().map_or(0, Vec::len)
+        + ext2_image.as_ref().map_or(0, Vec::len);
 
     let data_vec = vec![0_u8; disksize];
     let mut disk = Cursor::new(data_vec);
@@ -156,20 +193,35 @@ fn build(sh: &Shell, rl: bool, mut args: Arguments) -> Result<()> {
     {
         let root_dir = fs.root_dir();
 
-        copy_to_image(
-            &root_dir,
-            &format!("{xernel_dir}/target/{target}/{build_dir}/xernel"),
-            "xernel",
-        )?;
+        copy_to_image(&root_dir, &kernel_path, "xernel")?;
+        copy_to_image(&root_dir, &logo_path, "logo.bmp")?;
+        copy_to_image(&root_dir, &initramfs_path, "initramfs")?;
+
+        let mut limine_conf = fs::read_to_string(format!("{xernel_dir}/kernel/limine.conf"))?;
+
+        if let Some(disk_data) = &disk_data {
+            root_dir.create_file("ram0.img")?.write_all(disk_data)?;
+
+            limine_conf.push_str("\n  module_path: boot():/ram0.img\n  module_cmdline: \"ram0\"\n");
+        }
+
+        if let Some(boot_volume) = &boot_volume {
+            root_dir.create_file("ram1.img")?.write_all(boot_volume)?;
 
-        copy_to_image(&root_dir, &format!("{xernel_dir}/logo.bmp"), "logo.bmp")?;
-        copy_to_image(&root_dir, &format!("{xernel_dir}/target/initramfs"), "initramfs")?;
+            limine_conf.push_str("\n  module_path: boot():/ram1.img\n  module_cmdline: \"ram1\"\n");
+        }
+
+        if let Some(ext2_image) = &ext2_image {
+            root_dir.create_file("ram2.img")?.write_all(ext2_image)?;
+
+            limine_conf.push_str("\n  module_path: boot():/ram2.img\n  module_cmdline: \"ram2\"\n");
+        }
 
         let dir = root_dir.create_dir("EFI")?;
         let dir = dir.create_dir("BOOT")?;
 
         copy_to_image(&dir, &format!("{xernel_dir}/kernel/limine/BOOTX64.EFI"), "BOOTX64.EFI")?;
-        copy_to_image(&dir, &format!("{xernel_dir}/kernel/limine.conf"), "limine.conf")?;
+        dir.create_file("limine.conf")?.write_all(limine_conf.as_bytes())?;
     }
     fs.unmount()?;
 
@@ -179,38 +231,103 @@ fn build(sh: &Shell, rl: bool, mut args: Arguments) -> Result<()> {
 }
 
 fn create_initramfs() -> Result<()> {
-    // file format of the initramfs:
-    // 1. name of the file (16 byte)
-    // 2. size of the file (u64)
-    // 3. the file data
-    // 4. ... the next files until the end of the initramfs file
-
-    let mut data = Vec::new();
+    // the initramfs is a cpio archive in the newc format, so it can also be created and inspected with
+    // standard tools, e.g. `find . | cpio -o -H newc`
 
     let binding = root();
     let xernel_dir = binding.to_str().unwrap_or(".");
 
-    // (name, path)
-    let files = vec![("init", format!("{xernel_dir}/target/init"))];
-
-    for file in files {
-        let name = file.0;
-        let path = file.1;
+    let mut archive = CpioWriter::default();
 
-        let file_data = fs::read(path)?;
-        let mut name_vec = name.as_bytes().to_vec();
-        name_vec.resize(16, 0);
+    // everything inside the initramfs directory of the repository is packed as is
+    let tree = Path::new(xernel_dir).join("initramfs");
 
-        data.extend(&name_vec);
-        data.extend(&(file_data.len() as u64).to_le_bytes());
-        data.extend(&file_data);
+    if tree.is_dir() {
+        archive.append_tree(&tree, "")?;
     }
 
-    fs::write(format!("{xernel_dir}/target/initramfs"), data)?;
+    archive.append_file("init", 0o755, &fs::read(format!("{xernel_dir}/target/init"))?);
+
+    fs::write(format!("{xernel_dir}/target/initramfs"), archive.finish())?;
 
     Ok(())
 }
 
+/// Creates a FAT32 volume which holds a copy of the given files, as (source path, name on the volume)
+///
+/// The kernel can't access the boot image itself, so this copy is attached as RAM disk for the kernel to mount.
+fn create_boot_volume(files: &[(&str, &str)]) -> Result<Vec<u8>> {
+    let data = files
+        .iter()
+        .map(|(path, _)| fs::read(path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // FAT32 needs at least 65525 clusters, which are as small as possible to keep the image small
+    let size = 34 * 1024 * 1024 + data.iter().map(Vec::len).sum::<usize>();
+    let mut volume = Cursor::new(vec![0_u8; size]);
+
+    format_volume(
+        &mut volume,
+        FormatVolumeOptions::new()
+            .fat_type(fatfs::FatType::Fat32)
+            .bytes_per_cluster(512),
+    )?;
+
+    let fs = fatfs::FileSystem::new(&mut volume, fatfs::FsOptions::new())?;
+    {
+        let root_dir = fs.root_dir();
+
+        for ((_, name), data) in files.iter().zip(&data) {
+            root_dir.create_file(name)?.write_all(data)?;
+        }
+    }
+    fs.unmount()?;
+
+    Ok(volume.into_inner())
+}
+
+/// Creates an ext2 file system which holds a copy of the directory `dir`, using `mke2fs` of the host
+///
+/// The files are owned by root in the image, the rest of the image is left free for tests which write to it.
+fn create_ext2_image(sh: &Shell, dir: &str) -> Result<Vec<u8>> {
+    let binding = root();
+    let xernel_dir = binding.to_str().unwrap_or(".");
+
+    if !Path::new(dir).is_dir() {
+        bail!("'{}' is not a directory", dir);
+    }
+
+    let image = format!("{xernel_dir}/target/ext2.img");
+    // in KiB, twice the size of the files and room for the metadata
+    let size = (2 * dir_size(Path::new(dir))? / 1024 + 8 * 1024).to_string();
+
+    cmd!(
+        sh,
+        "mke2fs -q -F -t ext2 -b 1024 -E root_owner=0:0 -d {dir} {image} {size}k"
+    )
+    .run()?;
+
+    Ok(fs::read(image)?)
+}
+
+/// Returns the size of all files below `path`
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(size)
+}
+
 fn run(sh: &Shell, gdb: bool, mut args: Arguments) -> Result<()> {
     let gdb_debug = if gdb { &["-S"] } else { &[][..] };
 
@@ -282,6 +399,15 @@ fn clippy(sh: &Shell) -> Result<()> {
     Ok(())
 }
 
+fn test(sh: &Shell) -> Result<()> {
+    let _cwd = sh.push_dir(root());
+
+    // the kernel is built for the host, with the standard library and its test harness
+    cmd!(sh, "cargo test -p xernel -p xtask").run()?;
+
+    Ok(())
+}
+
 fn fmt(sh: &Shell, check: bool) -> Result<()> {
     let _cwd = sh.push_dir(root());
 
@@ -8,25 +8,226 @@ pub const SYS_OPEN: usize = 2;
 pub const SYS_CLOSE: usize = 3;
 pub const SYS_MMAP: usize = 4;
 pub const SYS_LOG: usize = 5;
+pub const SYS_FORK: usize = 6;
+pub const SYS_EXECVE: usize = 7;
+pub const SYS_EXIT: usize = 8;
+pub const SYS_WAIT4: usize = 9;
+pub const SYS_MSYNC: usize = 10;
+pub const SYS_MUNMAP: usize = 11;
+pub const SYS_MPROTECT: usize = 12;
+pub const SYS_LSEEK: usize = 13;
+pub const SYS_PREAD: usize = 14;
+pub const SYS_PWRITE: usize = 15;
+pub const SYS_DUP: usize = 16;
+pub const SYS_DUP2: usize = 17;
+pub const SYS_FCNTL: usize = 18;
+pub const SYS_MKDIR: usize = 19;
+pub const SYS_RMDIR: usize = 20;
+pub const SYS_GETDENTS: usize = 21;
+pub const SYS_UNLINK: usize = 22;
+pub const SYS_RENAME: usize = 23;
+pub const SYS_LINK: usize = 24;
+pub const SYS_SYMLINK: usize = 25;
+pub const SYS_READLINK: usize = 26;
+pub const SYS_STAT: usize = 27;
+pub const SYS_FSTAT: usize = 28;
+pub const SYS_CHMOD: usize = 29;
+pub const SYS_UTIMENS: usize = 30;
+pub const SYS_CHDIR: usize = 31;
+pub const SYS_GETCWD: usize = 32;
+pub const SYS_OPENAT: usize = 33;
+pub const SYS_MOUNT: usize = 34;
+pub const SYS_UMOUNT: usize = 35;
 
+/// Passed as the directory fd of the *at syscalls to resolve relative paths from the working directory
+pub const AT_FDCWD: isize = -100;
+
+// constants for the whence argument of lseek
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+// constants for fcntl
+
+pub const F_DUPFD: usize = 0;
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const F_DUPFD_CLOEXEC: usize = 1030;
+
+pub const FD_CLOEXEC: usize = 1;
+
+// constants for the type of a directory entry returned by getdents
+
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+pub const DT_SOCK: u8 = 12;
+
+// constants for the file type and permission bits of st_mode
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFSOCK: u32 = 0o140000;
+pub const S_IFLNK: u32 = 0o120000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFIFO: u32 = 0o010000;
+
+/// Mask of the permission bits including setuid, setgid and sticky
+pub const S_IALLUGO: u32 = 0o7777;
+
+// special values for tv_nsec of the times passed to utimens
+
+pub const UTIME_NOW: i64 = (1 << 30) - 1;
+pub const UTIME_OMIT: i64 = (1 << 30) - 2;
+
+/// Point in time as seconds and nanoseconds since the Unix epoch, layout of `struct timespec`
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(C)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+/// File status returned by stat and fstat, layout of `struct stat` on x86_64 Linux
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct Stat {
+    pub st_dev: u64,
+    pub st_ino: u64,
+    pub st_nlink: u64,
+    pub st_mode: u32,
+    pub st_uid: u32,
+    pub st_gid: u32,
+    pub __pad0: u32,
+    pub st_rdev: u64,
+    pub st_size: i64,
+    pub st_blksize: i64,
+    pub st_blocks: i64,
+    pub st_atim: Timespec,
+    pub st_mtim: Timespec,
+    pub st_ctim: Timespec,
+    pub __unused: [i64; 3],
+}
+
+// constants for the auxiliary vector which is passed to a new program on its stack
+
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+
+/// Errors returned by syscalls
+///
+/// The values are the negated Linux errno values, so a C runtime can use them without translation.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 #[repr(isize)]
 pub enum SyscallError {
+    /// EPERM
     NoPermission = -1,
-    VNodeNotFound = -2,
-    NotADirectory = -3,
-    IsADirectory = -4,
-    NoSpace = -5,
-    NotEmpty = -6,
-    EntryNotFound = -7,
-    MountPointNotFound = -8,
-    FileSystemNotFound = -9,
-    MalformedPath = -10,
-    InvalidArgument = -11,
+    /// ENOENT
+    EntryNotFound = -2,
+    /// ESRCH
+    NoSuchProcess = -3,
+    /// EINTR
+    Interrupted = -4,
+    /// EIO
+    IoError = -5,
+    /// ENXIO
+    NoSuchDeviceOrAddress = -6,
+    /// E2BIG
+    ArgumentListTooLong = -7,
+    /// ENOEXEC
+    ExecFormatError = -8,
+    /// EBADF
+    BadFileDescriptor = -9,
+    /// ECHILD
+    NoChildProcess = -10,
+    /// EAGAIN
+    TryAgain = -11,
+    /// ENOMEM
+    NoMemory = -12,
+    /// EACCES
+    PermissionDenied = -13,
+    /// EFAULT
+    BadAddress = -14,
+    /// ENOTBLK
+    NotABlockDevice = -15,
+    /// EBUSY
+    Busy = -16,
+    /// EEXIST
+    AlreadyExists = -17,
+    /// EXDEV
+    CrossDeviceLink = -18,
+    /// ENODEV
+    NoSuchDevice = -19,
+    /// ENOTDIR
+    NotADirectory = -20,
+    /// EISDIR
+    IsADirectory = -21,
+    /// EINVAL
+    InvalidArgument = -22,
+    /// ENFILE
+    FileTableOverflow = -23,
+    /// EMFILE
+    TooManyOpenFiles = -24,
+    /// ENOTTY
+    NotATerminal = -25,
+    /// ETXTBSY
+    TextFileBusy = -26,
+    /// EFBIG
+    FileTooLarge = -27,
+    /// ENOSPC
+    NoSpace = -28,
+    /// ESPIPE
+    IllegalSeek = -29,
+    /// EROFS
+    ReadOnlyFileSystem = -30,
+    /// EMLINK
+    TooManyLinks = -31,
+    /// EPIPE
+    BrokenPipe = -32,
+    /// EDOM
+    OutOfDomain = -33,
+    /// ERANGE
+    OutOfRange = -34,
+    /// EDEADLK
+    Deadlock = -35,
+    /// ENAMETOOLONG
+    NameTooLong = -36,
+    /// ENOLCK
+    NoLocks = -37,
+    /// ENOSYS
+    NotImplemented = -38,
+    /// ENOTEMPTY
+    NotEmpty = -39,
+    /// ELOOP
+    TooManySymlinks = -40,
+    /// EOVERFLOW
+    Overflow = -75,
+    /// EOPNOTSUPP
+    NotSupported = -95,
+    /// ETIMEDOUT
+    TimedOut = -110,
+}
+
+impl SyscallError {
+    /// Returns the positive errno value of the error
+    pub const fn errno(self) -> i32 {
+        -(self as isize) as i32
+    }
 }
 
 bitflags! {
-    #[derive(Clone, Copy)]
+    #[derive(Clone, Copy, PartialEq, Eq)]
     pub struct ProtectionFlags: u8 {
         const READ = 1 << 0;
         const WRITE = 1 << 1;
@@ -35,7 +236,59 @@ bitflags! {
 }
 
 bitflags! {
-    #[derive(PartialEq, Eq)]
+    #[derive(Clone, Copy)]
+    pub struct MsyncFlags: usize {
+        const ASYNC = 1 << 0;
+        const INVALIDATE = 1 << 1;
+        const SYNC = 1 << 2;
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy)]
+    pub struct WaitOptions: usize {
+        const NOHANG = 1 << 0;
+    }
+}
+
+bitflags! {
+    /// Flags of open and openat, a file without `WRITE_ONLY` or `READ_WRITE` is opened for reading only
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct OpenFlags: usize {
+        const WRITE_ONLY = 0o1;
+        const READ_WRITE = 0o2;
+        const CREATE = 0o100;
+        const EXCLUSIVE = 0o200;
+        const TRUNCATE = 0o1000;
+        const APPEND = 0o2000;
+        const DIRECTORY = 0o200000;
+        const CLOEXEC = 0o2000000;
+    }
+}
+
+impl OpenFlags {
+    pub fn is_readable(self) -> bool {
+        !self.contains(OpenFlags::WRITE_ONLY)
+    }
+
+    pub fn is_writable(self) -> bool {
+        self.intersects(OpenFlags::WRITE_ONLY | OpenFlags::READ_WRITE)
+    }
+}
+
+bitflags! {
+    /// Flags of a mounted file system, the values match the `MS_*` flags of Linux
+    #[derive(Clone, Copy, PartialEq, Eq, Default)]
+    pub struct MountFlags: usize {
+        const READ_ONLY = 1 << 0;
+        /// Set-user-ID and set-group-ID bits are ignored, which they always are as processes have no credentials yet
+        const NO_SUID = 1 << 1;
+        const NO_EXEC = 1 << 3;
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
     pub struct MapFlags: u8 {
         const SHARED = 1 << 0;
         const PRIVATE = 1 << 1;
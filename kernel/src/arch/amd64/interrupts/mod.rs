@@ -1,9 +1,12 @@
 pub mod idt;
 
 use crate::arch::amd64::apic::APIC;
+use crate::arch::amd64::tlb::{TLB_SHOOTDOWN_VECTOR, tlb_shootdown_handler};
+use crate::arch::amd64::user_copy::search_exception_fixup;
 use crate::arch::amd64::{ports::outb, read_cr2};
 use crate::dpc::dispatch_dpcs;
 use crate::drivers::ps2::keyboard::keyboard_handler;
+use crate::mem::mmap::handle_page_fault;
 use crate::sched::context::TrapFrame;
 use core::arch::asm;
 use core::sync::atomic::{Ordering, compiler_fence};
@@ -12,6 +15,7 @@ use libxernel::ipl::{IPL, get_ipl, raise_ipl, splx};
 
 use super::apic::apic_spurious_interrupt;
 use libxernel::sync::SpinlockIRQ;
+use x86_64::structures::idt::PageFaultErrorCode;
 
 static INTERRUPT_HANDLERS: SpinlockIRQ<[IRQHandler; IDT_ENTRIES]> = SpinlockIRQ::new([IRQHandler::None; IDT_ENTRIES]);
 
@@ -27,6 +31,7 @@ pub fn init() {
     // TODO: allocate vectors accordingly or manually set all known interrupt handlers here
     handlers[0x2f] = IRQHandler::Handler(dispatch_dpcs);
     handlers[0xd0] = IRQHandler::Handler(keyboard_handler);
+    handlers[TLB_SHOOTDOWN_VECTOR] = IRQHandler::Handler(tlb_shootdown_handler);
 }
 
 #[unsafe(no_mangle)]
@@ -120,6 +125,18 @@ fn double_fault_handler(frame: &mut TrapFrame) {
 }
 
 fn page_fault_handler(frame: &mut TrapFrame) {
+    let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+
+    if handle_page_fault(read_cr2(), error_code) {
+        return;
+    }
+
+    // a kernel access to user memory which can't be resolved makes the copy fail instead of crashing
+    if let Some(fixup) = search_exception_fixup(frame.rip) {
+        frame.rip = fixup;
+        return;
+    }
+
     dbg!("EXCEPTION: PAGE FAULT");
     dbg!("Accessed Address: {:?}", read_cr2());
     dbg!("Error Code: {:?}", frame.error_code);
@@ -5,7 +5,9 @@ pub mod interrupts;
 mod ioapic;
 mod lapic;
 pub mod ports;
+pub mod tlb;
 pub mod tsc;
+pub mod user_copy;
 
 use crate::KERNEL_PAGE_MAPPER;
 use crate::arch::amd64::apic::APIC;
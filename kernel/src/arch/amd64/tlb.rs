@@ -0,0 +1,58 @@
+use core::arch::asm;
+use core::sync::atomic::Ordering;
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::arch::amd64::apic::APIC;
+use crate::cpu::{CPUS, current_cpu};
+use crate::sched::context::TrapFrame;
+
+/// Interrupt vector of the TLB shootdown IPI, it runs at the highest IPL so it can't be blocked by a waiting CPU
+pub const TLB_SHOOTDOWN_VECTOR: usize = 0xf1;
+
+#[inline]
+pub fn invlpg(addr: VirtAddr) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) addr.as_u64(), options(nostack, preserves_flags));
+    }
+}
+
+/// Invalidates the given pages on all other CPUs which have the page table `pml4` loaded
+///
+/// Returns once every CPU flushed the pages, so frames which were mapped by them can be freed afterwards.
+/// The TLB of the current CPU has to be flushed by the caller.
+pub fn shootdown(pml4: PhysAddr, pages: &[VirtAddr]) {
+    if pages.is_empty() {
+        return;
+    }
+
+    let current_cpu_id = current_cpu().cpu_id;
+    let cpus = CPUS.lock().clone();
+
+    let targets = cpus
+        .iter()
+        .filter(|cpu| cpu.cpu_id != current_cpu_id && cpu.loaded_pml4.load(Ordering::Acquire) == pml4.as_u64());
+
+    for cpu in targets.clone() {
+        cpu.pending_invalidations.lock().extend_from_slice(pages);
+        APIC.send_ipi(cpu.lapic_id, TLB_SHOOTDOWN_VECTOR as u32);
+    }
+
+    for cpu in targets {
+        while !cpu.pending_invalidations.lock().is_empty() {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+pub fn tlb_shootdown_handler(_: &mut TrapFrame) {
+    let cpu = current_cpu();
+    let mut pages = cpu.pending_invalidations.lock();
+
+    for page in pages.iter() {
+        invlpg(*page);
+    }
+
+    // the initiating CPU waits until the list is empty, so it must only be cleared after the flush
+    pages.clear();
+}
@@ -0,0 +1,46 @@
+use core::arch::global_asm;
+
+global_asm!(include_str!("user_copy.S"));
+
+unsafe extern "sysv64" {
+    fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+}
+
+unsafe extern "C" {
+    static user_copy_start: u8;
+    static user_copy_end: u8;
+    static user_copy_fixup: u8;
+}
+
+/// Faults with an instruction pointer between `start` and `end` continue at `fixup` instead of crashing the kernel
+struct ExceptionFixup {
+    start: u64,
+    end: u64,
+    fixup: u64,
+}
+
+fn exception_fixups() -> [ExceptionFixup; 1] {
+    [ExceptionFixup {
+        start: &raw const user_copy_start as u64,
+        end: &raw const user_copy_end as u64,
+        fixup: &raw const user_copy_fixup as u64,
+    }]
+}
+
+/// Returns the address execution should continue at, if the faulting instruction has an exception fixup
+pub fn search_exception_fixup(rip: u64) -> Option<u64> {
+    exception_fixups()
+        .iter()
+        .find(|entry| entry.start <= rip && rip < entry.end)
+        .map(|entry| entry.fixup)
+}
+
+/// Copies memory from or to the address space of the current process
+///
+/// Page faults during the copy are resolved as usual, if that fails the copy is aborted and false is returned.
+///
+/// # Safety
+/// The kernel side of the copy has to be valid for `len` bytes.
+pub unsafe fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> bool {
+    unsafe { user_copy(dst, src, len) == 0 }
+}
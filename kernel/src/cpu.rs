@@ -12,14 +12,18 @@ use alloc::vec::Vec;
 use core::cell::{Cell, UnsafeCell};
 use core::ops::Deref;
 use core::pin::Pin;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use libxernel::ipl::IPL;
 use libxernel::sync::{Once, Spinlock};
+use x86_64::VirtAddr;
 
 static CPU_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 pub static CPU_COUNT: Once<usize> = Once::new();
 
+/// All registered CPUs, used to send IPIs to CPUs with a certain state
+pub static CPUS: Spinlock<Vec<&'static Cpu>> = Spinlock::new(Vec::new());
+
 pub struct PerCpu<T> {
     data: UnsafeCell<Vec<T>>,
 }
@@ -132,6 +136,12 @@ pub struct Cpu {
     pub timer_queue: Spinlock<TimerQueue>,
     pub dpc_queue: Spinlock<DpcQueue>,
     pub next: Spinlock<Option<Arc<Thread>>>,
+    /// Exited threads which were switched away from, they are dropped on the next reschedule
+    pub dead_threads: Spinlock<Vec<Arc<Thread>>>,
+    /// Physical address of the PML4 which is loaded in CR3 of this CPU
+    pub loaded_pml4: AtomicU64,
+    /// Pages which have to be invalidated by the next TLB shootdown IPI
+    pub pending_invalidations: Spinlock<Vec<VirtAddr>>,
 }
 
 impl Cpu {
@@ -164,8 +174,13 @@ pub fn register_cpu() {
         timer_queue: Spinlock::new(TimerQueue::new()),
         dpc_queue: Spinlock::new(DpcQueue::new()),
         next: Spinlock::new(None),
+        dead_threads: Spinlock::new(Vec::new()),
+        loaded_pml4: AtomicU64::new(0),
+        pending_invalidations: Spinlock::new(Vec::new()),
     }));
 
+    CPUS.lock().push(cpu_data);
+
     // use KERNEL_GS_BASE to store the cpu_data
     unsafe { wrmsr(KERNEL_GS_BASE, (cpu_data as *const Cpu).expose_provenance() as u64) }
 }
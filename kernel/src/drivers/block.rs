@@ -0,0 +1,55 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+};
+use libxernel::sync::Spinlock;
+
+use crate::fs::{Error, Result, devfs};
+
+/// A device which is accessed in fixed size sectors, like a disk
+///
+/// File systems don't use the device directly, but read and write its blocks through the buffer cache.
+pub trait BlockDevice: Send + Sync {
+    /// Size of a sector in bytes, which is the smallest unit the device can transfer
+    fn sector_size(&self) -> usize;
+
+    /// Number of sectors of the device
+    fn sector_count(&self) -> u64;
+
+    /// Reads the sectors starting at `sector` into `buf`, whose length is a multiple of the sector size
+    fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Writes `buf` to the sectors starting at `sector`, its length is a multiple of the sector size
+    fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<()>;
+
+    /// Waits until all written sectors reached the device
+    fn flush(&self) -> Result<()>;
+
+    /// Size of the device in bytes
+    fn size(&self) -> u64 {
+        self.sector_count() * self.sector_size() as u64
+    }
+}
+
+/// All block devices by their name, which is also their name in `/dev`
+static BLOCK_DEVICES: Spinlock<BTreeMap<String, Arc<dyn BlockDevice>>> = Spinlock::new(BTreeMap::new());
+
+/// Registers `device` and publishes it as `/dev/<name>`
+pub fn register(name: &str, device: Arc<dyn BlockDevice>) -> Result<()> {
+    let mut devices = BLOCK_DEVICES.lock();
+
+    if devices.contains_key(name) {
+        return Err(Error::EntryExists);
+    }
+
+    devfs::register_block_device(name, device.clone())?;
+    devices.insert(name.to_string(), device);
+
+    Ok(())
+}
+
+/// Returns the block device which was registered as `name`
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES.lock().get(name).cloned()
+}
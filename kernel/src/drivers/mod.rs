@@ -1 +1,4 @@
+pub mod block;
 pub mod ps2;
+pub mod ramdisk;
+pub mod serial;
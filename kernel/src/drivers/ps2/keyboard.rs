@@ -1,11 +1,46 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc};
+use libxernel::sync::SpinlockIRQ;
 
 use crate::{
     arch::amd64::ports::inb,
     dpc::{Dpc, enqueue_dpc},
+    fs::{Error, Result, devfs},
     sched::context::TrapFrame,
 };
 
+/// Scancode of F12, which toggles syscall tracing
+const TOGGLE_TRACING_SCANCODE: u8 = 0x58;
+
+/// Number of scancodes which are kept until they are read, older ones are dropped
+const SCANCODE_BUFFER_SIZE: usize = 256;
+
+static SCANCODES: SpinlockIRQ<VecDeque<u8>> = SpinlockIRQ::new(VecDeque::new());
+
+/// `/dev/keyboard`, reads return the raw scancodes received since the last read without blocking
+struct Keyboard;
+
+impl devfs::CharDevice for Keyboard {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut scancodes = SCANCODES.lock();
+        let count = buf.len().min(scancodes.len());
+
+        for (byte, scancode) in buf.iter_mut().zip(scancodes.drain(..count)) {
+            *byte = scancode;
+        }
+
+        Ok(count)
+    }
+
+    fn write(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(Error::NotPermitted)
+    }
+}
+
+/// Publishes the keyboard in devfs
+pub fn init() {
+    devfs::register_device("keyboard", Arc::new(Keyboard)).expect("Registration of /dev/keyboard failed");
+}
+
 pub fn keyboard_handler(_: &mut TrapFrame) {
     let dpc = Dpc::new(keyboard, ());
 
@@ -17,4 +52,24 @@ pub fn keyboard<T>(_: T) {
     let scancode = unsafe { inb(0x60) };
     dbg!("scancode: {}", scancode);
     debug!("scancode: {}", scancode);
+
+    {
+        let mut scancodes = SCANCODES.lock();
+
+        if scancodes.len() == SCANCODE_BUFFER_SIZE {
+            scancodes.pop_front();
+        }
+
+        scancodes.push_back(scancode);
+    }
+
+    debug_hotkey(scancode);
+}
+
+/// Shortcuts for debugging the kernel, which work in every build, the scancodes are still passed on to
+/// `/dev/keyboard`
+fn debug_hotkey(scancode: u8) {
+    if scancode == TOGGLE_TRACING_SCANCODE {
+        crate::logger::toggle_syscall_tracing();
+    }
 }
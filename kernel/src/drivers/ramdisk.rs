@@ -0,0 +1,120 @@
+use alloc::{sync::Arc, vec};
+use libxernel::sync::Spinlock;
+
+use crate::{
+    fs::{Error, Result},
+    utils::limine_module::{get_limine_module, limine_modules},
+};
+
+use super::block::{self, BlockDevice};
+
+const SECTOR_SIZE: usize = 512;
+
+/// Name of the zeroed RAM disk which is always available as scratch space, unless a module takes its name
+const SCRATCH_DISK: &str = "ram3";
+const SCRATCH_DISK_SIZE: usize = 1024 * 1024;
+
+/// A block device whose sectors are kept in memory
+pub struct RamDisk {
+    data: Spinlock<&'static mut [u8]>,
+}
+
+impl RamDisk {
+    /// Creates a zeroed RAM disk of `size` bytes, which is rounded down to whole sectors
+    ///
+    /// NOTE: block devices are never removed, so the memory is never freed
+    pub fn new(size: usize) -> Self {
+        let data = vec![0; size - size % SECTOR_SIZE].leak();
+
+        Self {
+            data: Spinlock::new(data),
+        }
+    }
+
+    /// Creates a RAM disk on top of the memory of the limine module `name`, a trailing partial sector is ignored
+    ///
+    /// Writes change the module in memory, the file the module was loaded from stays untouched.
+    pub fn from_module(name: &str) -> Option<Self> {
+        let module = get_limine_module(name)?;
+        let len = module.length as usize;
+
+        let data = unsafe { core::slice::from_raw_parts_mut(module.base.as_ptr()?, len - len % SECTOR_SIZE) };
+
+        Some(Self {
+            data: Spinlock::new(data),
+        })
+    }
+
+    /// Returns the bytes of the sectors starting at `sector`, which are `len` bytes long
+    fn range(&self, sector: u64, len: usize) -> Result<core::ops::Range<usize>> {
+        let start = (sector as usize)
+            .checked_mul(SECTOR_SIZE)
+            .ok_or(Error::InvalidArgument)?;
+        let end = start.checked_add(len).ok_or(Error::InvalidArgument)?;
+
+        if !len.is_multiple_of(SECTOR_SIZE) || end > self.data.lock().len() {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(start..end)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<()> {
+        let range = self.range(sector, buf.len())?;
+        buf.copy_from_slice(&self.data.lock()[range]);
+
+        Ok(())
+    }
+
+    fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<()> {
+        let range = self.range(sector, buf.len())?;
+        self.data.lock()[range].copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Publishes every limine module named `ram<N>` as the block device `/dev/ram<N>`, followed by the scratch disk
+///
+/// `xtask` attaches a disk image as `ram0` when it is given `--disk <image>`.
+pub fn init() {
+    for (name, _) in limine_modules() {
+        let Some(index) = name.strip_prefix("ram") else {
+            continue;
+        };
+
+        if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        // UNWRAP: the module was just found
+        register(name, RamDisk::from_module(name).unwrap());
+    }
+
+    if get_limine_module(SCRATCH_DISK).is_none() {
+        register(SCRATCH_DISK, RamDisk::new(SCRATCH_DISK_SIZE));
+    }
+}
+
+/// Publishes `disk` as `/dev/<name>`, a disk which can't be registered is skipped
+fn register(name: &str, disk: RamDisk) {
+    info!("{}: RAM disk with {} sectors", name, disk.sector_count());
+
+    if let Err(err) = block::register(name, Arc::new(disk)) {
+        error!("Registration of /dev/{} failed: {:?}", name, err);
+    }
+}
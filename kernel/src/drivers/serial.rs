@@ -0,0 +1,105 @@
+use alloc::sync::Arc;
+use libxernel::sync::SpinlockIRQ;
+
+use crate::{
+    arch::amd64::ports::{inb, outb},
+    fs::{Result, devfs},
+};
+
+/// I/O port base of the first serial port
+const COM1: u16 = 0x3f8;
+
+/// Offsets of the registers of a 16550 UART from its base
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+/// Line status bits
+const DATA_READY: u8 = 1 << 0;
+const TRANSMITTER_EMPTY: u8 = 1 << 5;
+
+/// `/dev/ttyS0`, a 16550 UART at 115200 baud with 8 data bits, no parity and one stop bit
+///
+/// Reads return the bytes received since the last read without blocking, since the receive interrupt isn't used.
+struct SerialPort {
+    base: u16,
+    /// Keeps the bytes of concurrent writes together
+    lock: SpinlockIRQ<()>,
+}
+
+impl SerialPort {
+    /// Programs the UART and checks that it exists with a loopback test
+    fn probe(base: u16) -> Option<Self> {
+        unsafe {
+            outb(base + INTERRUPT_ENABLE, 0x00);
+            // the divisor latch is accessible while bit 7 of the line control register is set
+            outb(base + LINE_CONTROL, 0x80);
+            outb(base + DATA, 0x01);
+            outb(base + INTERRUPT_ENABLE, 0x00);
+            outb(base + LINE_CONTROL, 0x03);
+            outb(base + FIFO_CONTROL, 0xc7);
+
+            outb(base + MODEM_CONTROL, 0x1e);
+            outb(base + DATA, 0xae);
+
+            if inb(base + DATA) != 0xae {
+                return None;
+            }
+
+            // leave loopback mode, with DTR, RTS and OUT2 set
+            outb(base + MODEM_CONTROL, 0x0f);
+        }
+
+        Some(Self {
+            base,
+            lock: SpinlockIRQ::new(()),
+        })
+    }
+
+    fn line_status(&self) -> u8 {
+        unsafe { inb(self.base + LINE_STATUS) }
+    }
+}
+
+impl devfs::CharDevice for SerialPort {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let _guard = self.lock.lock();
+        let mut count = 0;
+
+        while count < buf.len() && self.line_status() & DATA_READY != 0 {
+            buf[count] = unsafe { inb(self.base + DATA) };
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        let _guard = self.lock.lock();
+
+        for &byte in buf {
+            while self.line_status() & TRANSMITTER_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+
+            unsafe {
+                outb(self.base + DATA, byte);
+            }
+        }
+
+        Ok(buf.len())
+    }
+}
+
+/// Publishes the first serial port in devfs, if the machine has one
+pub fn init() {
+    let Some(port) = SerialPort::probe(COM1) else {
+        info!("no serial port found");
+        return;
+    };
+
+    devfs::register_device("ttyS0", Arc::new(port)).expect("Registration of /dev/ttyS0 failed");
+}
@@ -0,0 +1,206 @@
+//! Blocks of block devices are cached in buffers, disk file systems read and write their metadata through them
+//!
+//! The least recently used buffers which aren't in use are evicted once the cache is full, dirty buffers are written
+//! back before. Buffers are keyed by their device, block size and block number, so a file system has to use the same
+//! block size for all blocks it accesses, otherwise different buffers could cache the same sectors.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use libxernel::sync::Spinlock;
+
+use crate::drivers::block::BlockDevice;
+
+use super::{Error, Result};
+
+/// Maximum number of buffers which are kept in the cache
+const MAX_BUFFERS: usize = 1024;
+
+static BUFFER_CACHE: Spinlock<BufferCache> = Spinlock::new(BufferCache::new());
+
+/// A block of a block device
+pub struct Buffer {
+    device: Arc<dyn BlockDevice>,
+    block: u64,
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+impl Buffer {
+    pub fn block(&self) -> u64 {
+        self.block
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the data for modification, the buffer is written back when it is evicted or synced
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.dirty = true;
+        &mut self.data
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Writes the buffer to the device, if it was changed
+    pub fn write_back(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let sectors_per_block = (self.data.len() / self.device.sector_size()) as u64;
+
+        self.device.write_sectors(self.block * sectors_per_block, &self.data)?;
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+/// (device, block size, block number)
+type BufferKey = (usize, usize, u64);
+
+struct CachedBuffer {
+    buffer: Arc<Spinlock<Buffer>>,
+    /// Value of the clock at the last access, the least recently used buffers are evicted first
+    last_used: u64,
+}
+
+struct BufferCache {
+    buffers: BTreeMap<BufferKey, CachedBuffer>,
+    clock: u64,
+}
+
+impl BufferCache {
+    const fn new() -> Self {
+        Self {
+            buffers: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Evicts the least recently used buffer which is not in use, fails if all buffers are in use
+    fn evict(&mut self) -> Result<()> {
+        // NOTE: only the cache itself references a buffer which is not in use
+        let key = self
+            .buffers
+            .iter()
+            .filter(|(_, cached)| Arc::strong_count(&cached.buffer) == 1)
+            .min_by_key(|(_, cached)| cached.last_used)
+            .map(|(key, _)| *key)
+            .ok_or(Error::NoSpace)?;
+
+        // UNWRAP: the key was just taken from the map
+        let cached = self.buffers.remove(&key).unwrap();
+
+        let written = cached.buffer.lock().write_back();
+
+        if let Err(err) = written {
+            self.buffers.insert(key, cached);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    fn buffers_of<'a>(
+        &'a self,
+        device: &Arc<dyn BlockDevice>,
+    ) -> impl Iterator<Item = (&'a BufferKey, &'a Arc<Spinlock<Buffer>>)> + 'a {
+        let id = device_id(device);
+
+        self.buffers
+            .range((id, 0, 0)..=(id, usize::MAX, u64::MAX))
+            .map(|(key, cached)| (key, &cached.buffer))
+    }
+}
+
+/// Identifies a device in the cache, the buffers keep their device alive, so the address isn't reused
+fn device_id(device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(device) as *const () as usize
+}
+
+/// Returns the buffer of the block `block` of `device`, which is `block_size` bytes long
+///
+/// The block is read from the device if it isn't cached. The block size has to be a multiple of the sector size.
+pub fn bread(device: &Arc<dyn BlockDevice>, block: u64, block_size: usize) -> Result<Arc<Spinlock<Buffer>>> {
+    if block_size == 0 || !block_size.is_multiple_of(device.sector_size()) {
+        return Err(Error::InvalidArgument);
+    }
+
+    let key = (device_id(device), block_size, block);
+    let mut cache = BUFFER_CACHE.lock();
+
+    cache.clock += 1;
+    let clock = cache.clock;
+
+    if let Some(cached) = cache.buffers.get_mut(&key) {
+        cached.last_used = clock;
+        return Ok(cached.buffer.clone());
+    }
+
+    let sectors_per_block = (block_size / device.sector_size()) as u64;
+
+    if (block + 1) * sectors_per_block > device.sector_count() {
+        return Err(Error::InvalidArgument);
+    }
+
+    if cache.buffers.len() >= MAX_BUFFERS {
+        cache.evict()?;
+    }
+
+    let mut data = vec![0; block_size];
+    device.read_sectors(block * sectors_per_block, &mut data)?;
+
+    let buffer = Arc::new(Spinlock::new(Buffer {
+        device: device.clone(),
+        block,
+        data,
+        dirty: false,
+    }));
+
+    cache.buffers.insert(
+        key,
+        CachedBuffer {
+            buffer: buffer.clone(),
+            last_used: clock,
+        },
+    );
+
+    Ok(buffer)
+}
+
+/// Writes the buffer to the device right away
+pub fn bwrite(buffer: &Arc<Spinlock<Buffer>>) -> Result<()> {
+    buffer.lock().write_back()
+}
+
+/// Writes all dirty buffers of `device` back and flushes the device
+pub fn sync(device: &Arc<dyn BlockDevice>) -> Result<()> {
+    let buffers: Vec<_> = BUFFER_CACHE
+        .lock()
+        .buffers_of(device)
+        .map(|(_, buffer)| buffer.clone())
+        .collect();
+
+    for buffer in buffers {
+        buffer.lock().write_back()?;
+    }
+
+    device.flush()
+}
+
+/// Writes all buffers of `device` back and drops them from the cache, used when a file system is unmounted
+pub fn invalidate(device: &Arc<dyn BlockDevice>) -> Result<()> {
+    sync(device)?;
+
+    let mut cache = BUFFER_CACHE.lock();
+    let keys: Vec<_> = cache.buffers_of(device).map(|(key, _)| *key).collect();
+
+    for key in keys {
+        cache.buffers.remove(&key);
+    }
+
+    Ok(())
+}
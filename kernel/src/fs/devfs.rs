@@ -0,0 +1,487 @@
+use alloc::{
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use libxernel::{
+    boot::InitAtBoot,
+    sync::{Once, Spinlock},
+    syscall::{MountFlags, S_IALLUGO, Timespec},
+};
+
+use crate::{drivers::block::BlockDevice, fs::Error, fs::Result, utils::rtc};
+
+use super::{
+    buffer_cache,
+    mount::{Mount, VfsOps},
+    pathbuf::PathBuf,
+    vfs::VFS,
+    vnode::{DirEntry, SetAttr, VAttr, VNode, VNodeOperations, VType},
+};
+
+/// A character device which drivers publish in devfs with [`register_device`]
+pub trait CharDevice: Send + Sync {
+    /// Reads up to `buf.len()` bytes, `offset` is only meaningful for devices which support seeking
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize>;
+
+    /// Writes `buf`, `offset` is only meaningful for devices which support seeking
+    fn write(&self, offset: usize, buf: &[u8]) -> Result<usize>;
+}
+
+/// The only instance of devfs, which is mounted on `/dev`
+static DEVFS: Once<Arc<Spinlock<Devfs>>> = Once::new();
+
+/// Inode numbers are only used to identify nodes in directory listings, they are never reused
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+/// Mounts devfs on `/dev` and publishes the devices which don't belong to any driver
+pub fn init() {
+    let mut devfs = Devfs::new();
+    devfs.vfs_init();
+
+    DEVFS.set_once(Arc::new(Spinlock::new(devfs)));
+
+    let mut vfs = VFS.lock();
+
+    let root = vfs.root_node();
+    vfs.vn_mkdir(&root, "/dev".to_string(), 0o755)
+        .expect("Creation of /dev failed");
+
+    // all mounts share the devices, so devfs can only be mounted once at a time
+    vfs.register_filesystem(String::from("devfs"), |_| Ok(DEVFS.clone()));
+
+    vfs.vn_mount("devfs", &root, "/dev", None, MountFlags::NO_EXEC | MountFlags::NO_SUID)
+        .expect("Mounting devfs on /dev failed");
+
+    drop(vfs);
+
+    register_device("null", Arc::new(Null)).expect("Registration of /dev/null failed");
+    register_device("zero", Arc::new(Zero)).expect("Registration of /dev/zero failed");
+}
+
+/// Publishes `device` as `/dev/<name>`
+pub fn register_device(name: &str, device: Arc<dyn CharDevice>) -> Result<()> {
+    insert_node(name, DevfsNodeKind::Device(device))
+}
+
+/// Publishes `device` as the block device `/dev/<name>`, drivers register their disks with
+/// [`block::register`](crate::drivers::block::register) instead
+pub fn register_block_device(name: &str, device: Arc<dyn BlockDevice>) -> Result<()> {
+    insert_node(name, DevfsNodeKind::BlockDevice(device))
+}
+
+/// Returns the block device `node` refers to, if it is a block device of devfs
+pub fn block_device(node: &VNode) -> Option<Arc<dyn BlockDevice>> {
+    node.downcast_data(|node: &mut DevfsNode| match &node.kind {
+        DevfsNodeKind::BlockDevice(device) => Some(device.clone()),
+        _ => None,
+    })
+    .flatten()
+}
+
+fn insert_node(name: &str, kind: DevfsNodeKind) -> Result<()> {
+    // UNWRAP: devfs always has a root directory
+    let root = DEVFS.lock().vfs_root().unwrap();
+    let root = root.lock();
+    let mount = root.vfsp.clone();
+
+    root.downcast_data(|dir: &mut DevfsNode| dir.insert_node(name, kind, mount))
+        .ok_or(Error::InvalidArgument)?
+}
+
+pub struct Devfs {
+    root_node: InitAtBoot<Arc<Spinlock<VNode>>>,
+}
+
+impl Devfs {
+    pub fn new() -> Self {
+        Self {
+            root_node: InitAtBoot::Uninitialized,
+        }
+    }
+}
+
+impl VfsOps for Devfs {
+    fn vfs_mount(&mut self, path: String) {
+        println!("mounting devfs on {}", path);
+    }
+
+    fn vfs_start(&mut self) {}
+
+    fn vfs_unmount(&mut self) -> Result<()> {
+        let root = self.root_node.lock();
+
+        // the devices stay registered, only the references of devfs itself may be left
+        let busy = root.mounted_here().is_some()
+            || root
+                .downcast_data(|root: &mut DevfsNode| {
+                    root.entries()
+                        .is_ok_and(|entries| entries.iter().any(|entry| Arc::strong_count(&entry.node) > 1))
+                })
+                .unwrap_or(false);
+
+        drop(root);
+
+        if busy || Arc::strong_count(&self.root_node) > 1 {
+            return Err(Error::Busy);
+        }
+
+        Ok(())
+    }
+
+    fn vfs_root(&self) -> Result<Arc<Spinlock<VNode>>> {
+        Ok(self.root_node.clone())
+    }
+
+    fn vfs_init(&mut self) {
+        let root = DevfsNode::new(DevfsNodeKind::Directory(Vec::new()), 0o755);
+
+        self.root_node = InitAtBoot::Initialized(Arc::new(Spinlock::new(VNode::new(
+            Weak::new(),
+            Arc::new(Spinlock::new(root)),
+            VType::Directory,
+            None,
+        ))));
+    }
+
+    fn vfs_name(&self) -> String {
+        "devfs".to_string()
+    }
+
+    fn vfs_lookup(&self, path: &PathBuf) -> Result<Arc<Spinlock<VNode>>> {
+        let mut node = self.root_node.clone();
+
+        for name in path.as_string().split('/').filter(|name| !name.is_empty()) {
+            let next = node.lock().lookup(name)?;
+            node = next;
+        }
+
+        Ok(node)
+    }
+
+    fn vfs_sync(&self) {}
+}
+
+struct DevfsDirEntry {
+    name: String,
+    ino: u64,
+    node: Arc<Spinlock<VNode>>,
+    /// Position of the entry in directory listings, which stays the same while other entries come and go
+    cookie: usize,
+}
+
+enum DevfsNodeKind {
+    Directory(Vec<DevfsDirEntry>),
+    Device(Arc<dyn CharDevice>),
+    BlockDevice(Arc<dyn BlockDevice>),
+}
+
+pub struct DevfsNode {
+    ino: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    atime: Timespec,
+    mtime: Timespec,
+    ctime: Timespec,
+    kind: DevfsNodeKind,
+    /// Cookie of the next entry added to this directory, the entries are kept in the order of their cookies
+    next_cookie: usize,
+}
+
+impl DevfsNode {
+    fn new(kind: DevfsNodeKind, mode: u32) -> Self {
+        let now = rtc::now();
+
+        Self {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            mode,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            kind,
+            next_cookie: 0,
+        }
+    }
+
+    fn entries(&self) -> Result<&Vec<DevfsDirEntry>> {
+        match &self.kind {
+            DevfsNodeKind::Directory(entries) => Ok(entries),
+            _ => Err(Error::NotADirectory),
+        }
+    }
+
+    fn insert_node(&mut self, name: &str, kind: DevfsNodeKind, mount: Weak<Spinlock<Mount>>) -> Result<()> {
+        let DevfsNodeKind::Directory(entries) = &mut self.kind else {
+            return Err(Error::NotADirectory);
+        };
+
+        if entries.iter().any(|entry| entry.name == name) {
+            return Err(Error::EntryExists);
+        }
+
+        // disks are only accessible by root
+        let mode = match kind {
+            DevfsNodeKind::BlockDevice(_) => 0o660,
+            _ => 0o666,
+        };
+
+        let device_node = DevfsNode::new(kind, mode);
+        let ino = device_node.ino;
+        let v_type = device_node.v_type();
+
+        let node = Arc::new(Spinlock::new(VNode::new(
+            mount,
+            Arc::new(Spinlock::new(device_node)),
+            v_type,
+            None,
+        )));
+
+        entries.push(DevfsDirEntry {
+            name: name.to_string(),
+            ino,
+            node,
+            cookie: self.next_cookie,
+        });
+        self.next_cookie += 1;
+
+        self.mtime = rtc::now();
+        self.ctime = self.mtime;
+
+        Ok(())
+    }
+
+    fn v_type(&self) -> VType {
+        match self.kind {
+            DevfsNodeKind::Directory(_) => VType::Directory,
+            DevfsNodeKind::Device(_) => VType::CharacterDevice,
+            DevfsNodeKind::BlockDevice(_) => VType::BlockDevice,
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        match &self.kind {
+            DevfsNodeKind::Directory(_) => Err(Error::IsADirectory),
+            DevfsNodeKind::Device(device) => device.read(offset, buf),
+            DevfsNodeKind::BlockDevice(device) => read_blocks(device, offset, buf),
+        }
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        match &self.kind {
+            DevfsNodeKind::Directory(_) => Err(Error::IsADirectory),
+            DevfsNodeKind::Device(device) => device.write(offset, buf),
+            DevfsNodeKind::BlockDevice(device) => write_blocks(device, offset, buf),
+        }
+    }
+}
+
+/// Reads from a block device through the buffer cache, the read stops at the end of the device
+fn read_blocks(device: &Arc<dyn BlockDevice>, offset: usize, buf: &mut [u8]) -> Result<usize> {
+    let sector_size = device.sector_size();
+    let end = offset.saturating_add(buf.len()).min(device.size() as usize);
+    let mut pos = offset;
+
+    while pos < end {
+        let start = pos % sector_size;
+        let len = (sector_size - start).min(end - pos);
+
+        let buffer = buffer_cache::bread(device, (pos / sector_size) as u64, sector_size)?;
+        buf[pos - offset..pos - offset + len].copy_from_slice(&buffer.lock().data()[start..start + len]);
+
+        pos += len;
+    }
+
+    Ok(pos.saturating_sub(offset))
+}
+
+/// Writes to a block device through the buffer cache, each sector is written to the device right away
+fn write_blocks(device: &Arc<dyn BlockDevice>, offset: usize, buf: &[u8]) -> Result<usize> {
+    let sector_size = device.sector_size();
+    let end = offset.saturating_add(buf.len()).min(device.size() as usize);
+
+    if offset >= end && !buf.is_empty() {
+        return Err(Error::NoSpace);
+    }
+
+    let mut pos = offset;
+
+    while pos < end {
+        let start = pos % sector_size;
+        let len = (sector_size - start).min(end - pos);
+
+        let buffer = buffer_cache::bread(device, (pos / sector_size) as u64, sector_size)?;
+        buffer.lock().data_mut()[start..start + len].copy_from_slice(&buf[pos - offset..pos - offset + len]);
+        buffer_cache::bwrite(&buffer)?;
+
+        pos += len;
+    }
+
+    Ok(pos - offset)
+}
+
+// NOTE: the devices are published by their drivers, so the directory can't be changed through the VFS
+impl VNodeOperations for DevfsNode {
+    fn close(&self) {}
+
+    fn create(
+        &mut self,
+        _path: String,
+        _v_type: VType,
+        _mode: u32,
+        _mount: Weak<Spinlock<Mount>>,
+    ) -> Result<Arc<Spinlock<VNode>>> {
+        Err(Error::NotPermitted)
+    }
+
+    fn getattr(&self) -> Result<VAttr> {
+        let (nlink, size) = match &self.kind {
+            DevfsNodeKind::Directory(_) => (2, 0),
+            DevfsNodeKind::Device(_) => (1, 0),
+            DevfsNodeKind::BlockDevice(device) => (1, device.size() as usize),
+        };
+
+        Ok(VAttr {
+            v_type: self.v_type(),
+            mode: self.mode,
+            uid: self.uid,
+            gid: self.gid,
+            size,
+            nlink,
+            ino: self.ino,
+            atime: self.atime,
+            mtime: self.mtime,
+            ctime: self.ctime,
+        })
+    }
+
+    fn link(&mut self, _name: &str, _node: &Arc<Spinlock<VNode>>) -> Result<()> {
+        Err(Error::NotPermitted)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Spinlock<VNode>>> {
+        self.entries()?
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.node.clone())
+            .ok_or(Error::EntryNotFound)
+    }
+
+    fn open(&self) {}
+
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.read_at(offset, buf)
+    }
+
+    fn readdir(&self, cookie: usize) -> Result<Option<DirEntry>> {
+        let entry = self
+            .entries()?
+            .iter()
+            .find(|entry| entry.cookie >= cookie)
+            .map(|entry| DirEntry {
+                name: entry.name.clone(),
+                ino: entry.ino,
+                v_type: entry.node.lock().v_type(),
+                next_cookie: entry.cookie + 1,
+            });
+
+        Ok(entry)
+    }
+
+    fn readlink(&self) -> Result<String> {
+        Err(Error::InvalidArgument)
+    }
+
+    fn remove(&mut self, _name: &str) -> Result<()> {
+        Err(Error::NotPermitted)
+    }
+
+    fn rename(&mut self, _old_name: &str, _new_dir: Option<&VNode>, _new_name: &str) -> Result<()> {
+        Err(Error::NotPermitted)
+    }
+
+    fn mkdir(&mut self, _name: &str, _mode: u32, _mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>> {
+        Err(Error::NotPermitted)
+    }
+
+    fn rmdir(&mut self, _name: &str) -> Result<()> {
+        Err(Error::NotPermitted)
+    }
+
+    fn setattr(&mut self, attr: &SetAttr) -> Result<()> {
+        if attr.size.is_some() {
+            return Err(Error::InvalidArgument);
+        }
+
+        if let Some(mode) = attr.mode {
+            self.mode = mode & S_IALLUGO;
+        }
+
+        if let Some(uid) = attr.uid {
+            self.uid = uid;
+        }
+
+        if let Some(gid) = attr.gid {
+            self.gid = gid;
+        }
+
+        if let Some(atime) = attr.atime {
+            self.atime = atime;
+        }
+
+        if let Some(mtime) = attr.mtime {
+            self.mtime = mtime;
+        }
+
+        self.ctime = rtc::now();
+
+        Ok(())
+    }
+
+    fn symlink(&mut self, _name: &str, _target: &str, _mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>> {
+        Err(Error::NotPermitted)
+    }
+
+    fn write(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.write_at(offset, buf)
+    }
+
+    fn getpages(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.read_at(offset, buf)
+    }
+
+    fn putpages(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.write_at(offset, buf)
+    }
+}
+
+/// `/dev/null`, reads return end of file and writes are discarded
+struct Null;
+
+impl CharDevice for Null {
+    fn read(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/zero`, reads return zeros and writes are discarded
+struct Zero;
+
+impl CharDevice for Zero {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        buf.fill(0);
+
+        Ok(buf.len())
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,1935 @@
+//! ext2 file system driver
+//!
+//! Files are stored in inodes, which map directly onto vnodes: hard links of a file share its vnode and the
+//! permissions, owners and times of the inode are its attributes. Changes to an inode are written to the buffer cache
+//! right away, so the inode table always holds the current state of the nodes which are in use. Directories indexed by
+//! a hash tree are read linearly and lose their index when they are changed, the file system has to start at the first
+//! sector of the block device.
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use libxernel::{
+    boot::InitAtBoot,
+    sync::Spinlock,
+    syscall::{
+        MountFlags, S_IALLUGO, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK, Timespec,
+    },
+};
+
+use crate::{
+    allocator::align_up,
+    drivers::block::{self, BlockDevice},
+    fs::Error,
+    fs::Result,
+    utils::rtc,
+};
+
+use super::{
+    buffer_cache::{self, Buffer},
+    mount::{Mount, VfsOps},
+    pathbuf::PathBuf,
+    vfs::VFS,
+    vnode::{DirEntry, SetAttr, VAttr, VNode, VNodeOperations, VType},
+};
+
+/// RAM disk which `xtask` attaches the ext2 image as, when it is given `--ext2 <dir>`
+const DATA_DEVICE: &str = "ram2";
+
+/// The superblock is always stored at this byte position, independent of the block size
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const EXT2_MAGIC: u16 = 0xef53;
+
+/// Revision 0 file systems have fixed inode sizes and no feature flags
+const GOOD_OLD_REV: u32 = 0;
+/// Size of the inodes of revision 0, larger inodes only add fields which this driver doesn't use
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GOOD_OLD_FIRST_INO: u32 = 11;
+
+const GROUP_DESC_SIZE: u64 = 32;
+
+/// Directory entries store the file type
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Backups of the superblock are only stored in some groups, which only matters to resizing and file system checks
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+/// Regular files can be larger than 2 GiB
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+/// The driver writes to the file system, so it also refuses unknown read-only compatible features
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE;
+const SUPPORTED_RO_COMPAT: u32 = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE;
+
+const ROOT_INO: u32 = 2;
+
+/// Number of block numbers in an inode, the last three of them refer to indirect blocks
+const N_BLOCKS: usize = 15;
+const DIRECT_BLOCKS: u64 = 12;
+
+/// Set on directories which are indexed by a hash tree
+const INDEX_FL: u32 = 0x1000;
+
+/// Targets of symbolic links which are shorter are stored in place of the block numbers of the inode
+const FAST_SYMLINK_MAX: usize = 60;
+
+const MAX_NAME_LEN: usize = 255;
+const LINK_MAX: u16 = 32000;
+
+/// Extended attribute blocks are shared by inodes with the same attributes and count their references
+const XATTR_MAGIC: u32 = 0xea02_0000;
+
+// file types stored in directory entries
+
+const FT_UNKNOWN: u8 = 0;
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_BLKDEV: u8 = 4;
+const FT_FIFO: u8 = 5;
+const FT_SOCK: u8 = 6;
+const FT_SYMLINK: u8 = 7;
+
+/// Mounts the ext2 data disk on `/mnt`, if it was attached as RAM disk
+pub fn mount_data_disk() {
+    if block::get(DATA_DEVICE).is_none() {
+        return;
+    }
+
+    let mut vfs = VFS.lock();
+    let root = vfs.root_node();
+
+    match vfs.vn_mkdir(&root, "/mnt".to_string(), 0o755) {
+        Ok(_) | Err(Error::EntryExists) => {}
+        Err(err) => panic!("Creation of /mnt failed: {:?}", err),
+    }
+
+    vfs.vn_mount(
+        "ext2",
+        &root,
+        "/mnt",
+        Some(&format!("/dev/{}", DATA_DEVICE)),
+        MountFlags::empty(),
+    )
+    .expect("Mounting the data disk on /mnt failed");
+
+    info!("data disk mounted on /mnt");
+}
+
+pub struct Ext2 {
+    volume: Arc<Volume>,
+    /// Inode of the root directory, until its vnode is created in `vfs_init`
+    root_inode: Option<Inode>,
+    root_node: InitAtBoot<Arc<Spinlock<VNode>>>,
+    mounted_on: Option<String>,
+}
+
+impl Ext2 {
+    /// Reads the superblock on `device`, fails if the device doesn't contain an ext2 file system this driver supports
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        let volume = Volume::new(device)?;
+        let root_inode = volume.read_inode(ROOT_INO)?;
+
+        if root_inode.v_type() != VType::Directory {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(Self {
+            volume: Arc::new(volume),
+            root_inode: Some(root_inode),
+            root_node: InitAtBoot::Uninitialized,
+            mounted_on: None,
+        })
+    }
+}
+
+impl VfsOps for Ext2 {
+    fn vfs_mount(&mut self, path: String) {
+        println!("mounting ext2 on {}", path);
+
+        self.mounted_on = Some(path);
+    }
+
+    fn vfs_start(&mut self) {
+        // the vnodes which are read from the disk later on belong to the mount of the root
+        *self.volume.mount.lock() = self.root_node.lock().vfsp.clone();
+    }
+
+    fn vfs_unmount(&mut self) -> Result<()> {
+        let in_use = self
+            .volume
+            .nodes
+            .lock()
+            .iter()
+            .any(|(ino, node)| *ino != ROOT_INO && node.strong_count() > 0);
+
+        if in_use || Arc::strong_count(&self.root_node) > 1 || self.root_node.lock().mounted_here().is_some() {
+            return Err(Error::Busy);
+        }
+
+        self.volume.write_superblock()?;
+        buffer_cache::invalidate(&self.volume.device)?;
+
+        println!("unmounting ext2 from {}", self.mounted_on.take().unwrap_or_default());
+
+        Ok(())
+    }
+
+    fn vfs_root(&self) -> Result<Arc<Spinlock<VNode>>> {
+        Ok(self.root_node.clone())
+    }
+
+    fn vfs_init(&mut self) {
+        // UNWRAP: the inode is read when the file system is created
+        let inode = self.root_inode.take().unwrap();
+
+        let root = Ext2Node {
+            volume: self.volume.clone(),
+            ino: ROOT_INO,
+            inode,
+        }
+        .into_vnode(Weak::new());
+
+        self.volume.nodes.lock().insert(ROOT_INO, Arc::downgrade(&root));
+
+        self.root_node = InitAtBoot::Initialized(root);
+    }
+
+    fn vfs_name(&self) -> String {
+        "ext2".to_string()
+    }
+
+    fn vfs_lookup(&self, path: &PathBuf) -> Result<Arc<Spinlock<VNode>>> {
+        let mut node = self.root_node.clone();
+
+        for name in path.as_string().split('/').filter(|name| !name.is_empty()) {
+            let next = node.lock().lookup(name)?;
+            node = next;
+        }
+
+        Ok(node)
+    }
+
+    fn vfs_sync(&self) {
+        let synced = self
+            .volume
+            .write_superblock()
+            .and_then(|_| buffer_cache::sync(&self.volume.device));
+
+        if let Err(err) = synced {
+            error!("ext2: sync failed: {:?}", err);
+        }
+    }
+}
+
+/// Counters of the superblock, which are written back when the file system is synced
+struct Summary {
+    free_blocks: u32,
+    free_inodes: u32,
+    ro_compat: u32,
+    /// The counters changed since they were written
+    dirty: bool,
+}
+
+/// Layout of an ext2 file system, which is shared by the file system and all of its nodes
+///
+/// All blocks are accessed through the buffer cache, in the block size of the file system.
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    block_size: usize,
+    blocks_count: u32,
+    inodes_count: u32,
+    /// Block which holds the superblock, the groups start with it
+    first_data_block: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    group_count: u32,
+    inode_size: usize,
+    first_ino: u32,
+    has_file_type: bool,
+    max_file_size: u64,
+    /// Block and inode allocations are serialized by this lock
+    summary: Spinlock<Summary>,
+    /// Nodes which are in use, by their inode number
+    nodes: Spinlock<BTreeMap<u32, Weak<Spinlock<VNode>>>>,
+    /// Mount the vnodes of the file system belong to
+    mount: Spinlock<Weak<Spinlock<Mount>>>,
+}
+
+impl Volume {
+    fn new(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        // the block size isn't known yet, so the superblock is read around the buffer cache
+        let mut start = vec![0; align_up(SUPERBLOCK_OFFSET as usize + SUPERBLOCK_SIZE, device.sector_size())];
+        device.read_sectors(0, &mut start)?;
+
+        let superblock = &start[SUPERBLOCK_OFFSET as usize..][..SUPERBLOCK_SIZE];
+
+        let inodes_count = read_u32(superblock, 0);
+        let blocks_count = read_u32(superblock, 4);
+        let free_blocks = read_u32(superblock, 12);
+        let free_inodes = read_u32(superblock, 16);
+        let first_data_block = read_u32(superblock, 20);
+        let log_block_size = read_u32(superblock, 24);
+        let blocks_per_group = read_u32(superblock, 32);
+        let inodes_per_group = read_u32(superblock, 40);
+        let rev_level = read_u32(superblock, 76);
+
+        if read_u16(superblock, 56) != EXT2_MAGIC || log_block_size > 6 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let block_size: usize = 1024 << log_block_size;
+
+        let (inode_size, first_ino, incompat, ro_compat) = if rev_level == GOOD_OLD_REV {
+            (GOOD_OLD_INODE_SIZE, GOOD_OLD_FIRST_INO, 0, 0)
+        } else {
+            (
+                read_u16(superblock, 88) as usize,
+                read_u32(superblock, 84),
+                read_u32(superblock, 96),
+                read_u32(superblock, 100),
+            )
+        };
+
+        if incompat & !SUPPORTED_INCOMPAT != 0 || ro_compat & !SUPPORTED_RO_COMPAT != 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let bits_per_block = block_size as u32 * 8;
+
+        let is_valid = block_size.is_multiple_of(device.sector_size())
+            && first_data_block < blocks_count
+            && (1..=bits_per_block).contains(&blocks_per_group)
+            && (1..=bits_per_block).contains(&inodes_per_group)
+            && inode_size.is_power_of_two()
+            && (GOOD_OLD_INODE_SIZE..=block_size).contains(&inode_size)
+            && first_ino > ROOT_INO;
+
+        if !is_valid {
+            return Err(Error::InvalidArgument);
+        }
+
+        let group_count = (blocks_count - first_data_block).div_ceil(blocks_per_group);
+
+        let fits_on_device = blocks_count as u64 * block_size as u64 <= device.size();
+        let fits_in_groups = inodes_count as u64 <= group_count as u64 * inodes_per_group as u64;
+
+        if !fits_on_device || !fits_in_groups {
+            return Err(Error::InvalidArgument);
+        }
+
+        // the size is limited by the blocks which can be mapped and by the block count of the inode, which is stored
+        // in 512 byte units
+        let pointers = (block_size / 4) as u64;
+        let mappable = (DIRECT_BLOCKS + pointers + pointers.pow(2) + pointers.pow(3)) * block_size as u64;
+        let countable = u32::MAX as u64 * 512;
+
+        let max_file_size = if rev_level == GOOD_OLD_REV {
+            mappable.min(countable).min(i32::MAX as u64)
+        } else {
+            mappable.min(countable)
+        };
+
+        Ok(Self {
+            device,
+            block_size,
+            blocks_count,
+            inodes_count,
+            first_data_block,
+            blocks_per_group,
+            inodes_per_group,
+            group_count,
+            inode_size,
+            first_ino,
+            has_file_type: incompat & INCOMPAT_FILETYPE != 0,
+            max_file_size,
+            summary: Spinlock::new(Summary {
+                free_blocks,
+                free_inodes,
+                ro_compat,
+                dirty: false,
+            }),
+            nodes: Spinlock::new(BTreeMap::new()),
+            mount: Spinlock::new(Weak::new()),
+        })
+    }
+
+    /// Stores the counters in the superblock, if they changed
+    fn write_superblock(&self) -> Result<()> {
+        let mut summary = self.summary.lock();
+
+        if !summary.dirty {
+            return Ok(());
+        }
+
+        let write_time = seconds(rtc::now());
+
+        self.write_at(SUPERBLOCK_OFFSET + 12, &summary.free_blocks.to_le_bytes())?;
+        self.write_at(SUPERBLOCK_OFFSET + 16, &summary.free_inodes.to_le_bytes())?;
+        self.write_at(SUPERBLOCK_OFFSET + 48, &write_time.to_le_bytes())?;
+        self.write_at(SUPERBLOCK_OFFSET + 100, &summary.ro_compat.to_le_bytes())?;
+
+        summary.dirty = false;
+
+        Ok(())
+    }
+
+    fn bread(&self, block: u32) -> Result<Arc<Spinlock<Buffer>>> {
+        buffer_cache::bread(&self.device, block as u64, self.block_size)
+    }
+
+    /// Reads `buf.len()` bytes at the byte `position` of the file system
+    fn read_at(&self, position: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = position + done as u64;
+            let start = (pos % self.block_size as u64) as usize;
+            let len = (self.block_size - start).min(buf.len() - done);
+
+            let buffer = self.bread((pos / self.block_size as u64) as u32)?;
+            buf[done..done + len].copy_from_slice(&buffer.lock().data()[start..start + len]);
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `buf` at the byte `position` of the file system, the buffers are written back when it is synced
+    fn write_at(&self, position: u64, buf: &[u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = position + done as u64;
+            let start = (pos % self.block_size as u64) as usize;
+            let len = (self.block_size - start).min(buf.len() - done);
+
+            let buffer = self.bread((pos / self.block_size as u64) as u32)?;
+            buffer.lock().data_mut()[start..start + len].copy_from_slice(&buf[done..done + len]);
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Checks a block number which was read from the disk, 0 stands for a missing block
+    fn check_block(&self, block: u32) -> Result<u32> {
+        if block == 0 || (self.first_data_block..self.blocks_count).contains(&block) {
+            Ok(block)
+        } else {
+            Err(Error::InvalidArgument)
+        }
+    }
+
+    fn sectors_per_block(&self) -> u32 {
+        (self.block_size / 512) as u32
+    }
+
+    /// Returns the byte position of the descriptor of `group`, the descriptors follow the superblock
+    fn group_position(&self, group: u32) -> u64 {
+        (self.first_data_block as u64 + 1) * self.block_size as u64 + group as u64 * GROUP_DESC_SIZE
+    }
+
+    /// Returns the block number at `offset` of the descriptor of `group`
+    fn group_block(&self, group: u32, offset: u64) -> Result<u32> {
+        let mut block = [0; 4];
+        self.read_at(self.group_position(group) + offset, &mut block)?;
+
+        match self.check_block(u32::from_le_bytes(block))? {
+            0 => Err(Error::InvalidArgument),
+            block => Ok(block),
+        }
+    }
+
+    fn group_count_at(&self, group: u32, offset: u64) -> Result<u16> {
+        let mut count = [0; 2];
+        self.read_at(self.group_position(group) + offset, &mut count)?;
+
+        Ok(u16::from_le_bytes(count))
+    }
+
+    /// Adds `delta` to the counter at `offset` of the descriptor of `group`
+    fn adjust_group_count(&self, group: u32, offset: u64, delta: i16) -> Result<()> {
+        let count = self.group_count_at(group, offset)?.saturating_add_signed(delta);
+
+        self.write_at(self.group_position(group) + offset, &count.to_le_bytes())
+    }
+
+    fn block_bitmap(&self, group: u32) -> Result<u32> {
+        self.group_block(group, 0)
+    }
+
+    fn inode_bitmap(&self, group: u32) -> Result<u32> {
+        self.group_block(group, 4)
+    }
+
+    fn inode_table(&self, group: u32) -> Result<u32> {
+        self.group_block(group, 8)
+    }
+
+    /// Sets the first clear bit among the first `limit` bits of the bitmap in `block` and returns it
+    fn take_bit(&self, block: u32, limit: usize) -> Result<Option<usize>> {
+        let buffer = self.bread(block)?;
+        let mut buffer = buffer.lock();
+
+        let Some(bit) = (0..limit).find(|bit| buffer.data()[bit / 8] & (1 << (bit % 8)) == 0) else {
+            return Ok(None);
+        };
+
+        buffer.data_mut()[bit / 8] |= 1 << (bit % 8);
+
+        Ok(Some(bit))
+    }
+
+    /// Clears `bit` of the bitmap in `block`, returns whether it was set
+    fn clear_bit(&self, block: u32, bit: usize) -> Result<bool> {
+        let buffer = self.bread(block)?;
+        let mut buffer = buffer.lock();
+
+        if buffer.data()[bit / 8] & (1 << (bit % 8)) == 0 {
+            return Ok(false);
+        }
+
+        buffer.data_mut()[bit / 8] &= !(1 << (bit % 8));
+
+        Ok(true)
+    }
+
+    fn group_of(&self, ino: u32) -> u32 {
+        (ino - 1) / self.inodes_per_group
+    }
+
+    /// Groups in the order they are searched for free blocks or inodes, starting with `goal`
+    fn groups_from(&self, goal: u32) -> impl Iterator<Item = u32> {
+        let goal = goal.min(self.group_count - 1);
+
+        (goal..self.group_count).chain(0..goal)
+    }
+
+    /// Allocates a zeroed block, preferably in the group `goal`
+    fn allocate_block(&self, goal: u32) -> Result<u32> {
+        let mut summary = self.summary.lock();
+
+        for group in self.groups_from(goal) {
+            if self.group_count_at(group, 12)? == 0 {
+                continue;
+            }
+
+            let first = self.first_data_block + group * self.blocks_per_group;
+            let limit = self.blocks_per_group.min(self.blocks_count - first) as usize;
+
+            let Some(bit) = self.take_bit(self.block_bitmap(group)?, limit)? else {
+                continue;
+            };
+
+            self.adjust_group_count(group, 12, -1)?;
+
+            summary.free_blocks = summary.free_blocks.saturating_sub(1);
+            summary.dirty = true;
+
+            drop(summary);
+
+            let block = first + bit as u32;
+            self.bread(block)?.lock().data_mut().fill(0);
+
+            return Ok(block);
+        }
+
+        Err(Error::NoSpace)
+    }
+
+    fn free_block(&self, block: u32) -> Result<()> {
+        let group = (block - self.first_data_block) / self.blocks_per_group;
+        let bit = ((block - self.first_data_block) % self.blocks_per_group) as usize;
+
+        let mut summary = self.summary.lock();
+
+        if self.clear_bit(self.block_bitmap(group)?, bit)? {
+            self.adjust_group_count(group, 12, 1)?;
+
+            summary.free_blocks += 1;
+            summary.dirty = true;
+        }
+
+        Ok(())
+    }
+
+    /// Allocates an inode, preferably in the group `goal`
+    fn allocate_inode(&self, goal: u32, is_directory: bool) -> Result<u32> {
+        let mut summary = self.summary.lock();
+
+        for group in self.groups_from(goal) {
+            if self.group_count_at(group, 14)? == 0 {
+                continue;
+            }
+
+            let Some(bit) = self.take_bit(self.inode_bitmap(group)?, self.inodes_per_group as usize)? else {
+                continue;
+            };
+
+            let ino = group * self.inodes_per_group + bit as u32 + 1;
+
+            // the reserved inodes are marked as used when the file system is created
+            if ino < self.first_ino || ino > self.inodes_count {
+                return Err(Error::InvalidArgument);
+            }
+
+            self.adjust_group_count(group, 14, -1)?;
+
+            if is_directory {
+                self.adjust_group_count(group, 16, 1)?;
+            }
+
+            summary.free_inodes = summary.free_inodes.saturating_sub(1);
+            summary.dirty = true;
+
+            return Ok(ino);
+        }
+
+        Err(Error::NoSpace)
+    }
+
+    fn free_inode(&self, ino: u32, is_directory: bool) -> Result<()> {
+        let group = self.group_of(ino);
+        let bit = ((ino - 1) % self.inodes_per_group) as usize;
+
+        let mut summary = self.summary.lock();
+
+        if self.clear_bit(self.inode_bitmap(group)?, bit)? {
+            self.adjust_group_count(group, 14, 1)?;
+
+            if is_directory {
+                self.adjust_group_count(group, 16, -1)?;
+            }
+
+            summary.free_inodes += 1;
+            summary.dirty = true;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the byte position of the inode `ino` in its inode table
+    fn inode_position(&self, ino: u32) -> Result<u64> {
+        if ino == 0 || ino > self.inodes_count {
+            return Err(Error::InvalidArgument);
+        }
+
+        let index = (ino - 1) % self.inodes_per_group;
+        let table = self.inode_table(self.group_of(ino))?;
+
+        Ok(table as u64 * self.block_size as u64 + index as u64 * self.inode_size as u64)
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<Inode> {
+        let mut raw = [0; GOOD_OLD_INODE_SIZE];
+        self.read_at(self.inode_position(ino)?, &mut raw)?;
+
+        Ok(Inode::parse(&raw))
+    }
+
+    /// Writes `inode` to the inode table, the fields which aren't part of [`Inode`] are kept
+    fn write_inode(&self, ino: u32, inode: &Inode) -> Result<()> {
+        let position = self.inode_position(ino)?;
+
+        let mut raw = [0; GOOD_OLD_INODE_SIZE];
+        self.read_at(position, &mut raw)?;
+        inode.store(&mut raw);
+        self.write_at(position, &raw)?;
+
+        // the feature is set once the first file grows beyond 2 GiB
+        if inode.v_type() == VType::Regular && inode.size > i32::MAX as u64 {
+            let mut summary = self.summary.lock();
+
+            if summary.ro_compat & RO_COMPAT_LARGE_FILE == 0 {
+                summary.ro_compat |= RO_COMPAT_LARGE_FILE;
+                summary.dirty = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Zeroes the inode `ino` including the fields which aren't part of [`Inode`], before it is used for a new file
+    fn clear_inode(&self, ino: u32) -> Result<()> {
+        self.write_at(self.inode_position(ino)?, &vec![0; self.inode_size])
+    }
+
+    /// Frees the data and the inode `ino` of a file without any links left
+    fn delete_inode(&self, ino: u32, inode: &mut Inode) -> Result<()> {
+        if self.has_block_map(inode) {
+            self.truncate_blocks(inode, 0)?;
+        }
+
+        if inode.file_acl != 0 {
+            self.release_attribute_block(inode.file_acl)?;
+            inode.file_acl = 0;
+        }
+
+        inode.size = 0;
+        inode.blocks = 0;
+        inode.links_count = 0;
+        inode.dtime = seconds(rtc::now());
+
+        self.write_inode(ino, inode)?;
+        self.free_inode(ino, inode.v_type() == VType::Directory)
+    }
+
+    /// Drops a reference to the extended attribute block `block`, which is freed once no inode refers to it
+    fn release_attribute_block(&self, block: u32) -> Result<()> {
+        let block = match self.check_block(block)? {
+            0 => return Ok(()),
+            block => block,
+        };
+
+        let buffer = self.bread(block)?;
+        let mut buffer = buffer.lock();
+
+        if read_u32(buffer.data(), 0) != XATTR_MAGIC {
+            return Err(Error::InvalidArgument);
+        }
+
+        let references = read_u32(buffer.data(), 4);
+
+        if references > 1 {
+            write_u32(buffer.data_mut(), 4, references - 1);
+            return Ok(());
+        }
+
+        drop(buffer);
+
+        self.free_block(block)
+    }
+
+    /// Returns whether the block numbers of `inode` refer to data blocks
+    ///
+    /// Device files keep their device number there and fast symbolic links their target.
+    fn has_block_map(&self, inode: &Inode) -> bool {
+        match inode.v_type() {
+            VType::Regular | VType::Directory => true,
+            VType::SymbolicLink => !self.is_fast_symlink(inode),
+            _ => false,
+        }
+    }
+
+    /// A symbolic link is fast if it has no blocks except for an extended attribute block
+    fn is_fast_symlink(&self, inode: &Inode) -> bool {
+        let attribute_sectors = if inode.file_acl != 0 {
+            self.sectors_per_block()
+        } else {
+            0
+        };
+
+        inode.v_type() == VType::SymbolicLink && inode.blocks == attribute_sectors
+    }
+
+    /// Number of block numbers which fit into an indirect block
+    fn pointers_per_block(&self) -> u64 {
+        (self.block_size / 4) as u64
+    }
+
+    /// Returns the index into the block numbers of an inode and the indices into the indirect blocks which lead to the
+    /// block `index` of a file
+    fn block_path(&self, index: u64) -> Result<(usize, Vec<usize>)> {
+        let pointers = self.pointers_per_block();
+
+        if index < DIRECT_BLOCKS {
+            return Ok((index as usize, Vec::new()));
+        }
+
+        let mut index = index - DIRECT_BLOCKS;
+
+        for level in 1..=3 {
+            let span = pointers.pow(level);
+
+            if index < span {
+                let path = (0..level)
+                    .rev()
+                    .map(|depth| ((index / pointers.pow(depth)) % pointers) as usize)
+                    .collect();
+
+                return Ok((DIRECT_BLOCKS as usize + level as usize - 1, path));
+            }
+
+            index -= span;
+        }
+
+        Err(Error::NoSpace)
+    }
+
+    fn read_pointer(&self, block: u32, index: usize) -> Result<u32> {
+        let pointer = read_u32(self.bread(block)?.lock().data(), index * 4);
+
+        self.check_block(pointer)
+    }
+
+    fn write_pointer(&self, block: u32, index: usize, pointer: u32) -> Result<()> {
+        write_u32(self.bread(block)?.lock().data_mut(), index * 4, pointer);
+
+        Ok(())
+    }
+
+    /// Returns the block which holds the block `index` of the file, 0 for a hole
+    fn block_of(&self, inode: &Inode, index: u64) -> Result<u32> {
+        let (slot, path) = self.block_path(index)?;
+        let mut block = self.check_block(inode.block[slot])?;
+
+        for index in path {
+            if block == 0 {
+                break;
+            }
+
+            block = self.read_pointer(block, index)?;
+        }
+
+        Ok(block)
+    }
+
+    /// Returns the block which holds the block `index` of the file, it is allocated together with the indirect blocks
+    /// leading to it if it is missing
+    fn map_block(&self, inode: &mut Inode, ino: u32, index: u64) -> Result<u32> {
+        let goal = self.group_of(ino);
+        let (slot, path) = self.block_path(index)?;
+
+        if self.check_block(inode.block[slot])? == 0 {
+            let blocks = self.add_block_sectors(inode)?;
+            inode.block[slot] = self.allocate_block(goal)?;
+            inode.blocks = blocks;
+        }
+
+        let mut block = inode.block[slot];
+
+        for index in path {
+            let mut next = self.read_pointer(block, index)?;
+
+            if next == 0 {
+                let blocks = self.add_block_sectors(inode)?;
+                next = self.allocate_block(goal)?;
+                inode.blocks = blocks;
+
+                self.write_pointer(block, index, next)?;
+            }
+
+            block = next;
+        }
+
+        Ok(block)
+    }
+
+    /// Returns the sector count of the file with one more block, the count of a corrupted inode may not have room left
+    fn add_block_sectors(&self, inode: &Inode) -> Result<u32> {
+        inode.blocks.checked_add(self.sectors_per_block()).ok_or(Error::NoSpace)
+    }
+
+    /// Frees all blocks of the file from block `count` on, including the indirect blocks which aren't needed anymore
+    fn truncate_blocks(&self, inode: &mut Inode, count: u64) -> Result<()> {
+        let pointers = self.pointers_per_block();
+        let mut start = 0;
+
+        for slot in 0..N_BLOCKS {
+            // the direct blocks are followed by an indirect, a double and a triple indirect block
+            let level = (slot as u32).saturating_sub(DIRECT_BLOCKS as u32 - 1);
+            let span = pointers.pow(level);
+
+            let block = self.check_block(inode.block[slot])?;
+            let keep = count.saturating_sub(start);
+
+            if block != 0 && keep < span {
+                let freed = self.free_tree(block, level, keep)?;
+                inode.blocks = inode.blocks.saturating_sub(freed * self.sectors_per_block());
+
+                if keep == 0 {
+                    inode.block[slot] = 0;
+                }
+            }
+
+            start += span;
+        }
+
+        Ok(())
+    }
+
+    /// Frees the blocks below `block` which hold the file blocks from `keep` on, counted from the first block below
+    /// it, and `block` itself if nothing is kept
+    ///
+    /// `level` is 0 for a data block and 1 to 3 for indirect blocks. Returns the number of freed blocks.
+    fn free_tree(&self, block: u32, level: u32, keep: u64) -> Result<u32> {
+        let mut freed = 0;
+
+        if level > 0 {
+            let span = self.pointers_per_block().pow(level - 1);
+
+            for index in 0..self.pointers_per_block() as usize {
+                let start = index as u64 * span;
+                let child = self.read_pointer(block, index)?;
+
+                if child == 0 || start + span <= keep {
+                    continue;
+                }
+
+                let child_keep = keep.saturating_sub(start);
+                freed += self.free_tree(child, level - 1, child_keep)?;
+
+                if child_keep == 0 && keep != 0 {
+                    self.write_pointer(block, index, 0)?;
+                }
+            }
+        }
+
+        if keep == 0 {
+            self.free_block(block)?;
+            freed += 1;
+        }
+
+        Ok(freed)
+    }
+
+    /// Reads the data of `inode` at `offset`, holes read as zeros
+    fn read_data(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let start = (pos % self.block_size as u64) as usize;
+            let len = (self.block_size - start).min(buf.len() - done);
+
+            match self.block_of(inode, pos / self.block_size as u64)? {
+                0 => buf[done..done + len].fill(0),
+                block => buf[done..done + len].copy_from_slice(&self.bread(block)?.lock().data()[start..start + len]),
+            }
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `buf` at `offset` of the data of the inode `ino`, missing blocks are allocated and the file is extended
+    /// if the data doesn't fit
+    fn write_data(&self, ino: u32, inode: &mut Inode, offset: u64, buf: &[u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let start = (pos % self.block_size as u64) as usize;
+            let len = (self.block_size - start).min(buf.len() - done);
+
+            let block = self.map_block(inode, ino, pos / self.block_size as u64)?;
+            self.bread(block)?.lock().data_mut()[start..start + len].copy_from_slice(&buf[done..done + len]);
+
+            done += len;
+            inode.size = inode.size.max(pos + len as u64);
+        }
+
+        Ok(())
+    }
+
+    fn read_dir(&self, inode: &Inode) -> Result<DirContents> {
+        if inode.v_type() != VType::Directory {
+            return Err(Error::NotADirectory);
+        }
+
+        // directories always consist of whole blocks, which have to be allocated, since they can't have holes
+        if !inode.size.is_multiple_of(self.block_size as u64) || inode.size > inode.blocks as u64 * 512 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut data = vec![0; inode.size as usize];
+        self.read_data(inode, 0, &mut data)?;
+
+        Ok(DirContents {
+            data,
+            block_size: self.block_size,
+            has_file_type: self.has_file_type,
+        })
+    }
+
+    /// Returns the type of the file `entry` refers to, the inode is only read if the entry doesn't store the type
+    fn entry_v_type(&self, entry: &Ext2DirEntry) -> Result<VType> {
+        match entry.file_type {
+            FT_REG_FILE => Ok(VType::Regular),
+            FT_DIR => Ok(VType::Directory),
+            FT_CHRDEV => Ok(VType::CharacterDevice),
+            FT_BLKDEV => Ok(VType::BlockDevice),
+            FT_FIFO => Ok(VType::Fifo),
+            FT_SOCK => Ok(VType::Socket),
+            FT_SYMLINK => Ok(VType::SymbolicLink),
+            _ => Ok(self.read_inode(entry.ino)?.v_type()),
+        }
+    }
+
+    /// Writes a directory entry to the start of `buf`
+    fn encode_entry(&self, buf: &mut [u8], ino: u32, rec_len: usize, name: &[u8], v_type: VType) {
+        write_u32(buf, 0, ino);
+        write_u16(buf, 4, rec_len as u16);
+        buf[6] = name.len() as u8;
+        // without the feature this byte is the upper half of the name length
+        buf[7] = if self.has_file_type { file_type(v_type) } else { 0 };
+        buf[8..8 + name.len()].copy_from_slice(name);
+    }
+
+    /// Points the `..` entry of the directory `ino` to `parent`
+    fn set_parent(&self, ino: u32, parent: u32) -> Result<()> {
+        let mut inode = self.read_inode(ino)?;
+
+        let dot_dot = self
+            .read_dir(&inode)?
+            .records()?
+            .into_iter()
+            .find(|record| record.ino != 0 && record.name == b"..")
+            .ok_or(Error::InvalidArgument)?;
+
+        // the entry already exists, so no blocks are allocated and the inode doesn't change
+        self.write_data(ino, &mut inode, dot_dot.offset as u64, &parent.to_le_bytes())
+    }
+
+    /// Returns the vnode of the inode `ino`, a new one is created if the node isn't in use
+    fn node(self: &Arc<Self>, ino: u32) -> Result<Arc<Spinlock<VNode>>> {
+        let mut nodes = self.nodes.lock();
+
+        if let Some(node) = nodes.get(&ino).and_then(Weak::upgrade) {
+            return Ok(node);
+        }
+
+        let inode = self.read_inode(ino)?;
+
+        // a directory entry which refers to a free inode means the file system is corrupted
+        if inode.links_count == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let node = Ext2Node {
+            volume: self.clone(),
+            ino,
+            inode,
+        }
+        .into_vnode(self.mount.lock().clone());
+
+        nodes.insert(ino, Arc::downgrade(&node));
+
+        Ok(node)
+    }
+}
+
+/// The fields of an inode which are used by this driver
+#[derive(Clone)]
+struct Inode {
+    /// File type and permission bits
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    /// Time at which the inode was freed
+    dtime: u32,
+    links_count: u16,
+    /// Number of 512 byte sectors of the blocks which belong to the file, including indirect blocks
+    blocks: u32,
+    flags: u32,
+    /// Block numbers of the data, or the target of a fast symbolic link
+    block: [u32; N_BLOCKS],
+    /// Block which holds the extended attributes
+    file_acl: u32,
+}
+
+impl Inode {
+    fn new(mode: u32, links_count: u16) -> Self {
+        let now = seconds(rtc::now());
+
+        Self {
+            mode: mode as u16,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            atime: now,
+            ctime: now,
+            mtime: now,
+            dtime: 0,
+            links_count,
+            blocks: 0,
+            flags: 0,
+            block: [0; N_BLOCKS],
+            file_acl: 0,
+        }
+    }
+
+    fn parse(raw: &[u8]) -> Self {
+        let mut inode = Self {
+            mode: read_u16(raw, 0),
+            uid: read_u16(raw, 2) as u32 | (read_u16(raw, 120) as u32) << 16,
+            gid: read_u16(raw, 24) as u32 | (read_u16(raw, 122) as u32) << 16,
+            size: read_u32(raw, 4) as u64,
+            atime: read_u32(raw, 8),
+            ctime: read_u32(raw, 12),
+            mtime: read_u32(raw, 16),
+            dtime: read_u32(raw, 20),
+            links_count: read_u16(raw, 26),
+            blocks: read_u32(raw, 28),
+            flags: read_u32(raw, 32),
+            block: core::array::from_fn(|index| read_u32(raw, 40 + index * 4)),
+            file_acl: read_u32(raw, 104),
+        };
+
+        // only regular files use the upper half of the size, it holds an ACL of directories in revision 0
+        if inode.v_type() == VType::Regular {
+            inode.size |= (read_u32(raw, 108) as u64) << 32;
+        }
+
+        inode
+    }
+
+    fn store(&self, raw: &mut [u8]) {
+        write_u16(raw, 0, self.mode);
+        write_u16(raw, 2, self.uid as u16);
+        write_u32(raw, 4, self.size as u32);
+        write_u32(raw, 8, self.atime);
+        write_u32(raw, 12, self.ctime);
+        write_u32(raw, 16, self.mtime);
+        write_u32(raw, 20, self.dtime);
+        write_u16(raw, 24, self.gid as u16);
+        write_u16(raw, 26, self.links_count);
+        write_u32(raw, 28, self.blocks);
+        write_u32(raw, 32, self.flags);
+
+        for (index, block) in self.block.iter().enumerate() {
+            write_u32(raw, 40 + index * 4, *block);
+        }
+
+        write_u32(raw, 104, self.file_acl);
+
+        if self.v_type() == VType::Regular {
+            write_u32(raw, 108, (self.size >> 32) as u32);
+        }
+
+        write_u16(raw, 120, (self.uid >> 16) as u16);
+        write_u16(raw, 122, (self.gid >> 16) as u16);
+    }
+
+    fn v_type(&self) -> VType {
+        match self.mode as u32 & S_IFMT {
+            S_IFREG => VType::Regular,
+            S_IFDIR => VType::Directory,
+            S_IFLNK => VType::SymbolicLink,
+            S_IFCHR => VType::CharacterDevice,
+            S_IFBLK => VType::BlockDevice,
+            S_IFIFO => VType::Fifo,
+            S_IFSOCK => VType::Socket,
+            _ => VType::Bad,
+        }
+    }
+}
+
+/// A record of a directory, records with the inode 0 are unused
+struct Ext2DirEntry {
+    ino: u32,
+    name: Vec<u8>,
+    file_type: u8,
+    /// Byte offset of the record in the directory
+    offset: usize,
+    /// Length of the record, which includes the unused space up to the next one
+    rec_len: usize,
+    /// Offset and length of the record in front of this one in the same block
+    previous: Option<(usize, usize)>,
+}
+
+impl Ext2DirEntry {
+    fn name(&self) -> String {
+        String::from_utf8_lossy(&self.name).into_owned()
+    }
+
+    /// Number of bytes the record needs, the rest of it can hold other entries
+    fn used_len(&self) -> usize {
+        if self.ino == 0 { 0 } else { entry_len(self.name.len()) }
+    }
+}
+
+/// Contents of a directory
+struct DirContents {
+    data: Vec<u8>,
+    block_size: usize,
+    has_file_type: bool,
+}
+
+impl DirContents {
+    /// Returns all records of the directory, including the unused ones
+    fn records(&self) -> Result<Vec<Ext2DirEntry>> {
+        let mut records = Vec::new();
+
+        for (index, block) in self.data.chunks_exact(self.block_size).enumerate() {
+            let mut offset = 0;
+            let mut previous = None;
+
+            // the records of a block cover it completely
+            while offset < block.len() {
+                let rec_len = read_u16(block, offset + 4) as usize;
+                let name_len = block[offset + 6] as usize;
+                let ino = read_u32(block, offset);
+
+                let is_valid = rec_len >= 8
+                    && rec_len.is_multiple_of(4)
+                    && offset + rec_len <= block.len()
+                    && (ino == 0 || 8 + name_len <= rec_len);
+
+                if !is_valid {
+                    return Err(Error::InvalidArgument);
+                }
+
+                let name = if ino == 0 {
+                    Vec::new()
+                } else {
+                    block[offset + 8..offset + 8 + name_len].to_vec()
+                };
+
+                let position = index * self.block_size + offset;
+
+                records.push(Ext2DirEntry {
+                    ino,
+                    name,
+                    file_type: if self.has_file_type {
+                        block[offset + 7]
+                    } else {
+                        FT_UNKNOWN
+                    },
+                    offset: position,
+                    rec_len,
+                    previous,
+                });
+
+                previous = Some((position, rec_len));
+                offset += rec_len;
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Returns the entries of the directory, without `.` and `..`
+    fn entries(&self) -> Result<Vec<Ext2DirEntry>> {
+        let mut entries = self.records()?;
+        entries.retain(|entry| entry.ino != 0 && entry.name != b"." && entry.name != b"..");
+
+        Ok(entries)
+    }
+
+    fn find(&self, name: &str) -> Result<Option<Ext2DirEntry>> {
+        Ok(self.entries()?.into_iter().find(|entry| entry.name == name.as_bytes()))
+    }
+
+    /// Returns the inode number `..` refers to
+    fn parent(&self) -> Result<u32> {
+        self.records()?
+            .into_iter()
+            .find(|record| record.ino != 0 && record.name == b"..")
+            .map(|record| record.ino)
+            .ok_or(Error::InvalidArgument)
+    }
+}
+
+pub struct Ext2Node {
+    volume: Arc<Volume>,
+    ino: u32,
+    inode: Inode,
+}
+
+impl Ext2Node {
+    /// Wraps the node into a vnode
+    fn into_vnode(self, mount: Weak<Spinlock<Mount>>) -> Arc<Spinlock<VNode>> {
+        let v_type = self.inode.v_type();
+
+        Arc::new(Spinlock::new(VNode::new(
+            mount,
+            Arc::new(Spinlock::new(self)),
+            v_type,
+            None,
+        )))
+    }
+
+    fn write_inode(&self) -> Result<()> {
+        self.volume.write_inode(self.ino, &self.inode)
+    }
+
+    /// Updates the modification and change time after the contents changed
+    fn touch(&mut self) -> Result<()> {
+        self.inode.mtime = seconds(rtc::now());
+        self.inode.ctime = self.inode.mtime;
+
+        self.write_inode()
+    }
+
+    fn read_dir(&self) -> Result<DirContents> {
+        self.volume.read_dir(&self.inode)
+    }
+
+    /// Reads the data of a regular file
+    fn read_data(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let size = self.inode.size as usize;
+        let start = offset.min(size);
+        let end = offset.saturating_add(buf.len()).min(size);
+
+        self.volume
+            .read_data(&self.inode, start as u64, &mut buf[..end - start])?;
+
+        Ok(end - start)
+    }
+
+    /// Truncates or extends the file to `size` bytes, the extension is a hole which reads as zeros
+    fn resize(&mut self, size: u64) -> Result<()> {
+        let block_size = self.volume.block_size as u64;
+
+        if size < self.inode.size {
+            self.volume
+                .truncate_blocks(&mut self.inode, size.div_ceil(block_size))?;
+
+            // the rest of the last block has to read as zeros if the file is extended again
+            let tail = (size % block_size) as usize;
+
+            if tail != 0 {
+                match self.volume.block_of(&self.inode, size / block_size)? {
+                    0 => {}
+                    block => self.volume.bread(block)?.lock().data_mut()[tail..].fill(0),
+                }
+            }
+        }
+
+        self.inode.size = size;
+
+        Ok(())
+    }
+
+    /// Adds the entry `name`, which refers to the inode `ino`, to this directory
+    fn insert_entry(&mut self, name: &str, ino: u32, v_type: VType) -> Result<()> {
+        check_name(name)?;
+
+        // nothing can be created in a removed directory
+        if self.inode.links_count == 0 {
+            return Err(Error::EntryNotFound);
+        }
+
+        let contents = self.read_dir()?;
+
+        if contents.find(name)?.is_some() {
+            return Err(Error::EntryExists);
+        }
+
+        let len = entry_len(name.len());
+        let free = contents
+            .records()?
+            .into_iter()
+            .find(|record| record.rec_len - record.used_len() >= len);
+
+        let (offset, data) = match free {
+            // the entry takes the unused space at the end of a record
+            Some(record) if record.ino != 0 => {
+                let used = record.used_len();
+                let mut data = vec![0; record.rec_len];
+
+                data[..used].copy_from_slice(&contents.data[record.offset..record.offset + used]);
+                write_u16(&mut data, 4, used as u16);
+                self.volume
+                    .encode_entry(&mut data[used..], ino, record.rec_len - used, name.as_bytes(), v_type);
+
+                (record.offset, data)
+            }
+            Some(record) => {
+                let mut data = vec![0; entry_len(name.len())];
+                self.volume
+                    .encode_entry(&mut data, ino, record.rec_len, name.as_bytes(), v_type);
+
+                (record.offset, data)
+            }
+            // the directory is extended by a block which only holds the new entry
+            None => {
+                let mut data = vec![0; self.volume.block_size];
+                self.volume
+                    .encode_entry(&mut data, ino, self.volume.block_size, name.as_bytes(), v_type);
+
+                (contents.data.len(), data)
+            }
+        };
+
+        self.volume
+            .write_data(self.ino, &mut self.inode, offset as u64, &data)?;
+
+        // the hash tree doesn't know about the entry anymore
+        self.inode.flags &= !INDEX_FL;
+
+        self.touch()
+    }
+
+    /// Removes `entry` from this directory, the space is given to the record in front of it
+    fn delete_entry(&mut self, entry: &Ext2DirEntry) -> Result<()> {
+        match entry.previous {
+            Some((offset, rec_len)) => {
+                let rec_len = (rec_len + entry.rec_len) as u16;
+
+                self.volume
+                    .write_data(self.ino, &mut self.inode, offset as u64 + 4, &rec_len.to_le_bytes())?;
+            }
+            None => {
+                self.volume
+                    .write_data(self.ino, &mut self.inode, entry.offset as u64, &0u32.to_le_bytes())?;
+            }
+        }
+
+        self.inode.flags &= !INDEX_FL;
+
+        self.touch()
+    }
+
+    /// Points the existing `entry` of this directory to the inode `ino`
+    fn replace_entry(&mut self, entry: &Ext2DirEntry, ino: u32, v_type: VType) -> Result<()> {
+        let mut header = [0; 8];
+        self.volume.encode_entry(&mut header, ino, entry.rec_len, &[], v_type);
+        header[6] = entry.name.len() as u8;
+
+        self.volume
+            .write_data(self.ino, &mut self.inode, entry.offset as u64, &header)?;
+
+        self.touch()
+    }
+
+    /// Allocates an inode for `inode` and adds it as `name` to this directory
+    ///
+    /// The new file gets the data `data` returns for its inode number.
+    fn insert_node(
+        &mut self,
+        name: &str,
+        mut inode: Inode,
+        data: impl FnOnce(u32) -> Vec<u8>,
+    ) -> Result<Arc<Spinlock<VNode>>> {
+        let v_type = inode.v_type();
+        let ino = self
+            .volume
+            .allocate_inode(self.volume.group_of(self.ino), v_type == VType::Directory)?;
+
+        let create = || -> Result<()> {
+            self.volume.clear_inode(ino)?;
+            self.volume.write_data(ino, &mut inode, 0, &data(ino))?;
+            self.volume.write_inode(ino, &inode)?;
+
+            self.insert_entry(name, ino, v_type)
+        };
+
+        if let Err(err) = create() {
+            if let Err(err) = self.volume.delete_inode(ino, &mut inode) {
+                error!("ext2: freeing the inode of a failed creation failed: {:?}", err);
+            }
+
+            return Err(err);
+        }
+
+        // the `..` entry of a new directory links to this one
+        if v_type == VType::Directory {
+            self.inode.links_count += 1;
+            self.write_inode()?;
+        }
+
+        self.volume.node(ino)
+    }
+
+    /// Drops the link of the inode `ino`, whose entry was removed from this directory
+    ///
+    /// The inode is freed once it has no links left and isn't in use anymore.
+    fn unlink_node(&self, ino: u32) -> Result<()> {
+        let node = self.volume.node(ino)?;
+
+        node.lock()
+            .downcast_data(|node: &mut Ext2Node| {
+                // the entry in the parent and its own `.` are the only links of an empty directory
+                if node.inode.v_type() == VType::Directory {
+                    node.inode.links_count = 0;
+                } else {
+                    node.inode.links_count = node.inode.links_count.saturating_sub(1);
+                }
+
+                node.inode.ctime = seconds(rtc::now());
+                node.write_inode()
+            })
+            .ok_or(Error::InvalidArgument)?
+    }
+
+    /// Moves the entry `old_name` of this directory to `new_name` in `new_dir`, or in this directory if `new_dir` is
+    /// `None`
+    fn move_entry(&mut self, mut new_dir: Option<&mut Ext2Node>, old_name: &str, new_name: &str) -> Result<()> {
+        if new_dir
+            .as_ref()
+            .is_some_and(|new_dir| !Arc::ptr_eq(&self.volume, &new_dir.volume))
+        {
+            return Err(Error::CrossDevice);
+        }
+
+        let source = self.read_dir()?.find(old_name)?.ok_or(Error::EntryNotFound)?;
+        let source_type = self.volume.entry_v_type(&source)?;
+        let is_directory = source_type == VType::Directory;
+
+        {
+            let target_dir: &mut Ext2Node = match new_dir.as_deref_mut() {
+                Some(new_dir) => new_dir,
+                None => self,
+            };
+
+            match target_dir.read_dir()?.find(new_name)? {
+                // renaming a file to one of its own links does nothing
+                Some(target) if target.ino == source.ino => return Ok(()),
+                Some(target) => {
+                    let target_inode = target_dir.volume.read_inode(target.ino)?;
+                    let target_is_directory = target_inode.v_type() == VType::Directory;
+
+                    match (is_directory, target_is_directory) {
+                        (false, true) => return Err(Error::IsADirectory),
+                        (true, false) => return Err(Error::NotADirectory),
+                        (true, true) if !target_dir.volume.read_dir(&target_inode)?.entries()?.is_empty() => {
+                            return Err(Error::NotEmpty);
+                        }
+                        _ => {}
+                    }
+
+                    target_dir.replace_entry(&target, source.ino, source_type)?;
+
+                    // the `..` entry of the replaced directory is gone
+                    if target_is_directory {
+                        target_dir.inode.links_count -= 1;
+                        target_dir.write_inode()?;
+                    }
+
+                    target_dir.unlink_node(target.ino)?;
+                }
+                None => target_dir.insert_entry(new_name, source.ino, source_type)?,
+            }
+        }
+
+        // the records of this directory may have changed when the new entry was added to it
+        let source = self.read_dir()?.find(old_name)?.ok_or(Error::EntryNotFound)?;
+        self.delete_entry(&source)?;
+
+        // a moved directory has to refer to its new parent, which takes over the link of its `..` entry
+        if let Some(new_dir) = new_dir
+            && is_directory
+        {
+            self.volume.set_parent(source.ino, new_dir.ino)?;
+
+            self.inode.links_count -= 1;
+            self.write_inode()?;
+
+            new_dir.inode.links_count += 1;
+            new_dir.write_inode()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl VNodeOperations for Ext2Node {
+    fn close(&self) {}
+
+    fn create(
+        &mut self,
+        name: String,
+        v_type: VType,
+        mode: u32,
+        mount: Weak<Spinlock<Mount>>,
+    ) -> Result<Arc<Spinlock<VNode>>> {
+        let type_mode = match v_type {
+            VType::Directory => return self.mkdir(&name, mode, mount),
+            VType::Regular => S_IFREG,
+            VType::CharacterDevice => S_IFCHR,
+            VType::BlockDevice => S_IFBLK,
+            VType::Fifo => S_IFIFO,
+            VType::Socket => S_IFSOCK,
+            // symbolic links are created with their target
+            _ => return Err(Error::InvalidArgument),
+        };
+
+        self.insert_node(&name, Inode::new(type_mode | (mode & S_IALLUGO), 1), |_| Vec::new())
+    }
+
+    fn inactive(&self) {
+        {
+            let mut nodes = self.volume.nodes.lock();
+
+            // the inode may already be in use by a new vnode
+            if nodes.get(&self.ino).is_some_and(|node| node.strong_count() == 0) {
+                nodes.remove(&self.ino);
+            }
+        }
+
+        if self.inode.links_count != 0 {
+            return;
+        }
+
+        if let Err(err) = self.volume.delete_inode(self.ino, &mut self.inode.clone()) {
+            error!("ext2: freeing the inode of a removed file failed: {:?}", err);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Spinlock<VNode>>> {
+        // a removed directory is empty, even though its blocks are only freed once it isn't in use anymore
+        if self.inode.links_count == 0 {
+            return Err(Error::EntryNotFound);
+        }
+
+        let contents = self.read_dir()?;
+
+        let ino = if name == ".." {
+            contents.parent()?
+        } else {
+            contents.find(name)?.ok_or(Error::EntryNotFound)?.ino
+        };
+
+        self.volume.node(ino)
+    }
+
+    fn open(&self) {}
+
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        // NOTE: the access time is not updated on reads, like a file system mounted with noatime
+        match self.inode.v_type() {
+            VType::Regular => self.read_data(offset, buf),
+            VType::Directory => Err(Error::IsADirectory),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+
+    fn write(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
+        match self.inode.v_type() {
+            VType::Regular => {}
+            VType::Directory => return Err(Error::IsADirectory),
+            _ => return Err(Error::InvalidArgument),
+        }
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if offset
+            .checked_add(buf.len())
+            .is_none_or(|end| end as u64 > self.volume.max_file_size)
+        {
+            return Err(Error::FileTooLarge);
+        }
+
+        let written = self.volume.write_data(self.ino, &mut self.inode, offset as u64, buf);
+
+        // blocks which were allocated before a failure belong to the file, so the inode is written in any case
+        self.touch()?;
+        written?;
+
+        Ok(buf.len())
+    }
+
+    fn getattr(&self) -> Result<VAttr> {
+        Ok(VAttr {
+            v_type: self.inode.v_type(),
+            mode: self.inode.mode as u32 & S_IALLUGO,
+            uid: self.inode.uid,
+            gid: self.inode.gid,
+            size: self.inode.size as usize,
+            nlink: self.inode.links_count as usize,
+            ino: self.ino as u64,
+            atime: timespec(self.inode.atime),
+            mtime: timespec(self.inode.mtime),
+            ctime: timespec(self.inode.ctime),
+        })
+    }
+
+    fn setattr(&mut self, attr: &SetAttr) -> Result<()> {
+        if let Some(mode) = attr.mode {
+            self.inode.mode = ((self.inode.mode as u32 & S_IFMT) | (mode & S_IALLUGO)) as u16;
+        }
+
+        if let Some(uid) = attr.uid {
+            self.inode.uid = uid;
+        }
+
+        if let Some(gid) = attr.gid {
+            self.inode.gid = gid;
+        }
+
+        if let Some(size) = attr.size {
+            match self.inode.v_type() {
+                VType::Regular => {}
+                VType::Directory => return Err(Error::IsADirectory),
+                _ => return Err(Error::InvalidArgument),
+            }
+
+            if size as u64 > self.volume.max_file_size {
+                return Err(Error::FileTooLarge);
+            }
+
+            if size as u64 != self.inode.size {
+                let resized = self.resize(size as u64);
+
+                self.inode.mtime = seconds(rtc::now());
+                self.write_inode()?;
+                resized?;
+            }
+        }
+
+        if let Some(atime) = attr.atime {
+            self.inode.atime = seconds(atime);
+        }
+
+        if let Some(mtime) = attr.mtime {
+            self.inode.mtime = seconds(mtime);
+        }
+
+        self.inode.ctime = seconds(rtc::now());
+
+        self.write_inode()
+    }
+
+    fn getpages(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if self.inode.v_type() != VType::Regular {
+            return Err(Error::IsADirectory);
+        }
+
+        self.read_data(offset, buf)
+    }
+
+    fn putpages(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
+        if self.inode.v_type() != VType::Regular {
+            return Err(Error::IsADirectory);
+        }
+
+        let size = self.inode.size as usize;
+        let start = offset.min(size);
+        let end = offset.saturating_add(buf.len()).min(size);
+
+        if start == end {
+            return Ok(0);
+        }
+
+        // holes in the written range get blocks now
+        let written = self
+            .volume
+            .write_data(self.ino, &mut self.inode, start as u64, &buf[..end - start]);
+
+        self.touch()?;
+        written?;
+
+        Ok(end - start)
+    }
+
+    fn readdir(&self, cookie: usize) -> Result<Option<DirEntry>> {
+        // the cookie is the offset at which the search for the next entry starts
+        let Some(entry) = self
+            .read_dir()?
+            .entries()?
+            .into_iter()
+            .find(|entry| entry.offset >= cookie)
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(DirEntry {
+            name: entry.name(),
+            ino: entry.ino as u64,
+            v_type: self.volume.entry_v_type(&entry)?,
+            next_cookie: entry.offset + entry.rec_len,
+        }))
+    }
+
+    fn readlink(&self) -> Result<String> {
+        if self.inode.v_type() != VType::SymbolicLink {
+            return Err(Error::InvalidArgument);
+        }
+
+        let size = self.inode.size as usize;
+
+        let target = if self.volume.is_fast_symlink(&self.inode) {
+            self.inode
+                .block
+                .iter()
+                .flat_map(|block| block.to_le_bytes())
+                .take(size)
+                .collect()
+        } else {
+            let mut target = vec![0; size];
+            self.volume.read_data(&self.inode, 0, &mut target)?;
+
+            target
+        };
+
+        Ok(String::from_utf8_lossy(&target).into_owned())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<()> {
+        let entry = self.read_dir()?.find(name)?.ok_or(Error::EntryNotFound)?;
+
+        if self.volume.entry_v_type(&entry)? == VType::Directory {
+            return Err(Error::IsADirectory);
+        }
+
+        self.delete_entry(&entry)?;
+        self.unlink_node(entry.ino)
+    }
+
+    fn link(&mut self, name: &str, node: &Arc<Spinlock<VNode>>) -> Result<()> {
+        let node = node.lock();
+
+        if node.v_type() == VType::Directory {
+            return Err(Error::NotPermitted);
+        }
+
+        node.downcast_data(|node: &mut Ext2Node| {
+            if !Arc::ptr_eq(&self.volume, &node.volume) {
+                return Err(Error::CrossDevice);
+            }
+
+            // the file may have been removed since it was looked up
+            if node.inode.links_count == 0 {
+                return Err(Error::EntryNotFound);
+            }
+
+            if node.inode.links_count >= LINK_MAX {
+                return Err(Error::NotPermitted);
+            }
+
+            self.insert_entry(name, node.ino, node.inode.v_type())?;
+
+            node.inode.links_count += 1;
+            node.inode.ctime = seconds(rtc::now());
+
+            node.write_inode()
+        })
+        .ok_or(Error::CrossDevice)?
+    }
+
+    fn rename(&mut self, old_name: &str, new_dir: Option<&VNode>, new_name: &str) -> Result<()> {
+        let Some(new_dir) = new_dir else {
+            return self.move_entry(None, old_name, new_name);
+        };
+
+        new_dir
+            .downcast_data(|new_dir: &mut Ext2Node| self.move_entry(Some(new_dir), old_name, new_name))
+            .ok_or(Error::CrossDevice)?
+    }
+
+    fn mkdir(&mut self, name: &str, mode: u32, _mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>> {
+        let volume = self.volume.clone();
+        let parent = self.ino;
+
+        // the directory links to itself through `.`
+        self.insert_node(name, Inode::new(S_IFDIR | (mode & S_IALLUGO), 2), |ino| {
+            let mut data = vec![0; volume.block_size];
+            let dot_len = entry_len(1);
+
+            volume.encode_entry(&mut data, ino, dot_len, b".", VType::Directory);
+            volume.encode_entry(
+                &mut data[dot_len..],
+                parent,
+                volume.block_size - dot_len,
+                b"..",
+                VType::Directory,
+            );
+
+            data
+        })
+    }
+
+    fn rmdir(&mut self, name: &str) -> Result<()> {
+        let entry = self.read_dir()?.find(name)?.ok_or(Error::EntryNotFound)?;
+        let inode = self.volume.read_inode(entry.ino)?;
+
+        if inode.v_type() != VType::Directory {
+            return Err(Error::NotADirectory);
+        }
+
+        if !self.volume.read_dir(&inode)?.entries()?.is_empty() {
+            return Err(Error::NotEmpty);
+        }
+
+        self.delete_entry(&entry)?;
+
+        self.inode.links_count -= 1;
+        self.write_inode()?;
+
+        self.unlink_node(entry.ino)
+    }
+
+    fn symlink(&mut self, name: &str, target: &str, _mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>> {
+        if target.is_empty() || target.len() >= self.volume.block_size {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut inode = Inode::new(S_IFLNK | 0o777, 1);
+
+        if target.len() >= FAST_SYMLINK_MAX {
+            let data = target.as_bytes().to_vec();
+
+            return self.insert_node(name, inode, |_| data);
+        }
+
+        for (block, chunk) in inode.block.iter_mut().zip(target.as_bytes().chunks(4)) {
+            let mut bytes = [0; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+
+            *block = u32::from_le_bytes(bytes);
+        }
+
+        inode.size = target.len() as u64;
+
+        self.insert_node(name, inode, |_| Vec::new())
+    }
+}
+
+/// Returns the number of bytes a directory entry with a name of `name_len` bytes needs
+fn entry_len(name_len: usize) -> usize {
+    (8 + name_len).next_multiple_of(4)
+}
+
+fn file_type(v_type: VType) -> u8 {
+    match v_type {
+        VType::Regular => FT_REG_FILE,
+        VType::Directory => FT_DIR,
+        VType::CharacterDevice => FT_CHRDEV,
+        VType::BlockDevice => FT_BLKDEV,
+        VType::Fifo => FT_FIFO,
+        VType::Socket => FT_SOCK,
+        VType::SymbolicLink => FT_SYMLINK,
+        _ => FT_UNKNOWN,
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let is_valid = !name.is_empty() && name.len() <= MAX_NAME_LEN && !name.contains(['/', '\0']);
+
+    if is_valid { Ok(()) } else { Err(Error::InvalidArgument) }
+}
+
+/// ext2 stores times as unsigned seconds since the Unix epoch
+fn timespec(seconds: u32) -> Timespec {
+    Timespec {
+        tv_sec: seconds as i64,
+        tv_nsec: 0,
+    }
+}
+
+fn seconds(time: Timespec) -> u32 {
+    time.tv_sec.clamp(0, u32::MAX as i64) as u32
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    // UNWRAP: the slice is 4 bytes long
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,1673 @@
+//! FAT32 file system driver
+//!
+//! Names are stored as VFAT long names, every entry gets a generated 8.3 short name as well. FAT has no inodes, so a
+//! node is identified by the position of its short directory entry on the disk. The volume has to start at the first
+//! sector of the block device, partition tables are not supported.
+//!
+//! The inode numbers are derived from the entry positions as well, so they are only unique among the entries which
+//! exist right now. A file gets a new number when it is renamed, and the number of a file which was renamed or removed
+//! while it was open may meanwhile belong to another file.
+//!
+//! What's mounted on `/boot` is a copy of the boot volume, which `xtask` attaches as the RAM disk `ram1`. Changes
+//! only live in memory and never reach the image the kernel was booted from.
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use libxernel::{
+    boot::InitAtBoot,
+    sync::Spinlock,
+    syscall::{MountFlags, Timespec},
+};
+
+use crate::{
+    allocator::align_up,
+    drivers::block::{self, BlockDevice},
+    fs::Error,
+    fs::Result,
+    utils::rtc,
+};
+
+use super::{
+    buffer_cache::{self, Buffer},
+    mount::{Mount, VfsOps},
+    pathbuf::PathBuf,
+    vfs::VFS,
+    vnode::{DirEntry, SetAttr, VAttr, VNode, VNodeOperations, VType},
+};
+
+/// RAM disk which `xtask` attaches the copy of the boot volume as, when it is given `--boot-disk`
+const BOOT_DEVICE: &str = "ram1";
+
+const DIR_ENTRY_SIZE: usize = 32;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+/// Long name entries are marked as read-only, hidden, system and volume id at once
+const ATTR_LONG_NAME: u8 = 0x0f;
+const ATTR_LONG_NAME_MASK: u8 = 0x3f;
+
+/// Marks the long name entry which holds the end of the name, it comes first on the disk
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// Number of UCS-2 characters stored in a long name entry
+const LONG_NAME_CHARS: usize = 13;
+/// Offsets of the characters in a long name entry
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// Maximum length of a long name in UCS-2 characters
+const MAX_NAME_LEN: usize = 255;
+/// Characters which can't be part of a long name
+const INVALID_NAME_CHARS: &str = "\"*/:<>?\\|";
+
+/// First byte of a free entry
+const ENTRY_FREE: u8 = 0xe5;
+/// First byte of the entry which ends the directory, all following entries are free as well
+const ENTRY_END: u8 = 0x00;
+
+/// Set in a short entry if the base name is displayed in lower case
+const CASE_LOWER_BASE: u8 = 0x08;
+/// Set in a short entry if the extension is displayed in lower case
+const CASE_LOWER_EXT: u8 = 0x10;
+
+const DOT_NAME: [u8; 11] = *b".          ";
+const DOT_DOT_NAME: [u8; 11] = *b"..         ";
+
+/// Only the lower 28 bits of a FAT entry are used, this value also marks the end of a cluster chain
+const CLUSTER_MASK: u32 = 0x0fff_ffff;
+/// Entries from this value on mark the end of a cluster chain
+const END_OF_CHAIN: u32 = 0x0fff_fff8;
+/// Volumes with fewer clusters are FAT12 or FAT16
+const MIN_CLUSTERS: u32 = 65525;
+/// Cluster numbers from 0x0ffffff7 on are reserved
+const MAX_CLUSTERS: u32 = 0x0fff_fff5;
+
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+/// Value of the free cluster count in the FSInfo sector if the count is unknown
+const FS_INFO_UNKNOWN: u32 = 0xffff_ffff;
+
+/// The root directory has no directory entry, so it gets a fixed inode number
+const ROOT_INO: u64 = 1;
+
+/// Mounts the copy of the boot volume on `/boot`, if it was attached as RAM disk
+pub fn mount_boot_volume() {
+    if block::get(BOOT_DEVICE).is_none() {
+        return;
+    }
+
+    let mut vfs = VFS.lock();
+    let root = vfs.root_node();
+
+    match vfs.vn_mkdir(&root, "/boot".to_string(), 0o755) {
+        Ok(_) | Err(Error::EntryExists) => {}
+        Err(err) => panic!("Creation of /boot failed: {:?}", err),
+    }
+
+    vfs.vn_mount(
+        "fat32",
+        &root,
+        "/boot",
+        Some(&format!("/dev/{}", BOOT_DEVICE)),
+        MountFlags::empty(),
+    )
+    .expect("Mounting the boot volume on /boot failed");
+
+    info!("copy of the boot volume mounted on /boot");
+}
+
+pub struct Fat32 {
+    volume: Arc<Volume>,
+    root_node: InitAtBoot<Arc<Spinlock<VNode>>>,
+    mounted_on: Option<String>,
+}
+
+impl Fat32 {
+    /// Reads the layout of the volume on `device`, fails if the device doesn't contain a FAT32 volume
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        Ok(Self {
+            volume: Arc::new(Volume::new(device)?),
+            root_node: InitAtBoot::Uninitialized,
+            mounted_on: None,
+        })
+    }
+}
+
+impl VfsOps for Fat32 {
+    fn vfs_mount(&mut self, path: String) {
+        println!("mounting fat32 on {}", path);
+
+        self.mounted_on = Some(path);
+    }
+
+    fn vfs_start(&mut self) {
+        // the vnodes which are read from the disk later on belong to the mount of the root
+        *self.volume.mount.lock() = self.root_node.lock().vfsp.clone();
+    }
+
+    fn vfs_unmount(&mut self) -> Result<()> {
+        // each node holds a reference to its parent, so the root is only referenced by the file system while no node
+        // is in use
+        if Arc::strong_count(&self.root_node) > 1 || self.root_node.lock().mounted_here().is_some() {
+            return Err(Error::Busy);
+        }
+
+        self.volume.write_fs_info()?;
+        buffer_cache::invalidate(&self.volume.device)?;
+
+        println!("unmounting fat32 from {}", self.mounted_on.take().unwrap_or_default());
+
+        Ok(())
+    }
+
+    fn vfs_root(&self) -> Result<Arc<Spinlock<VNode>>> {
+        Ok(self.root_node.clone())
+    }
+
+    fn vfs_init(&mut self) {
+        let root = Fat32Node::root(self.volume.clone()).into_vnode(Weak::new());
+
+        self.root_node = InitAtBoot::Initialized(root);
+    }
+
+    fn vfs_name(&self) -> String {
+        "fat32".to_string()
+    }
+
+    fn vfs_lookup(&self, path: &PathBuf) -> Result<Arc<Spinlock<VNode>>> {
+        let mut node = self.root_node.clone();
+
+        for name in path.as_string().split('/').filter(|name| !name.is_empty()) {
+            let next = node.lock().lookup(name)?;
+            node = next;
+        }
+
+        Ok(node)
+    }
+
+    fn vfs_sync(&self) {
+        let synced = self
+            .volume
+            .write_fs_info()
+            .and_then(|_| buffer_cache::sync(&self.volume.device));
+
+        if let Err(err) = synced {
+            error!("fat32: sync failed: {:?}", err);
+        }
+    }
+}
+
+/// Hints for the allocation of clusters, which are kept in the FSInfo sector
+struct Allocation {
+    /// Number of free clusters, if it is known
+    free_count: Option<u32>,
+    /// Cluster at which the search for a free cluster starts
+    next_free: u32,
+}
+
+/// Layout of a FAT32 volume, which is shared by the file system and all of its nodes
+///
+/// All sectors are accessed through the buffer cache, in blocks of the sector size of the volume.
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    bytes_per_sector: usize,
+    sectors_per_cluster: usize,
+    /// First sector of the first FAT
+    fat_start: u64,
+    /// Number of sectors of each FAT
+    fat_size: u64,
+    fat_count: u64,
+    /// First sector of cluster 2, the first data cluster
+    data_start: u64,
+    /// Number of data clusters, valid clusters range from 2 to `cluster_count + 1`
+    cluster_count: u32,
+    root_cluster: u32,
+    fs_info_sector: Option<u64>,
+    allocation: Spinlock<Allocation>,
+    /// Nodes which are in use, by the position of their directory entry
+    nodes: Spinlock<BTreeMap<u64, Weak<Spinlock<VNode>>>>,
+    /// Mount the vnodes of the file system belong to
+    mount: Spinlock<Weak<Spinlock<Mount>>>,
+}
+
+impl Volume {
+    fn new(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        // the layout of the volume isn't known yet, so the boot sector is read around the buffer cache
+        let mut boot_sector = vec![0; align_up(512, device.sector_size())];
+        device.read_sectors(0, &mut boot_sector)?;
+
+        let bytes_per_sector = read_u16(&boot_sector, 11) as usize;
+        let sectors_per_cluster = boot_sector[13] as usize;
+        let reserved_sectors = read_u16(&boot_sector, 14) as u64;
+        let fat_count = boot_sector[16] as u64;
+        let root_entries = read_u16(&boot_sector, 17);
+        let fat_size_16 = read_u16(&boot_sector, 22);
+        let fat_size = read_u32(&boot_sector, 36) as u64;
+        let root_cluster = read_u32(&boot_sector, 44);
+        let fs_info_sector = read_u16(&boot_sector, 48) as u64;
+
+        let total_sectors = match read_u16(&boot_sector, 19) {
+            0 => read_u32(&boot_sector, 32) as u64,
+            sectors => sectors as u64,
+        };
+
+        // FAT32 has no fixed root directory and stores the size of the FAT in 32 bits
+        let is_fat32 = read_u16(&boot_sector, 510) == 0xaa55 && root_entries == 0 && fat_size_16 == 0 && fat_size != 0;
+
+        let is_valid = (512..=4096).contains(&bytes_per_sector)
+            && bytes_per_sector.is_power_of_two()
+            && bytes_per_sector.is_multiple_of(device.sector_size())
+            && sectors_per_cluster.is_power_of_two()
+            && fat_count != 0;
+
+        if !is_fat32 || !is_valid {
+            return Err(Error::InvalidArgument);
+        }
+
+        let data_start = reserved_sectors + fat_count * fat_size;
+        let cluster_count =
+            total_sectors.checked_sub(data_start).ok_or(Error::InvalidArgument)? / sectors_per_cluster as u64;
+
+        let fits_on_device = total_sectors * (bytes_per_sector / device.sector_size()) as u64 <= device.sector_count();
+        let fits_in_fat = fat_size * bytes_per_sector as u64 / 4 >= cluster_count + 2;
+
+        if cluster_count < MIN_CLUSTERS as u64 || cluster_count > MAX_CLUSTERS as u64 || !fits_on_device || !fits_in_fat
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut volume = Self {
+            device,
+            bytes_per_sector,
+            sectors_per_cluster,
+            fat_start: reserved_sectors,
+            fat_size,
+            fat_count,
+            data_start,
+            cluster_count: cluster_count as u32,
+            root_cluster,
+            fs_info_sector: (1..reserved_sectors)
+                .contains(&fs_info_sector)
+                .then_some(fs_info_sector),
+            allocation: Spinlock::new(Allocation {
+                free_count: None,
+                next_free: 2,
+            }),
+            nodes: Spinlock::new(BTreeMap::new()),
+            mount: Spinlock::new(Weak::new()),
+        };
+
+        if !volume.is_valid_cluster(root_cluster) {
+            return Err(Error::InvalidArgument);
+        }
+
+        volume.read_fs_info()?;
+
+        Ok(volume)
+    }
+
+    /// Takes the allocation hints from the FSInfo sector, a sector without valid signatures is ignored
+    fn read_fs_info(&mut self) -> Result<()> {
+        let Some(sector) = self.fs_info_sector else {
+            return Ok(());
+        };
+
+        let buffer = self.bread(sector)?;
+        let buffer = buffer.lock();
+        let data = buffer.data();
+
+        if read_u32(data, 0) != FS_INFO_LEAD_SIGNATURE || read_u32(data, 484) != FS_INFO_STRUCT_SIGNATURE {
+            drop(buffer);
+            self.fs_info_sector = None;
+
+            return Ok(());
+        }
+
+        let free_count = read_u32(data, 488);
+        let next_free = read_u32(data, 492);
+
+        let mut allocation = self.allocation.lock();
+
+        allocation.free_count = (free_count <= self.cluster_count).then_some(free_count);
+
+        if self.is_valid_cluster(next_free) {
+            allocation.next_free = next_free;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the allocation hints in the FSInfo sector
+    fn write_fs_info(&self) -> Result<()> {
+        let Some(sector) = self.fs_info_sector else {
+            return Ok(());
+        };
+
+        let allocation = self.allocation.lock();
+
+        let buffer = self.bread(sector)?;
+        let mut buffer = buffer.lock();
+        let data = buffer.data_mut();
+
+        write_u32(data, 488, allocation.free_count.unwrap_or(FS_INFO_UNKNOWN));
+        write_u32(data, 492, allocation.next_free);
+
+        Ok(())
+    }
+
+    fn bread(&self, sector: u64) -> Result<Arc<Spinlock<Buffer>>> {
+        buffer_cache::bread(&self.device, sector, self.bytes_per_sector)
+    }
+
+    /// Reads `buf.len()` bytes at the byte `position` of the volume
+    fn read_at(&self, position: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = position + done as u64;
+            let start = (pos % self.bytes_per_sector as u64) as usize;
+            let len = (self.bytes_per_sector - start).min(buf.len() - done);
+
+            let buffer = self.bread(pos / self.bytes_per_sector as u64)?;
+            buf[done..done + len].copy_from_slice(&buffer.lock().data()[start..start + len]);
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `buf` at the byte `position` of the volume, the buffers are written back when the volume is synced
+    fn write_at(&self, position: u64, buf: &[u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = position + done as u64;
+            let start = (pos % self.bytes_per_sector as u64) as usize;
+            let len = (self.bytes_per_sector - start).min(buf.len() - done);
+
+            let buffer = self.bread(pos / self.bytes_per_sector as u64)?;
+            buffer.lock().data_mut()[start..start + len].copy_from_slice(&buf[done..done + len]);
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+
+    /// Returns the byte position of `cluster` on the volume
+    fn cluster_position(&self, cluster: u32) -> u64 {
+        (self.data_start + (cluster as u64 - 2) * self.sectors_per_cluster as u64) * self.bytes_per_sector as u64
+    }
+
+    /// Returns the byte position of the entry of `cluster` in the FAT `fat`
+    fn fat_position(&self, fat: u64, cluster: u32) -> u64 {
+        (self.fat_start + fat * self.fat_size) * self.bytes_per_sector as u64 + cluster as u64 * 4
+    }
+
+    fn fat_entry(&self, cluster: u32) -> Result<u32> {
+        let mut entry = [0; 4];
+        self.read_at(self.fat_position(0, cluster), &mut entry)?;
+
+        Ok(u32::from_le_bytes(entry) & CLUSTER_MASK)
+    }
+
+    /// Sets the entry of `cluster` in all FATs, the reserved upper bits of the entries are kept
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<()> {
+        for fat in 0..self.fat_count {
+            let position = self.fat_position(fat, cluster);
+
+            let mut entry = [0; 4];
+            self.read_at(position, &mut entry)?;
+
+            let entry = (u32::from_le_bytes(entry) & !CLUSTER_MASK) | (value & CLUSTER_MASK);
+            self.write_at(position, &entry.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the clusters of the chain starting at `first`, cluster 0 stands for an empty chain
+    fn chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+
+        if first == 0 {
+            return Ok(chain);
+        }
+
+        loop {
+            // free or reserved clusters and loops in a chain mean the file system is corrupted
+            if !self.is_valid_cluster(cluster) || chain.len() >= self.cluster_count as usize {
+                return Err(Error::InvalidArgument);
+            }
+
+            chain.push(cluster);
+
+            cluster = self.fat_entry(cluster)?;
+
+            if cluster >= END_OF_CHAIN {
+                return Ok(chain);
+            }
+        }
+    }
+
+    /// Returns the byte position on the volume of the byte at `offset` in the clusters of `chain`, together with the
+    /// number of bytes left in its cluster
+    fn chain_position(&self, chain: &[u32], offset: usize) -> Result<(u64, usize)> {
+        let cluster_size = self.cluster_size();
+        let cluster = *chain.get(offset / cluster_size).ok_or(Error::InvalidArgument)?;
+        let start = offset % cluster_size;
+
+        Ok((self.cluster_position(cluster) + start as u64, cluster_size - start))
+    }
+
+    /// Returns the byte position of the directory entry `slot` in the directory stored in the clusters of `chain`
+    fn slot_position(&self, chain: &[u32], slot: usize) -> Result<u64> {
+        Ok(self.chain_position(chain, slot * DIR_ENTRY_SIZE)?.0)
+    }
+
+    /// Reads the data at `offset` of the clusters of `chain`
+    fn read_chain(&self, chain: &[u32], offset: usize, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let (position, left) = self.chain_position(chain, offset + done)?;
+            let len = left.min(buf.len() - done);
+
+            self.read_at(position, &mut buf[done..done + len])?;
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `buf` at `offset` of the clusters of `chain`
+    fn write_chain(&self, chain: &[u32], offset: usize, buf: &[u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let (position, left) = self.chain_position(chain, offset + done)?;
+            let len = left.min(buf.len() - done);
+
+            self.write_at(position, &buf[done..done + len])?;
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Allocates a zeroed cluster and appends it to the chain which ends with `last`
+    fn allocate_cluster(&self, last: Option<u32>) -> Result<u32> {
+        let mut allocation = self.allocation.lock();
+        let start = allocation.next_free;
+        let mut free = None;
+
+        for cluster in (start..self.cluster_count + 2).chain(2..start) {
+            if self.fat_entry(cluster)? == 0 {
+                free = Some(cluster);
+                break;
+            }
+        }
+
+        let cluster = free.ok_or(Error::NoSpace)?;
+
+        self.set_fat_entry(cluster, CLUSTER_MASK)?;
+
+        if let Some(last) = last {
+            self.set_fat_entry(last, cluster)?;
+        }
+
+        allocation.next_free = if self.is_valid_cluster(cluster + 1) {
+            cluster + 1
+        } else {
+            2
+        };
+
+        if let Some(free_count) = &mut allocation.free_count {
+            *free_count = free_count.saturating_sub(1);
+        }
+
+        drop(allocation);
+
+        self.write_at(self.cluster_position(cluster), &vec![0; self.cluster_size()])?;
+
+        Ok(cluster)
+    }
+
+    fn free_clusters(&self, clusters: &[u32]) -> Result<()> {
+        for cluster in clusters {
+            self.set_fat_entry(*cluster, 0)?;
+        }
+
+        if let Some(free_count) = &mut self.allocation.lock().free_count {
+            *free_count += clusters.len() as u32;
+        }
+
+        Ok(())
+    }
+
+    fn free_chain(&self, first: u32) -> Result<()> {
+        self.free_clusters(&self.chain(first)?)
+    }
+
+    /// Reads the directory stored in the chain starting at `first`
+    fn read_dir(&self, first: u32) -> Result<DirContents> {
+        let chain = self.chain(first)?;
+
+        // a directory always has at least one cluster
+        if chain.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut data = vec![0; chain.len() * self.cluster_size()];
+        self.read_chain(&chain, 0, &mut data)?;
+
+        Ok(DirContents { chain, data })
+    }
+
+    /// Points the `..` entry of the directory stored in the chain starting at `first` to `parent_cluster`
+    fn set_dot_dot(&self, first: u32, parent_cluster: u32) -> Result<()> {
+        let contents = self.read_dir(first)?;
+
+        // some implementations store long names for `.` and `..`, so the entry isn't always the second one
+        let slot = contents
+            .data
+            .chunks_exact(DIR_ENTRY_SIZE)
+            .position(|entry| entry[..11] == DOT_DOT_NAME && entry[11] & ATTR_LONG_NAME_MASK != ATTR_LONG_NAME);
+
+        let Some(slot) = slot else {
+            return Ok(());
+        };
+
+        let mut entry = [0; DIR_ENTRY_SIZE];
+        entry.copy_from_slice(&contents.data[slot * DIR_ENTRY_SIZE..][..DIR_ENTRY_SIZE]);
+        set_entry_cluster(&mut entry, parent_cluster);
+
+        self.write_at(self.slot_position(&contents.chain, slot)?, &entry)
+    }
+
+    /// Returns the vnode of the directory entry `entry` at `position`, a new one is created if the node isn't in use
+    fn node(self: &Arc<Self>, parent: &Arc<Spinlock<VNode>>, position: u64, entry: &[u8]) -> Arc<Spinlock<VNode>> {
+        let mut nodes = self.nodes.lock();
+
+        if let Some(node) = nodes.get(&position).and_then(Weak::upgrade) {
+            return node;
+        }
+
+        let node =
+            Fat32Node::from_entry(self.clone(), parent.clone(), position, entry).into_vnode(self.mount.lock().clone());
+
+        nodes.insert(position, Arc::downgrade(&node));
+
+        node
+    }
+}
+
+/// A directory entry together with its long name
+struct FatDirEntry {
+    name: String,
+    short_name: [u8; 11],
+    /// Slot of the first long name entry, or of the short entry if there is no long name
+    first_slot: usize,
+    /// Slot of the short entry
+    slot: usize,
+    raw: [u8; DIR_ENTRY_SIZE],
+}
+
+impl FatDirEntry {
+    fn is_directory(&self) -> bool {
+        self.raw[11] & ATTR_DIRECTORY != 0
+    }
+
+    fn first_cluster(&self) -> u32 {
+        entry_cluster(&self.raw)
+    }
+
+    fn v_type(&self) -> VType {
+        if self.is_directory() {
+            VType::Directory
+        } else {
+            VType::Regular
+        }
+    }
+
+    /// Names are compared without regard to case, an entry can also be referred to by its short name
+    fn matches(&self, name: &str) -> bool {
+        names_equal(&self.name, name) || names_equal(&short_name_string(&self.raw), name)
+    }
+}
+
+/// Long name which is collected from the entries in front of a short entry
+struct LongName {
+    first_slot: usize,
+    checksum: u8,
+    /// Ordinal of the next entry, the ordinals count down to 1
+    next: u8,
+    /// Parts of the name, starting with the last one
+    parts: Vec<[u16; LONG_NAME_CHARS]>,
+}
+
+impl LongName {
+    /// Adds the long name entry `entry`, an entry which doesn't continue the name discards it
+    fn push(long_name: Option<Self>, slot: usize, entry: &[u8]) -> Option<Self> {
+        let ordinal = entry[0] & !LAST_LONG_ENTRY;
+        let chars = LONG_NAME_OFFSETS.map(|offset| read_u16(entry, offset));
+
+        if entry[0] & LAST_LONG_ENTRY != 0 {
+            return (ordinal != 0).then(|| Self {
+                first_slot: slot,
+                checksum: entry[13],
+                next: ordinal - 1,
+                parts: vec![chars],
+            });
+        }
+
+        let mut long_name = long_name?;
+
+        if ordinal == 0 || ordinal != long_name.next || entry[13] != long_name.checksum {
+            return None;
+        }
+
+        long_name.next -= 1;
+        long_name.parts.push(chars);
+
+        Some(long_name)
+    }
+
+    /// Returns the name, if it is complete and belongs to the short entry with the name `short_name`
+    fn finish(self, short_name: &[u8; 11]) -> Option<(String, usize)> {
+        if self.next != 0 || self.checksum != checksum(short_name) {
+            return None;
+        }
+
+        // the name is terminated by a NUL, unless it fills the last entry
+        let units = self.parts.iter().rev().flatten().copied().take_while(|unit| *unit != 0);
+
+        let name: String = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        (!name.is_empty()).then_some((name, self.first_slot))
+    }
+}
+
+/// Contents of a directory
+struct DirContents {
+    /// Clusters the directory is stored in
+    chain: Vec<u32>,
+    data: Vec<u8>,
+}
+
+impl DirContents {
+    /// Returns the entries of the directory, without `.`, `..` and the volume label
+    fn entries(&self) -> Vec<FatDirEntry> {
+        let mut entries = Vec::new();
+        let mut long_name = None;
+
+        for (slot, raw) in self.data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+            match raw[0] {
+                ENTRY_END => break,
+                ENTRY_FREE => {
+                    long_name = None;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if raw[11] & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+                long_name = LongName::push(long_name.take(), slot, raw);
+                continue;
+            }
+
+            // UNWRAP: the chunks are as long as an entry
+            let raw: [u8; DIR_ENTRY_SIZE] = raw.try_into().unwrap();
+            let short_name: [u8; 11] = raw[..11].try_into().unwrap();
+            let long_name = long_name.take();
+
+            if raw[11] & ATTR_VOLUME_ID != 0 || short_name == DOT_NAME || short_name == DOT_DOT_NAME {
+                continue;
+            }
+
+            let (name, first_slot) = long_name
+                .and_then(|long_name| long_name.finish(&short_name))
+                .unwrap_or_else(|| (short_name_string(&raw), slot));
+
+            entries.push(FatDirEntry {
+                name,
+                short_name,
+                first_slot,
+                slot,
+                raw,
+            });
+        }
+
+        entries
+    }
+
+    fn find(&self, name: &str) -> Option<FatDirEntry> {
+        self.entries().into_iter().find(|entry| entry.matches(name))
+    }
+
+    /// Returns the first slot of `count` consecutive free entries
+    fn free_slots(&self, count: usize) -> Option<usize> {
+        let mut run = 0;
+        let mut end = false;
+
+        for (slot, raw) in self.data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+            end |= raw[0] == ENTRY_END;
+
+            if end || raw[0] == ENTRY_FREE {
+                run += 1;
+
+                if run == count {
+                    return Some(slot + 1 - count);
+                }
+            } else {
+                run = 0;
+            }
+        }
+
+        None
+    }
+}
+
+pub struct Fat32Node {
+    volume: Arc<Volume>,
+    /// The vnode of this node, which becomes the parent of the nodes looked up in it
+    this: Weak<Spinlock<VNode>>,
+    /// Directory which contains the entry of this node, `None` for the root directory
+    parent: Option<Arc<Spinlock<VNode>>>,
+    /// Byte position of the short directory entry on the volume, 0 for the root directory
+    position: u64,
+    v_type: VType,
+    attributes: u8,
+    /// First cluster of the data, 0 for an empty file
+    first_cluster: u32,
+    size: u32,
+    atime: Timespec,
+    mtime: Timespec,
+    /// FAT doesn't store the time of the last change, it is only kept while the node is in use
+    ctime: Timespec,
+    /// The entry was removed while the node was in use, its clusters are freed once the node is inactive
+    unlinked: bool,
+}
+
+impl Fat32Node {
+    fn root(volume: Arc<Volume>) -> Self {
+        let now = rtc::now();
+        let first_cluster = volume.root_cluster;
+
+        Self {
+            volume,
+            this: Weak::new(),
+            parent: None,
+            position: 0,
+            v_type: VType::Directory,
+            attributes: ATTR_DIRECTORY,
+            first_cluster,
+            size: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            unlinked: false,
+        }
+    }
+
+    fn from_entry(volume: Arc<Volume>, parent: Arc<Spinlock<VNode>>, position: u64, entry: &[u8]) -> Self {
+        let attributes = entry[11];
+        let is_directory = attributes & ATTR_DIRECTORY != 0;
+        let mtime = fat_time(read_u16(entry, 24), read_u16(entry, 22));
+
+        Self {
+            volume,
+            this: Weak::new(),
+            parent: Some(parent),
+            position,
+            v_type: if is_directory { VType::Directory } else { VType::Regular },
+            attributes,
+            first_cluster: entry_cluster(entry),
+            // the size of directories is always 0
+            size: if is_directory { 0 } else { read_u32(entry, 28) },
+            atime: fat_time(read_u16(entry, 18), 0),
+            mtime,
+            ctime: mtime,
+            unlinked: false,
+        }
+    }
+
+    /// Wraps the node into a vnode, which the node keeps a weak reference to
+    fn into_vnode(mut self, mount: Weak<Spinlock<Mount>>) -> Arc<Spinlock<VNode>> {
+        let v_type = self.v_type;
+
+        Arc::new_cyclic(|this| {
+            self.this = this.clone();
+
+            Spinlock::new(VNode::new(mount, Arc::new(Spinlock::new(self)), v_type, None))
+        })
+    }
+
+    /// Returns the position of the entry as inode number, which is not stable across renames, see the module docs
+    fn ino(&self) -> u64 {
+        if self.position == 0 { ROOT_INO } else { self.position }
+    }
+
+    /// Writes the attributes, the size and the first cluster of the node to its directory entry
+    fn write_entry(&self) -> Result<()> {
+        // the entry of a removed node may already belong to another file
+        if self.position == 0 || self.unlinked {
+            return Ok(());
+        }
+
+        let mut entry = [0; DIR_ENTRY_SIZE];
+        self.volume.read_at(self.position, &mut entry)?;
+
+        let (date, time) = fat_date_time(self.mtime);
+        let (access_date, _) = fat_date_time(self.atime);
+
+        entry[11] = self.attributes;
+        set_entry_cluster(&mut entry, self.first_cluster);
+        write_u16(&mut entry, 18, access_date);
+        write_u16(&mut entry, 22, time);
+        write_u16(&mut entry, 24, date);
+        write_u32(&mut entry, 28, self.size);
+
+        self.volume.write_at(self.position, &entry)
+    }
+
+    /// Updates the modification and change time after the contents changed
+    fn touch(&mut self) -> Result<()> {
+        self.mtime = rtc::now();
+        self.ctime = self.mtime;
+
+        self.write_entry()
+    }
+
+    fn read_dir(&self) -> Result<DirContents> {
+        if self.v_type != VType::Directory {
+            return Err(Error::NotADirectory);
+        }
+
+        self.volume.read_dir(self.first_cluster)
+    }
+
+    /// Cluster `..` refers to in the directories created in this one, the root directory is referred to as 0
+    fn dot_dot_cluster(&self) -> u32 {
+        if self.position == 0 { 0 } else { self.first_cluster }
+    }
+
+    /// Reads the data of a regular file
+    fn read_data(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let size = self.size as usize;
+        let start = offset.min(size);
+        let end = offset.saturating_add(buf.len()).min(size);
+
+        if start == end {
+            return Ok(0);
+        }
+
+        let chain = self.volume.chain(self.first_cluster)?;
+        self.volume.read_chain(&chain, start, &mut buf[..end - start])?;
+
+        Ok(end - start)
+    }
+
+    /// Truncates or extends the file to `size` bytes, the extension reads as zeros
+    fn resize(&mut self, size: usize) -> Result<()> {
+        let cluster_size = self.volume.cluster_size();
+        let old_size = self.size as usize;
+        let old_count = old_size.div_ceil(cluster_size);
+        let count = size.div_ceil(cluster_size);
+
+        let mut chain = self.volume.chain(self.first_cluster)?;
+
+        self.truncate_chain(&chain, count)?;
+        chain.truncate(count);
+
+        while chain.len() < count {
+            match self.volume.allocate_cluster(chain.last().copied()) {
+                Ok(cluster) => chain.push(cluster),
+                Err(err) => {
+                    // the clusters of a failed extension are given back
+                    self.truncate_chain(&chain, old_count)?;
+                    return Err(err);
+                }
+            }
+
+            if self.first_cluster == 0 {
+                self.first_cluster = chain[0];
+            }
+        }
+
+        // new clusters are zeroed, but the last cluster may still hold data from before a truncation
+        let stale_end = size.min(old_count * cluster_size);
+
+        if stale_end > old_size {
+            self.volume
+                .write_chain(&chain, old_size, &vec![0; stale_end - old_size])?;
+        }
+
+        self.size = size as u32;
+
+        Ok(())
+    }
+
+    /// Frees the clusters of `chain` which follow the first `count` ones
+    fn truncate_chain(&mut self, chain: &[u32], count: usize) -> Result<()> {
+        if count >= chain.len() {
+            return Ok(());
+        }
+
+        if count == 0 {
+            self.first_cluster = 0;
+        } else {
+            self.volume.set_fat_entry(chain[count - 1], CLUSTER_MASK)?;
+        }
+
+        self.volume.free_clusters(&chain[count..])
+    }
+
+    /// Adds a new node with the name `name` and the short entry `entry` to this directory
+    fn insert_node(&mut self, name: &str, mut entry: [u8; DIR_ENTRY_SIZE]) -> Result<Arc<Spinlock<VNode>>> {
+        let position = self.insert_entry(name, &mut entry)?;
+
+        self.touch()?;
+
+        let this = self.this.upgrade().ok_or(Error::EntryNotFound)?;
+
+        Ok(self.volume.node(&this, position, &entry))
+    }
+
+    /// Writes the entries of `name` to this directory and returns the position of the short entry
+    ///
+    /// The short name of `entry` is replaced by the one which is generated for `name`.
+    fn insert_entry(&self, name: &str, entry: &mut [u8; DIR_ENTRY_SIZE]) -> Result<u64> {
+        check_name(name)?;
+
+        let mut contents = self.read_dir()?;
+        let entries = contents.entries();
+
+        if entries.iter().any(|entry| entry.matches(name)) {
+            return Err(Error::EntryExists);
+        }
+
+        // names which are valid short names don't need a long name
+        let (short_name, long_name) = match exact_short_name(name) {
+            Some(short_name) => (short_name, Vec::new()),
+            None => (unique_short_name(name, &entries), name.encode_utf16().collect()),
+        };
+
+        entry[..11].copy_from_slice(&short_name);
+        entry[12] = 0;
+
+        let long_entries = long_name.len().div_ceil(LONG_NAME_CHARS);
+
+        let slot = loop {
+            if let Some(slot) = contents.free_slots(long_entries + 1) {
+                break slot;
+            }
+
+            let cluster = self.volume.allocate_cluster(contents.chain.last().copied())?;
+
+            contents.chain.push(cluster);
+            contents
+                .data
+                .resize(contents.data.len() + self.volume.cluster_size(), 0);
+        };
+
+        let checksum = checksum(&short_name);
+
+        // the long name entries are stored in reverse order in front of the short entry
+        for index in 0..long_entries {
+            let ordinal = long_entries - index;
+            let long_entry = long_name_entry(&long_name, ordinal, checksum, index == 0);
+
+            self.volume
+                .write_at(self.volume.slot_position(&contents.chain, slot + index)?, &long_entry)?;
+        }
+
+        let position = self.volume.slot_position(&contents.chain, slot + long_entries)?;
+        self.volume.write_at(position, entry)?;
+
+        Ok(position)
+    }
+
+    /// Marks the entries of `entry` as free
+    fn delete_entry(&self, contents: &DirContents, entry: &FatDirEntry) -> Result<()> {
+        for slot in entry.first_slot..=entry.slot {
+            self.volume
+                .write_at(self.volume.slot_position(&contents.chain, slot)?, &[ENTRY_FREE])?;
+        }
+
+        Ok(())
+    }
+
+    /// Restores the entries of `entry` after [`Self::delete_entry`]
+    fn restore_entry(&self, contents: &DirContents, entry: &FatDirEntry) -> Result<()> {
+        for slot in entry.first_slot..=entry.slot {
+            self.volume.write_at(
+                self.volume.slot_position(&contents.chain, slot)?,
+                &contents.data[slot * DIR_ENTRY_SIZE..][..1],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes `entry` from this directory
+    ///
+    /// The clusters of the node are freed right away, unless the node is still in use.
+    fn unlink(&self, contents: &DirContents, entry: &FatDirEntry) -> Result<()> {
+        let position = self.volume.slot_position(&contents.chain, entry.slot)?;
+
+        self.delete_entry(contents, entry)?;
+
+        let node = self
+            .volume
+            .nodes
+            .lock()
+            .remove(&position)
+            .and_then(|node| node.upgrade());
+
+        match node {
+            Some(node) => {
+                node.lock().downcast_data(|node: &mut Fat32Node| node.unlinked = true);
+            }
+            None => self.volume.free_chain(entry.first_cluster())?,
+        }
+
+        Ok(())
+    }
+
+    /// Moves the entry `old_name` of this directory to `new_name` in `new_dir`, or in this directory if `new_dir` is
+    /// `None`
+    fn move_entry(&mut self, mut new_dir: Option<&mut Fat32Node>, old_name: &str, new_name: &str) -> Result<()> {
+        let contents = self.read_dir()?;
+        let source = contents.find(old_name).ok_or(Error::EntryNotFound)?;
+        let source_position = self.volume.slot_position(&contents.chain, source.slot)?;
+
+        let target_dir: &Fat32Node = new_dir.as_deref().unwrap_or(self);
+
+        if !Arc::ptr_eq(&self.volume, &target_dir.volume) {
+            return Err(Error::CrossDevice);
+        }
+
+        let target_contents = target_dir.read_dir()?;
+
+        if let Some(target) = target_contents.find(new_name) {
+            let target_position = self.volume.slot_position(&target_contents.chain, target.slot)?;
+
+            if target_position != source_position {
+                match (source.is_directory(), target.is_directory()) {
+                    (false, true) => return Err(Error::IsADirectory),
+                    (true, false) => return Err(Error::NotADirectory),
+                    (true, true) if !self.volume.read_dir(target.first_cluster())?.entries().is_empty() => {
+                        return Err(Error::NotEmpty);
+                    }
+                    _ => {}
+                }
+
+                target_dir.unlink(&target_contents, &target)?;
+            } else if source.name == new_name {
+                // renaming a file to its own name does nothing, but the case of a name can be changed
+                return Ok(());
+            }
+        }
+
+        self.delete_entry(&contents, &source)?;
+
+        let mut entry = source.raw;
+
+        let position = match target_dir.insert_entry(new_name, &mut entry) {
+            Ok(position) => position,
+            Err(err) => {
+                self.restore_entry(&contents, &source)?;
+                return Err(err);
+            }
+        };
+
+        let moved = new_dir.is_some();
+
+        // a moved directory has to refer to its new parent
+        if moved && source.is_directory() {
+            self.volume
+                .set_dot_dot(source.first_cluster(), target_dir.dot_dot_cluster())?;
+        }
+
+        let node = {
+            let mut nodes = self.volume.nodes.lock();
+            let node = nodes.remove(&source_position);
+
+            node.and_then(|node| {
+                nodes.insert(position, node.clone());
+                node.upgrade()
+            })
+        };
+
+        if let Some(node) = node {
+            let parent = target_dir.this.upgrade();
+
+            node.lock().downcast_data(|node: &mut Fat32Node| {
+                node.position = position;
+
+                if moved {
+                    node.parent = parent;
+                }
+            });
+        }
+
+        self.touch()?;
+
+        if let Some(new_dir) = &mut new_dir {
+            new_dir.touch()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl VNodeOperations for Fat32Node {
+    fn close(&self) {}
+
+    fn create(
+        &mut self,
+        name: String,
+        v_type: VType,
+        mode: u32,
+        mount: Weak<Spinlock<Mount>>,
+    ) -> Result<Arc<Spinlock<VNode>>> {
+        match v_type {
+            VType::Regular => self.insert_node(&name, new_entry(ATTR_ARCHIVE | mode_attributes(mode), 0)),
+            VType::Directory => self.mkdir(&name, mode, mount),
+            // FAT can only store regular files and directories
+            _ => Err(Error::NotPermitted),
+        }
+    }
+
+    fn inactive(&self) {
+        if !self.unlinked {
+            return;
+        }
+
+        if let Err(err) = self.volume.free_chain(self.first_cluster) {
+            error!("fat32: freeing the clusters of a removed file failed: {:?}", err);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Spinlock<VNode>>> {
+        if name == ".." {
+            return self.parent.clone().ok_or(Error::EntryNotFound);
+        }
+
+        let contents = self.read_dir()?;
+        let entry = contents.find(name).ok_or(Error::EntryNotFound)?;
+        let position = self.volume.slot_position(&contents.chain, entry.slot)?;
+
+        let this = self.this.upgrade().ok_or(Error::EntryNotFound)?;
+
+        Ok(self.volume.node(&this, position, &entry.raw))
+    }
+
+    fn open(&self) {}
+
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        // NOTE: the access date is not updated on reads, like a file system mounted with noatime
+        if self.v_type == VType::Directory {
+            return Err(Error::IsADirectory);
+        }
+
+        self.read_data(offset, buf)
+    }
+
+    fn write(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
+        if self.v_type == VType::Directory {
+            return Err(Error::IsADirectory);
+        }
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // the size of a file is stored in 32 bits
+        let end = offset
+            .checked_add(buf.len())
+            .filter(|end| *end <= u32::MAX as usize)
+            .ok_or(Error::NoSpace)?;
+
+        if end > self.size as usize {
+            self.resize(end)?;
+        }
+
+        let chain = self.volume.chain(self.first_cluster)?;
+        self.volume.write_chain(&chain, offset, buf)?;
+
+        self.attributes |= ATTR_ARCHIVE;
+        self.touch()?;
+
+        Ok(buf.len())
+    }
+
+    fn getattr(&self) -> Result<VAttr> {
+        // FAT has no permissions, only a read-only attribute
+        let (mode, nlink) = match self.v_type {
+            VType::Directory => {
+                let subdirectories = self
+                    .read_dir()?
+                    .entries()
+                    .iter()
+                    .filter(|entry| entry.is_directory())
+                    .count();
+
+                (0o755, subdirectories + 2)
+            }
+            _ => (0o644, 1),
+        };
+
+        let mode = if self.attributes & ATTR_READ_ONLY != 0 {
+            mode & !0o222
+        } else {
+            mode
+        };
+
+        Ok(VAttr {
+            v_type: self.v_type,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: self.size as usize,
+            nlink,
+            ino: self.ino(),
+            atime: self.atime,
+            mtime: self.mtime,
+            ctime: self.ctime,
+        })
+    }
+
+    fn setattr(&mut self, attr: &SetAttr) -> Result<()> {
+        // all files belong to root
+        if attr.uid.is_some_and(|uid| uid != 0) || attr.gid.is_some_and(|gid| gid != 0) {
+            return Err(Error::NotPermitted);
+        }
+
+        if let Some(mode) = attr.mode {
+            self.attributes = (self.attributes & !ATTR_READ_ONLY) | mode_attributes(mode);
+        }
+
+        if let Some(size) = attr.size {
+            if self.v_type == VType::Directory {
+                return Err(Error::IsADirectory);
+            }
+
+            if size > u32::MAX as usize {
+                return Err(Error::InvalidArgument);
+            }
+
+            if size != self.size as usize {
+                self.resize(size)?;
+                self.mtime = rtc::now();
+            }
+        }
+
+        if let Some(atime) = attr.atime {
+            self.atime = atime;
+        }
+
+        if let Some(mtime) = attr.mtime {
+            self.mtime = mtime;
+        }
+
+        self.ctime = rtc::now();
+
+        self.write_entry()
+    }
+
+    fn getpages(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if self.v_type == VType::Directory {
+            return Err(Error::IsADirectory);
+        }
+
+        self.read_data(offset, buf)
+    }
+
+    fn putpages(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
+        if self.v_type == VType::Directory {
+            return Err(Error::IsADirectory);
+        }
+
+        let size = self.size as usize;
+        let start = offset.min(size);
+        let end = offset.saturating_add(buf.len()).min(size);
+
+        if start == end {
+            return Ok(0);
+        }
+
+        let chain = self.volume.chain(self.first_cluster)?;
+        self.volume.write_chain(&chain, start, &buf[..end - start])?;
+
+        self.touch()?;
+
+        Ok(end - start)
+    }
+
+    fn readdir(&self, cookie: usize) -> Result<Option<DirEntry>> {
+        let contents = self.read_dir()?;
+
+        // the cookie is the slot at which the search for the next entry starts
+        let Some(entry) = contents.entries().into_iter().find(|entry| entry.first_slot >= cookie) else {
+            return Ok(None);
+        };
+
+        Ok(Some(DirEntry {
+            ino: self.volume.slot_position(&contents.chain, entry.slot)?,
+            v_type: entry.v_type(),
+            next_cookie: entry.slot + 1,
+            name: entry.name,
+        }))
+    }
+
+    fn readlink(&self) -> Result<String> {
+        Err(Error::InvalidArgument)
+    }
+
+    fn remove(&mut self, name: &str) -> Result<()> {
+        let contents = self.read_dir()?;
+        let entry = contents.find(name).ok_or(Error::EntryNotFound)?;
+
+        if entry.is_directory() {
+            return Err(Error::IsADirectory);
+        }
+
+        self.unlink(&contents, &entry)?;
+
+        self.touch()
+    }
+
+    fn link(&mut self, _name: &str, _node: &Arc<Spinlock<VNode>>) -> Result<()> {
+        // FAT has no hard links
+        Err(Error::NotPermitted)
+    }
+
+    fn rename(&mut self, old_name: &str, new_dir: Option<&VNode>, new_name: &str) -> Result<()> {
+        let Some(new_dir) = new_dir else {
+            return self.move_entry(None, old_name, new_name);
+        };
+
+        new_dir
+            .downcast_data(|new_dir: &mut Fat32Node| self.move_entry(Some(new_dir), old_name, new_name))
+            .ok_or(Error::CrossDevice)?
+    }
+
+    fn mkdir(&mut self, name: &str, mode: u32, _mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>> {
+        let cluster = self.volume.allocate_cluster(None)?;
+
+        let mut dot = new_entry(ATTR_DIRECTORY, cluster);
+        dot[..11].copy_from_slice(&DOT_NAME);
+
+        let mut dot_dot = new_entry(ATTR_DIRECTORY, self.dot_dot_cluster());
+        dot_dot[..11].copy_from_slice(&DOT_DOT_NAME);
+
+        let position = self.volume.cluster_position(cluster);
+
+        let inserted = self
+            .volume
+            .write_at(position, &dot)
+            .and_then(|_| self.volume.write_at(position + DIR_ENTRY_SIZE as u64, &dot_dot))
+            .and_then(|_| self.insert_node(name, new_entry(ATTR_DIRECTORY | mode_attributes(mode), cluster)));
+
+        if inserted.is_err() {
+            self.volume.free_clusters(&[cluster])?;
+        }
+
+        inserted
+    }
+
+    fn rmdir(&mut self, name: &str) -> Result<()> {
+        let contents = self.read_dir()?;
+        let entry = contents.find(name).ok_or(Error::EntryNotFound)?;
+
+        if !entry.is_directory() {
+            return Err(Error::NotADirectory);
+        }
+
+        if !self.volume.read_dir(entry.first_cluster())?.entries().is_empty() {
+            return Err(Error::NotEmpty);
+        }
+
+        self.unlink(&contents, &entry)?;
+
+        self.touch()
+    }
+
+    fn symlink(&mut self, _name: &str, _target: &str, _mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>> {
+        // FAT has no symbolic links
+        Err(Error::NotPermitted)
+    }
+}
+
+/// Returns a short entry with the current time, the name is filled in when the entry is inserted
+fn new_entry(attributes: u8, first_cluster: u32) -> [u8; DIR_ENTRY_SIZE] {
+    let mut entry = [0; DIR_ENTRY_SIZE];
+    let (date, time) = fat_date_time(rtc::now());
+
+    entry[11] = attributes;
+    write_u16(&mut entry, 14, time);
+    write_u16(&mut entry, 16, date);
+    write_u16(&mut entry, 18, date);
+    write_u16(&mut entry, 22, time);
+    write_u16(&mut entry, 24, date);
+    set_entry_cluster(&mut entry, first_cluster);
+
+    entry
+}
+
+/// Files without any write permission are marked as read-only
+fn mode_attributes(mode: u32) -> u8 {
+    if mode & 0o222 == 0 { ATTR_READ_ONLY } else { 0 }
+}
+
+fn entry_cluster(entry: &[u8]) -> u32 {
+    ((read_u16(entry, 20) as u32) << 16) | read_u16(entry, 26) as u32
+}
+
+fn set_entry_cluster(entry: &mut [u8], cluster: u32) {
+    write_u16(entry, 20, (cluster >> 16) as u16);
+    write_u16(entry, 26, cluster as u16);
+}
+
+/// Returns the long name entry with the ordinal `ordinal`, which holds the characters of `name` starting at
+/// `(ordinal - 1) * 13`
+fn long_name_entry(name: &[u16], ordinal: usize, checksum: u8, last: bool) -> [u8; DIR_ENTRY_SIZE] {
+    let mut entry = [0; DIR_ENTRY_SIZE];
+    let start = (ordinal - 1) * LONG_NAME_CHARS;
+
+    entry[0] = ordinal as u8 | if last { LAST_LONG_ENTRY } else { 0 };
+    entry[11] = ATTR_LONG_NAME;
+    entry[13] = checksum;
+
+    for (index, offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+        // the name is terminated by a NUL and padded with 0xffff
+        let unit = match name.get(start + index) {
+            Some(unit) => *unit,
+            None if start + index == name.len() => 0,
+            None => 0xffff,
+        };
+
+        write_u16(&mut entry, *offset, unit);
+    }
+
+    entry
+}
+
+/// Checksum of a short name, which is stored in its long name entries
+fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// Returns the short name of `entry` as it is displayed, e.g. `README.TXT`
+fn short_name_string(entry: &[u8]) -> String {
+    let mut base = entry[..8].to_vec();
+
+    // 0xe5 marks a free entry, so a name starting with it is stored with 0x05
+    if base[0] == 0x05 {
+        base[0] = ENTRY_FREE;
+    }
+
+    // NOTE: names are stored in an OEM code page, which is treated as Latin-1
+    let convert = |part: &[u8], lower: bool| -> String {
+        part.iter()
+            .map(|byte| *byte as char)
+            .map(|c| if lower { c.to_ascii_lowercase() } else { c })
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    };
+
+    let base = convert(&base, entry[12] & CASE_LOWER_BASE != 0);
+    let extension = convert(&entry[8..11], entry[12] & CASE_LOWER_EXT != 0);
+
+    if extension.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, extension)
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let is_valid = !name.is_empty()
+        && name.encode_utf16().count() <= MAX_NAME_LEN
+        && !name.chars().any(|c| c < ' ' || INVALID_NAME_CHARS.contains(c));
+
+    if is_valid { Ok(()) } else { Err(Error::InvalidArgument) }
+}
+
+fn is_short_name_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&byte)
+}
+
+/// Returns the short name of `name` if it can be stored without a long name
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, extension) = name.split_once('.').unwrap_or((name, ""));
+
+    let is_valid = (1..=8).contains(&base.len())
+        && extension.len() <= 3
+        && base.bytes().chain(extension.bytes()).all(is_short_name_char);
+
+    if !is_valid {
+        return None;
+    }
+
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + extension.len()].copy_from_slice(extension.as_bytes());
+
+    Some(short_name)
+}
+
+/// Generates a short name for `name` with a numeric tail like `LONGNA~1.TXT`, which no entry of the directory uses
+fn unique_short_name(name: &str, entries: &[FatDirEntry]) -> [u8; 11] {
+    let name = name.trim_start_matches('.');
+    let (base, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    let convert = |part: &str, len: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|c| *c != ' ' && *c != '.')
+            .map(|c| match c.to_ascii_uppercase() {
+                c if c.is_ascii() && is_short_name_char(c as u8) => c as u8,
+                _ => b'_',
+            })
+            .take(len)
+            .collect()
+    };
+
+    let base = convert(base, 8);
+    let extension = convert(extension, 3);
+
+    (1..)
+        .map(|number| {
+            let tail = format!("~{}", number);
+            let len = base.len().min(8 - tail.len());
+
+            let mut short_name = [b' '; 11];
+            short_name[..len].copy_from_slice(&base[..len]);
+            short_name[len..len + tail.len()].copy_from_slice(tail.as_bytes());
+            short_name[8..8 + extension.len()].copy_from_slice(&extension);
+
+            short_name
+        })
+        .find(|short_name| !entries.iter().any(|entry| entry.short_name == *short_name))
+        // UNWRAP: a directory has fewer entries than there are numeric tails
+        .unwrap()
+}
+
+fn names_equal(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+/// Converts a FAT date and time to a timestamp, the times on the disk are treated as UTC
+fn fat_time(date: u16, time: u16) -> Timespec {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf).max(1) as i64;
+    let day = (date & 0x1f).max(1) as i64;
+
+    let hour = (time >> 11) as i64;
+    let minute = ((time >> 5) & 0x3f) as i64;
+    let second = (time & 0x1f) as i64 * 2;
+
+    Timespec {
+        tv_sec: rtc::unix_time(year, month, day, hour, minute, second),
+        tv_nsec: 0,
+    }
+}
+
+/// Converts a timestamp to a FAT date and time, which can only store the years from 1980 to 2107
+fn fat_date_time(time: Timespec) -> (u16, u16) {
+    let (year, month, day, hour, minute, second) = rtc::date_time(time.tv_sec);
+
+    match year {
+        ..1980 => ((1 << 5) | 1, 0),
+        2108.. => ((127 << 9) | (12 << 5) | 31, (23 << 11) | (59 << 5) | 29),
+        _ => {
+            let date = (((year - 1980) << 9) | (month << 5) | day) as u16;
+            let time = ((hour << 11) | (minute << 5) | (second / 2)) as u16;
+
+            (date, time)
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    // UNWRAP: the slice is 4 bytes long
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,99 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use libxernel::sync::Spinlock;
+use libxernel::syscall::SyscallError;
+
+use crate::syscall::Result;
+
+use super::file::File;
+
+/// Maximum number of file descriptors a process can have open at the same time
+pub const MAX_FDS: usize = 256;
+
+/// Entry of the file descriptor table of a process
+///
+/// Descriptors which were duplicated or inherited by fork refer to the same [`File`] and share its offset.
+#[derive(Clone)]
+pub struct FileDescriptor {
+    pub file: Arc<Spinlock<File>>,
+    pub close_on_exec: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct FdTable {
+    fds: BTreeMap<usize, FileDescriptor>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self { fds: BTreeMap::new() }
+    }
+
+    pub fn get(&self, fd: usize) -> Result<&Arc<Spinlock<File>>> {
+        self.get_descriptor(fd).map(|descriptor| &descriptor.file)
+    }
+
+    pub fn get_descriptor(&self, fd: usize) -> Result<&FileDescriptor> {
+        self.fds.get(&fd).ok_or(SyscallError::BadFileDescriptor)
+    }
+
+    pub fn get_descriptor_mut(&mut self, fd: usize) -> Result<&mut FileDescriptor> {
+        self.fds.get_mut(&fd).ok_or(SyscallError::BadFileDescriptor)
+    }
+
+    /// Inserts the file at the lowest free descriptor
+    pub fn insert(&mut self, file: Arc<Spinlock<File>>, close_on_exec: bool) -> Result<usize> {
+        self.insert_from(0, file, close_on_exec)
+    }
+
+    /// Inserts the file at the lowest free descriptor which is greater than or equal to `min_fd`
+    pub fn insert_from(&mut self, min_fd: usize, file: Arc<Spinlock<File>>, close_on_exec: bool) -> Result<usize> {
+        if min_fd >= MAX_FDS {
+            return Err(SyscallError::InvalidArgument);
+        }
+
+        let fd = (min_fd..MAX_FDS)
+            .find(|fd| !self.fds.contains_key(fd))
+            .ok_or(SyscallError::TooManyOpenFiles)?;
+
+        self.fds.insert(fd, FileDescriptor { file, close_on_exec });
+
+        Ok(fd)
+    }
+
+    /// Places the file at the descriptor `fd` and returns the descriptor it replaced
+    pub fn replace(
+        &mut self,
+        fd: usize,
+        file: Arc<Spinlock<File>>,
+        close_on_exec: bool,
+    ) -> Result<Option<FileDescriptor>> {
+        if fd >= MAX_FDS {
+            return Err(SyscallError::BadFileDescriptor);
+        }
+
+        Ok(self.fds.insert(fd, FileDescriptor { file, close_on_exec }))
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Result<FileDescriptor> {
+        self.fds.remove(&fd).ok_or(SyscallError::BadFileDescriptor)
+    }
+
+    /// Removes all descriptors which are marked as close-on-exec
+    pub fn remove_close_on_exec(&mut self) -> Vec<FileDescriptor> {
+        let fds: Vec<usize> = self
+            .fds
+            .iter()
+            .filter(|(_, descriptor)| descriptor.close_on_exec)
+            .map(|(fd, _)| *fd)
+            .collect();
+
+        fds.iter().filter_map(|fd| self.fds.remove(fd)).collect()
+    }
+
+    /// Removes all descriptors, used when the process exits
+    pub fn clear(&mut self) -> Vec<FileDescriptor> {
+        core::mem::take(&mut self.fds).into_values().collect()
+    }
+}
@@ -1,19 +1,92 @@
 use alloc::sync::Arc;
 use libxernel::sync::Spinlock;
+use libxernel::syscall::OpenFlags;
 
 use super::vnode::VNode;
+use super::{Error, Result};
 
+/// An open file, file descriptors which refer to the same open file share its offset
 pub struct File {
     node: Arc<Spinlock<VNode>>,
     offset: usize,
+    flags: OpenFlags,
 }
 
 impl File {
-    pub fn new(node: Arc<Spinlock<VNode>>) -> Self {
-        Self { node, offset: 0 }
+    pub fn new(node: Arc<Spinlock<VNode>>, flags: OpenFlags) -> Self {
+        Self { node, offset: 0, flags }
     }
 
     pub fn get_node(&self) -> Arc<Spinlock<VNode>> {
         self.node.clone()
     }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn set_offset(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    /// Reads from the current offset and advances it by the number of bytes read
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.read_at(self.offset, buf)?;
+        self.offset += read;
+
+        Ok(read)
+    }
+
+    /// Writes at the current offset and advances it by the number of bytes written
+    ///
+    /// A file opened with `O_APPEND` moves the offset to the end of the file before every write.
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.check_writable()?;
+
+        let node = self.node.lock();
+
+        if self.flags.contains(OpenFlags::APPEND) {
+            self.offset = node.getattr()?.size;
+        }
+
+        let written = node.write(self.offset, buf)?;
+        self.offset += written;
+
+        Ok(written)
+    }
+
+    /// Reads at `offset` without using or changing the offset of the file
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if !self.flags.is_readable() {
+            return Err(Error::BadFileDescriptor);
+        }
+
+        self.node.lock().read(offset, buf)
+    }
+
+    /// Writes at `offset` without using or changing the offset of the file
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.check_writable()?;
+
+        self.node.lock().write(offset, buf)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.flags.is_writable() {
+            Ok(())
+        } else {
+            Err(Error::BadFileDescriptor)
+        }
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        // the last descriptor which referred to this file was closed
+        self.node.lock().close();
+    }
 }
@@ -1,44 +1,291 @@
+//! The initramfs is a cpio archive in the newc format, which is unpacked into the root file system at boot.
+
 use alloc::{
-    collections::btree_map::BTreeMap,
+    collections::BTreeMap,
+    format,
     string::{String, ToString},
-    vec::Vec,
+    sync::Arc,
+};
+use libxernel::{
+    sync::Spinlock,
+    syscall::{S_IALLUGO, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG, Timespec},
 };
-use libxernel::sync::Spinlock;
 
-use crate::utils::limine_module;
+use crate::{allocator::align_up, utils::limine_module};
+
+use super::{
+    Error, Result,
+    vfs::{VFS, Vfs},
+    vnode::{SetAttr, VNode, VType},
+};
+
+const NEWC_MAGIC: &[u8] = b"070701";
+/// Same layout as [`NEWC_MAGIC`], but the header carries a checksum of the data, which we ignore
+const NEWC_CRC_MAGIC: &[u8] = b"070702";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+/// A single member of a newc cpio archive
+struct CpioEntry<'a> {
+    ino: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    mtime: u32,
+    dev: (u32, u32),
+    name: &'a str,
+    data: &'a [u8],
+}
+
+impl<'a> CpioEntry<'a> {
+    /// Parses the entry at `offset` and returns it together with the offset of the next entry
+    fn parse(archive: &'a [u8], offset: usize) -> Option<(Self, usize)> {
+        let header = archive.get(offset..offset + HEADER_SIZE)?;
+
+        if &header[..6] != NEWC_MAGIC && &header[..6] != NEWC_CRC_MAGIC {
+            return None;
+        }
+
+        // all fields are 8 hex digits and follow the magic
+        let field = |index: usize| {
+            let digits = core::str::from_utf8(&header[6 + index * 8..14 + index * 8]).ok()?;
+            u32::from_str_radix(digits, 16).ok()
+        };
+
+        let name_size = field(11)? as usize;
+        let data_size = field(6)? as usize;
 
-static initramfs: Spinlock<BTreeMap<String, Vec<u8>>> = Spinlock::new(BTreeMap::new());
+        let name_start = offset + HEADER_SIZE;
+        // the name size includes the terminating NUL
+        let name = archive.get(name_start..name_start + name_size.checked_sub(1)?)?;
+        let name = core::str::from_utf8(name).ok()?;
 
+        let data_start = align_up(name_start + name_size, 4);
+        let data = archive.get(data_start..data_start + data_size)?;
+
+        let entry = Self {
+            ino: field(0)?,
+            mode: field(1)?,
+            uid: field(2)?,
+            gid: field(3)?,
+            nlink: field(4)?,
+            mtime: field(5)?,
+            dev: (field(7)?, field(8)?),
+            name,
+            data,
+        };
+
+        Some((entry, align_up(data_start + data_size, 4)))
+    }
+}
+
+/// Unpacks the initramfs into the root file system
+///
+/// Directories, regular files, hard links and symbolic links are supported, device nodes are skipped
+/// since the devices are published in devfs.
 pub fn load_initramfs() {
     let file = limine_module::get_limine_module("initramfs").unwrap();
-    let data = unsafe { core::slice::from_raw_parts(file.base.as_ptr().unwrap(), file.length as usize) };
+    let archive = unsafe { core::slice::from_raw_parts(file.base.as_ptr().unwrap(), file.length as usize) };
+
+    let vfs = VFS.lock();
+    let root = vfs.root_node();
+
+    // first path of every file with multiple links, keyed by device and inode number
+    let mut links = BTreeMap::new();
+
+    let mut offset = 0;
+
+    loop {
+        let Some((entry, next)) = CpioEntry::parse(archive, offset) else {
+            error!("initramfs: malformed cpio header at offset {}", offset);
+            break;
+        };
+
+        if entry.name == TRAILER {
+            break;
+        }
+
+        if let Err(err) = unpack_entry(&vfs, &root, &entry, &mut links) {
+            warning!("initramfs: unpacking {} failed: {:?}", entry.name, err);
+        }
+
+        offset = next;
+    }
+}
+
+fn unpack_entry(
+    vfs: &Vfs,
+    root: &Arc<Spinlock<VNode>>,
+    entry: &CpioEntry,
+    links: &mut BTreeMap<(u32, u32, u32), String>,
+) -> Result<()> {
+    let name = entry.name.trim_start_matches("./").trim_matches('/');
+
+    // the archive usually contains an entry for its root directory
+    if name.is_empty() || name == "." {
+        return Ok(());
+    }
+
+    let path = format!("/{}", name);
+    let perm = entry.mode & S_IALLUGO;
+
+    create_parents(vfs, root, &path)?;
+
+    match entry.mode & S_IFMT {
+        S_IFDIR => match vfs.vn_mkdir(root, path.clone(), perm) {
+            Ok(_) => {}
+            Err(Error::EntryExists) => vfs.vn_setattr(
+                root,
+                path.clone(),
+                &SetAttr {
+                    mode: Some(perm),
+                    ..Default::default()
+                },
+            )?,
+            Err(err) => return Err(err),
+        },
+        S_IFREG => {
+            let key = (entry.dev.0, entry.dev.1, entry.ino);
 
-    let mut idx: usize = 0;
+            if let Some(first) = links.get(&key).filter(|_| entry.nlink > 1) {
+                vfs.vn_link(root, first.clone(), path.clone())?;
 
-    while idx < file.length as usize {
-        let name = String::from_utf8(data[idx..idx + 16].iter().take_while(|&&b| b != 0).copied().collect())
-            .expect("Invalid UTF-8 in the name of a file in initramfs");
-        idx += 16;
+                // the data of a hard linked file may come with any of its names
+                if !entry.data.is_empty() {
+                    write_data(vfs, root, &path, entry.data)?;
+                }
+            } else {
+                vfs.vn_create(root, path.clone(), VType::Regular, perm)?;
+                write_data(vfs, root, &path, entry.data)?;
+
+                if entry.nlink > 1 {
+                    links.insert(key, path.clone());
+                }
+            }
+        }
+        S_IFLNK => {
+            let target = core::str::from_utf8(entry.data).map_err(|_| Error::InvalidArgument)?;
+
+            // symbolic links have no attributes of their own
+            return vfs.vn_symlink(root, target.to_string(), path);
+        }
+        _ => return Err(Error::NotPermitted),
+    }
 
-        let size = u64::from_le_bytes([
-            data[idx],
-            data[idx + 1],
-            data[idx + 2],
-            data[idx + 3],
-            data[idx + 4],
-            data[idx + 5],
-            data[idx + 6],
-            data[idx + 7],
-        ]) as usize;
-        idx += 8;
+    let mtime = Timespec {
+        tv_sec: entry.mtime as i64,
+        tv_nsec: 0,
+    };
 
-        let file_data = data[idx..idx + size].to_vec();
-        idx += size;
+    vfs.vn_setattr(
+        root,
+        path,
+        &SetAttr {
+            uid: Some(entry.uid),
+            gid: Some(entry.gid),
+            atime: Some(mtime),
+            mtime: Some(mtime),
+            ..Default::default()
+        },
+    )
+}
 
-        initramfs.lock().insert(name, file_data);
+/// Creates the directories leading to `path` which aren't part of the archive
+fn create_parents(vfs: &Vfs, root: &Arc<Spinlock<VNode>>, path: &str) -> Result<()> {
+    for (index, _) in path.match_indices('/').skip(1) {
+        match vfs.vn_mkdir(root, path[..index].to_string(), 0o755) {
+            Ok(_) | Err(Error::EntryExists) => {}
+            Err(err) => return Err(err),
+        }
     }
+
+    Ok(())
 }
 
-pub fn initramfs_read(path: &str) -> Option<Vec<u8>> {
-    initramfs.lock().get(&path.to_string()).cloned()
+fn write_data(vfs: &Vfs, root: &Arc<Spinlock<VNode>>, path: &str, data: &[u8]) -> Result<()> {
+    let node = vfs.namei(root, path, false)?;
+    let node = node.lock();
+
+    node.setattr(&SetAttr {
+        size: Some(0),
+        ..Default::default()
+    })?;
+    node.write(0, data)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Builds an entry with the header fields in the order of the format, padded like [`CpioEntry::parse`] expects
+    fn entry(magic: &[u8], fields: [u32; 13], name: &str, data: &[u8]) -> Vec<u8> {
+        let mut entry = magic.to_vec();
+
+        for field in fields {
+            entry.extend(format!("{field:08x}").as_bytes());
+        }
+
+        entry.extend(name.as_bytes());
+        entry.push(0);
+        entry.resize(entry.len().next_multiple_of(4), 0);
+        entry.extend(data);
+        entry.resize(entry.len().next_multiple_of(4), 0);
+
+        entry
+    }
+
+    #[test]
+    fn parse_reads_the_header_fields() {
+        let mut archive = entry(
+            NEWC_MAGIC,
+            [7, 0o100644, 1, 2, 3, 4, 5, 8, 9, 0, 0, 6, 0],
+            "hello",
+            b"world",
+        );
+        let first_len = archive.len();
+        archive.extend(entry(
+            NEWC_CRC_MAGIC,
+            [0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 11, 0],
+            TRAILER,
+            b"",
+        ));
+
+        let (file, next) = CpioEntry::parse(&archive, 0).unwrap();
+        assert_eq!((file.ino, file.mode, file.uid, file.gid), (7, 0o100644, 1, 2));
+        assert_eq!((file.nlink, file.mtime, file.dev), (3, 4, (8, 9)));
+        assert_eq!(file.name, "hello");
+        assert_eq!(file.data, b"world");
+        assert_eq!(next, first_len);
+
+        let (trailer, next) = CpioEntry::parse(&archive, next).unwrap();
+        assert_eq!(trailer.name, TRAILER);
+        assert!(trailer.data.is_empty());
+        assert_eq!(next, archive.len());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_entries() {
+        let archive = entry(
+            NEWC_MAGIC,
+            [1, 0o100644, 0, 0, 1, 0, 5, 0, 0, 0, 0, 6, 0],
+            "hello",
+            b"world",
+        );
+
+        // the data is cut off
+        assert!(CpioEntry::parse(&archive[..archive.len() - 4], 0).is_none());
+
+        // the old binary and ASCII formats aren't supported
+        let mut odc = archive.clone();
+        odc[..6].copy_from_slice(b"070707");
+        assert!(CpioEntry::parse(&odc, 0).is_none());
+
+        // a name size of 0 leaves no room for the terminating NUL
+        let empty_name = entry(NEWC_MAGIC, [1, 0o100644, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0], "", b"");
+        assert!(CpioEntry::parse(&empty_name, 0).is_none());
+    }
 }
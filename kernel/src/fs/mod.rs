@@ -10,10 +10,27 @@ pub enum Error {
     EntryNotFound,
     MountPointNotFound,
     FileSystemNotFound,
+    EntryExists,
+    Busy,
+    CrossDevice,
+    NotPermitted,
+    InvalidArgument,
+    TooManySymlinks,
+    /// The file is not open for reading or writing
+    BadFileDescriptor,
+    ReadOnlyFileSystem,
+    NotABlockDevice,
+    /// The file would exceed the largest size the file system supports
+    FileTooLarge,
 }
 
 pub type Result<T, E = Error> = core::result::Result<T, E>;
 
+pub mod buffer_cache;
+pub mod devfs;
+pub mod ext2;
+pub mod fat32;
+pub mod fd_table;
 pub mod file;
 pub mod initramfs;
 mod mount;
@@ -1,6 +1,8 @@
-use super::{Result, pathbuf::PathBuf, vnode::VNode};
+use super::{Error, Result, pathbuf::PathBuf, vnode::VNode};
+use crate::drivers::block::BlockDevice;
 use alloc::{string::String, sync::Arc};
 use libxernel::sync::Spinlock;
+use libxernel::syscall::MountFlags;
 
 // According to BSD each Mount object has a pointer to vfsops and to private data
 // As in vnode we combine the member which holds the vfs operations and the private data which is used by the file system
@@ -10,20 +12,42 @@ pub struct Mount {
     /// VNode we are mounted on
     /// None if root node
     vnode_covered: Option<Arc<Spinlock<VNode>>>,
-    flags: u64,
+    /// Block device a disk file system is read from
+    device: Option<Arc<dyn BlockDevice>>,
+    flags: MountFlags,
 }
 
 impl Mount {
-    pub fn new(driver: Arc<Spinlock<dyn VfsOps>>, vnode_covered: Option<Arc<Spinlock<VNode>>>) -> Self {
+    pub fn new(
+        driver: Arc<Spinlock<dyn VfsOps>>,
+        vnode_covered: Option<Arc<Spinlock<VNode>>>,
+        device: Option<Arc<dyn BlockDevice>>,
+        flags: MountFlags,
+    ) -> Self {
         Mount {
             mnt_op_data: driver,
             vnode_covered,
-            flags: 0,
+            device,
+            flags,
         }
     }
 }
 
 impl Mount {
+    /// Returns the vnode this file system is mounted on, `None` for the root file system
+    pub fn vnode_covered(&self) -> Option<Arc<Spinlock<VNode>>> {
+        self.vnode_covered.clone()
+    }
+
+    /// Returns whether the file system is read from `device`
+    pub fn is_on_device(&self, device: &Arc<dyn BlockDevice>) -> bool {
+        self.device.as_ref().is_some_and(|own| Arc::ptr_eq(own, device))
+    }
+
+    pub fn flags(&self) -> MountFlags {
+        self.flags
+    }
+
     pub fn vfs_mount(&mut self, path: String) {
         self.mnt_op_data.lock().vfs_mount(path)
     }
@@ -32,7 +56,7 @@ impl Mount {
         self.mnt_op_data.lock().vfs_start()
     }
 
-    pub fn vfs_unmount(&self) {
+    pub fn vfs_unmount(&self) -> Result<()> {
         self.mnt_op_data.lock().vfs_unmount()
     }
 
@@ -52,7 +76,7 @@ impl Mount {
         self.mnt_op_data.lock().vfs_sync()
     }
 
-    pub fn vfs_vget(&self) {
+    pub fn vfs_vget(&self) -> Result<Arc<Spinlock<VNode>>> {
         self.mnt_op_data.lock().vfs_vget()
     }
 
@@ -95,7 +119,10 @@ pub trait VfsOps {
     fn vfs_start(&mut self);
 
     /// Unmounts an instance of the file system.
-    fn vfs_unmount(&self);
+    ///
+    /// Fails with [`Error::Busy`](super::Error::Busy) while a vnode of the file system is referenced from outside of
+    /// it, e.g. by an open file, a working directory, a mapping or another file system mounted on it.
+    fn vfs_unmount(&mut self) -> Result<()>;
 
     /// Gets the file system root vnode.
     fn vfs_root(&self) -> Result<Arc<Spinlock<VNode>>>;
@@ -114,7 +141,9 @@ pub trait VfsOps {
     fn vfs_sync(&self);
 
     /// Gets a vnode from a file identifier.
-    fn vfs_vget(&self);
+    fn vfs_vget(&self) -> Result<Arc<Spinlock<VNode>>> {
+        Err(Error::NotPermitted)
+    }
 
     fn vfs_lookup(&self, path: &PathBuf) -> Result<Arc<Spinlock<VNode>>>;
 
@@ -137,7 +166,7 @@ pub trait VfsOps {
     }
 
     /// Finalizes the file system driver.
-    fn vfs_done(&self);
+    fn vfs_done(&self) {}
 
     /// Mounts an instance of the file system as the root file system.
     fn vfs_mountroot(&self) {
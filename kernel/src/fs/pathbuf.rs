@@ -67,6 +67,25 @@ impl PathBuf {
         )
     }
 
+    /// Splits the path into the path of the parent directory and the name of the last component
+    ///
+    /// Returns `None` if the path has no last component, e.g. for `/`.
+    pub fn split_file_name(&self) -> Option<(PathBuf, String)> {
+        let path = self.inner.trim_end_matches('/');
+
+        let (parent, name) = match path.rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some((parent, name)) => (parent, name),
+            None => (".", path),
+        };
+
+        if name.is_empty() {
+            return None;
+        }
+
+        Some((PathBuf::from(parent), name.to_string()))
+    }
+
     pub fn push(&mut self) {
         todo!()
     }
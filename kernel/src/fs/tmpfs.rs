@@ -4,16 +4,24 @@ use alloc::{
     sync::Weak,
     vec::Vec,
 };
-use libxernel::{boot::InitAtBoot, sync::Spinlock};
+use core::sync::atomic::{AtomicU64, Ordering};
+use libxernel::{
+    boot::InitAtBoot,
+    sync::Spinlock,
+    syscall::{S_IALLUGO, Timespec},
+};
 
-use crate::{fs::Error, fs::Result};
+use crate::{fs::Error, fs::Result, utils::rtc};
 
 use super::{
     mount::{Mount, VfsOps},
     pathbuf::PathBuf,
-    vnode::{VNode, VNodeOperations, VType},
+    vnode::{DirEntry, SetAttr, VAttr, VNode, VNodeOperations, VType},
 };
 
+/// Largest size of a file, the data is kept on the kernel heap
+const MAX_FILE_SIZE: usize = 64 * 1024 * 1024;
+
 pub struct Tmpfs {
     root_node: InitAtBoot<Arc<Spinlock<VNode>>>,
     mounted_on: Option<String>,
@@ -40,217 +48,598 @@ impl VfsOps for Tmpfs {
     fn vfs_start(&mut self) {
         self.root_node
             .lock()
-            .create("test.txt".to_string(), VType::Regular)
+            .create("test.txt".to_string(), VType::Regular, 0o644)
             .expect("Creation of root node in tmpfs failed");
     }
 
-    fn vfs_unmount(&self) {
-        todo!()
+    fn vfs_unmount(&mut self) -> Result<()> {
+        // the root is only referenced by this file system while nothing uses it
+        if Arc::strong_count(&self.root_node) > 1 || TmpfsNode::is_busy(&self.root_node) {
+            return Err(Error::Busy);
+        }
+
+        println!("unmounting tmpfs from {}", self.mounted_on.take().unwrap_or_default());
+
+        Ok(())
     }
 
     fn vfs_root(&self) -> Result<Arc<Spinlock<VNode>>> {
         Ok(self.root_node.clone())
     }
 
-    fn vfs_vget(&self) {
-        todo!()
-    }
-
     fn vfs_init(&mut self) {
-        let tmpfs_node = TmpfsNode::new(VType::Directory);
-
-        let root = Arc::new(Spinlock::new(VNode::new(
-            Weak::new(),
-            Arc::new(Spinlock::new(tmpfs_node)),
-            VType::Directory,
-            None,
-        )));
+        let root = TmpfsNode::new(VType::Directory, 0o755).into_vnode(Weak::new());
 
         self.root_node = InitAtBoot::Initialized(root);
     }
 
-    fn vfs_done(&self) {
-        todo!()
-    }
-
     fn vfs_name(&self) -> String {
         "tmpfs".to_string()
     }
 
     fn vfs_lookup(&self, path: &PathBuf) -> Result<Arc<Spinlock<VNode>>> {
-        if path == "/" || path.is_empty() {
-            return Ok(self.root_node.clone());
+        let mut node = self.root_node.clone();
+
+        for name in path.as_string().split('/').filter(|name| !name.is_empty()) {
+            let next = node.lock().lookup(name)?;
+            node = next;
         }
 
-        self.root_node.lock().lookup(path)
+        Ok(node)
     }
 
-    fn vfs_sync(&self) {
-        todo!()
-    }
+    fn vfs_sync(&self) {}
+}
+
+/// Inode numbers are only used to identify nodes in directory listings, they are never reused
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+struct TmpfsDirEntry {
+    name: String,
+    ino: u64,
+    node: Arc<Spinlock<VNode>>,
+    /// Position of the entry in directory listings, which stays the same while other entries come and go
+    cookie: usize,
 }
 
 enum TmpfsNodeData {
-    Children(Vec<(PathBuf, Arc<Spinlock<VNode>>)>),
+    Children(Vec<TmpfsDirEntry>),
     Data(Vec<u8>),
+    Link(String),
 }
 
 pub struct TmpfsNode {
-    parent: Option<Arc<Spinlock<VNode>>>,
+    /// The vnode of this node, which becomes the parent of the directories created in it
+    this: Weak<Spinlock<VNode>>,
+    /// Directory this node was created in, used to look up `..`
+    parent: Weak<Spinlock<VNode>>,
+    ino: u64,
+    v_type: VType,
+    /// Number of directory entries which refer to this node, a directory is also referred to by its own `.` and the
+    /// `..` entries of its subdirectories
+    nlink: usize,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    atime: Timespec,
+    mtime: Timespec,
+    ctime: Timespec,
     data: TmpfsNodeData,
+    /// Cookie of the next entry added to this directory, the entries are kept in the order of their cookies
+    next_cookie: usize,
 }
 
 impl TmpfsNode {
-    pub fn new(vtype: VType) -> Self {
-        if vtype == VType::Directory {
-            Self {
-                parent: None,
-                data: TmpfsNodeData::Children(Vec::new()),
+    pub fn new(vtype: VType, mode: u32) -> Self {
+        let (data, nlink) = match vtype {
+            VType::Directory => (TmpfsNodeData::Children(Vec::new()), 2),
+            VType::SymbolicLink => (TmpfsNodeData::Link(String::new()), 1),
+            _ => (TmpfsNodeData::Data(Vec::new()), 1),
+        };
+
+        let now = rtc::now();
+
+        Self {
+            this: Weak::new(),
+            parent: Weak::new(),
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            v_type: vtype,
+            nlink,
+            mode: mode & S_IALLUGO,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            data,
+            next_cookie: 0,
+        }
+    }
+
+    /// Wraps the node into a vnode, which the node keeps a weak reference to
+    fn into_vnode(mut self, mount: Weak<Spinlock<Mount>>) -> Arc<Spinlock<VNode>> {
+        let v_type = self.v_type;
+
+        Arc::new_cyclic(|this| {
+            self.this = this.clone();
+
+            Spinlock::new(VNode::new(mount, Arc::new(Spinlock::new(self)), v_type, None))
+        })
+    }
+
+    /// Updates the modification and change time after the contents changed
+    fn touch(&mut self) {
+        self.mtime = rtc::now();
+        self.ctime = self.mtime;
+    }
+
+    fn children(&self) -> Result<&Vec<TmpfsDirEntry>> {
+        match &self.data {
+            TmpfsNodeData::Children(children) => Ok(children),
+            _ => Err(Error::NotADirectory),
+        }
+    }
+
+    fn children_mut(&mut self) -> Result<&mut Vec<TmpfsDirEntry>> {
+        match &mut self.data {
+            TmpfsNodeData::Children(children) => Ok(children),
+            _ => Err(Error::NotADirectory),
+        }
+    }
+
+    /// Appends an entry to this directory
+    fn push_child(&mut self, name: &str, ino: u64, node: Arc<Spinlock<VNode>>) -> Result<()> {
+        let cookie = self.next_cookie;
+
+        self.children_mut()?.push(TmpfsDirEntry {
+            name: name.to_string(),
+            ino,
+            node,
+            cookie,
+        });
+        self.next_cookie += 1;
+
+        Ok(())
+    }
+
+    /// Adds a new node with the given name to this directory
+    fn insert_child(
+        &mut self,
+        name: &str,
+        v_type: VType,
+        mode: u32,
+        mount: Weak<Spinlock<Mount>>,
+    ) -> Result<Arc<Spinlock<VNode>>> {
+        self.insert_node(name, TmpfsNode::new(v_type, mode), mount)
+    }
+
+    fn insert_node(
+        &mut self,
+        name: &str,
+        mut tmpfs_node: TmpfsNode,
+        mount: Weak<Spinlock<Mount>>,
+    ) -> Result<Arc<Spinlock<VNode>>> {
+        if self.children()?.iter().any(|entry| entry.name == name) {
+            return Err(Error::EntryExists);
+        }
+
+        let ino = tmpfs_node.ino;
+        let v_type = tmpfs_node.v_type;
+        tmpfs_node.parent = self.this.clone();
+
+        let node = tmpfs_node.into_vnode(mount);
+
+        self.push_child(name, ino, node.clone())?;
+
+        if v_type == VType::Directory {
+            self.nlink += 1;
+        }
+
+        self.touch();
+
+        Ok(node)
+    }
+
+    /// Removes the entry at `index` and drops the link it held
+    ///
+    /// The node itself is freed once no other entry, open file or mapping refers to it anymore.
+    fn remove_child(&mut self, index: usize) -> Result<TmpfsDirEntry> {
+        let entry = self.children_mut()?.remove(index);
+        let is_directory = entry.node.lock().v_type() == VType::Directory;
+
+        entry.node.lock().downcast_data(|node: &mut TmpfsNode| {
+            if is_directory {
+                node.nlink = 0;
+            } else {
+                node.nlink -= 1;
             }
-        } else {
-            Self {
-                parent: None,
-                data: TmpfsNodeData::Data(Vec::new()),
+
+            node.ctime = rtc::now();
+        });
+
+        if is_directory {
+            self.nlink -= 1;
+        }
+
+        self.touch();
+
+        Ok(entry)
+    }
+
+    /// Makes room for an entry `name` which refers to `source`, by removing the existing entry if there is one
+    fn replace_target(&mut self, name: &str, source: &Arc<Spinlock<VNode>>) -> Result<()> {
+        let Some(index) = self.children()?.iter().position(|entry| entry.name == name) else {
+            return Ok(());
+        };
+
+        let source_is_directory = source.lock().v_type() == VType::Directory;
+
+        {
+            let target = self.children()?[index].node.lock();
+
+            match (source_is_directory, target.v_type() == VType::Directory) {
+                (false, true) => return Err(Error::IsADirectory),
+                (true, false) => return Err(Error::NotADirectory),
+                (true, true) if target.readdir(0)?.is_some() => return Err(Error::NotEmpty),
+                _ => {}
             }
         }
+
+        self.remove_child(index)?;
+
+        Ok(())
+    }
+
+    /// Returns whether `node` or a node below it is referenced from outside the file system or has a file system
+    /// mounted on it
+    fn is_busy(node: &Arc<Spinlock<VNode>>) -> bool {
+        let node = node.lock();
+
+        if node.mounted_here().is_some() {
+            return true;
+        }
+
+        node.downcast_data(|node: &mut TmpfsNode| {
+            let Ok(children) = node.children() else {
+                return false;
+            };
+
+            children.iter().any(|entry| {
+                // each link of a file holds a reference, a directory only has a single entry referring to it
+                let links = entry
+                    .node
+                    .lock()
+                    .downcast_data(|child: &mut TmpfsNode| match child.v_type {
+                        VType::Directory => 1,
+                        _ => child.nlink,
+                    })
+                    .unwrap_or(1);
+
+                Arc::strong_count(&entry.node) > links || Self::is_busy(&entry.node)
+            })
+        })
+        .unwrap_or(false)
+    }
+
+    fn position_of(&self, name: &str) -> Result<usize> {
+        self.children()?
+            .iter()
+            .position(|entry| entry.name == name)
+            .ok_or(Error::EntryNotFound)
     }
 }
 
 impl VNodeOperations for TmpfsNode {
-    fn close(&self) {
-        todo!()
-    }
+    fn close(&self) {}
 
     fn create(
         &mut self,
         file_name: String,
         v_type: VType,
+        mode: u32,
         mount: Weak<Spinlock<Mount>>,
     ) -> Result<Arc<Spinlock<VNode>>> {
-        let new_node = Arc::new(Spinlock::new(VNode::new(
-            mount,
-            Arc::new(Spinlock::new(TmpfsNode::new(v_type))),
-            v_type,
-            None,
-        )));
-
-        if let TmpfsNodeData::Children(children) = &mut self.data {
-            children.push((PathBuf::from(file_name), new_node.clone()));
-        } else {
-            return Err(Error::NotADirectory);
+        self.insert_child(&file_name, v_type, mode, mount)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Spinlock<VNode>>> {
+        println!("tmpfs path lookup: {}", name);
+
+        if name == ".." {
+            return self.parent.upgrade().ok_or(Error::EntryNotFound);
         }
 
-        Ok(new_node)
+        self.children()?
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.node.clone())
+            .ok_or(Error::EntryNotFound)
     }
 
-    fn ioctl(&self) {
-        todo!()
+    fn open(&self) {
+        println!("opening file on tmpfs");
     }
 
-    fn lookup(&self, path: &PathBuf) -> Result<Arc<Spinlock<VNode>>> {
-        println!("tmpfs path lookup: {}", path);
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        // NOTE: the access time is not updated on reads, like a file system mounted with noatime
+        if let TmpfsNodeData::Data(data) = &self.data {
+            let start = offset.min(data.len());
+            let end = offset.saturating_add(buf.len()).min(data.len());
+
+            buf[..end - start].copy_from_slice(&data[start..end]);
 
-        let stripped_path = if path.starts_with(&PathBuf::from("/")) {
-            path.strip_prefix(&PathBuf::from("/"))
+            Ok(end - start)
         } else {
-            path.clone()
-        };
-
-        let components = stripped_path.components();
+            Err(Error::IsADirectory)
+        }
+    }
 
-        if let TmpfsNodeData::Children(children) = &self.data {
-            match components.len().cmp(&1) {
-                core::cmp::Ordering::Equal => {
-                    let node = children
-                        .iter()
-                        .find(|(pt, _)| pt == components[0])
-                        .map(|(_, node)| node.clone());
-                    node.ok_or(Error::EntryNotFound)
-                }
-                core::cmp::Ordering::Greater => {
-                    let node = children
-                        .iter()
-                        .find(|(pt, _)| pt == components[0])
-                        .map(|(_, node)| node.clone());
-
-                    if let Some(node) = node {
-                        return node.lock().lookup(&stripped_path);
-                    } else {
-                        Err(Error::EntryNotFound)
-                    }
-                }
-                core::cmp::Ordering::Less => todo!(),
+    fn write(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
+        if let TmpfsNodeData::Data(data) = &mut self.data {
+            let end = offset
+                .checked_add(buf.len())
+                .filter(|&end| end <= MAX_FILE_SIZE)
+                .ok_or(Error::FileTooLarge)?;
+
+            // writing past the end of the file fills the gap with zeros
+            if end > data.len() {
+                resize(data, end)?;
             }
+
+            data[offset..end].copy_from_slice(buf);
+            self.touch();
+
+            Ok(buf.len())
         } else {
-            Err(Error::NotADirectory)
+            Err(Error::IsADirectory)
         }
     }
 
-    fn mknod(&self) {
-        todo!()
+    fn getattr(&self) -> Result<VAttr> {
+        let size = match &self.data {
+            TmpfsNodeData::Data(data) => data.len(),
+            TmpfsNodeData::Children(_) => 0,
+            TmpfsNodeData::Link(target) => target.len(),
+        };
+
+        Ok(VAttr {
+            v_type: self.v_type,
+            mode: self.mode,
+            uid: self.uid,
+            gid: self.gid,
+            size,
+            nlink: self.nlink,
+            ino: self.ino,
+            atime: self.atime,
+            mtime: self.mtime,
+            ctime: self.ctime,
+        })
     }
 
-    fn open(&self) {
-        println!("opening file on tmpfs");
+    fn setattr(&mut self, attr: &SetAttr) -> Result<()> {
+        if let Some(mode) = attr.mode {
+            self.mode = mode & S_IALLUGO;
+        }
+
+        if let Some(uid) = attr.uid {
+            self.uid = uid;
+        }
+
+        if let Some(gid) = attr.gid {
+            self.gid = gid;
+        }
+
+        if let Some(size) = attr.size {
+            let TmpfsNodeData::Data(data) = &mut self.data else {
+                return Err(Error::IsADirectory);
+            };
+
+            if size > MAX_FILE_SIZE {
+                return Err(Error::FileTooLarge);
+            }
+
+            if size != data.len() {
+                resize(data, size)?;
+                self.touch();
+            }
+        }
+
+        if let Some(atime) = attr.atime {
+            self.atime = atime;
+        }
+
+        if let Some(mtime) = attr.mtime {
+            self.mtime = mtime;
+        }
+
+        self.ctime = rtc::now();
+
+        Ok(())
     }
 
-    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+    fn getpages(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
         if let TmpfsNodeData::Data(data) = &self.data {
-            let max_read = if buf.len() > data.len() { data.len() } else { buf.len() };
+            let start = offset.min(data.len());
+            let end = offset.saturating_add(buf.len()).min(data.len());
 
-            buf[..max_read].copy_from_slice(&data[..max_read]);
+            buf[..end - start].copy_from_slice(&data[start..end]);
 
-            Ok(max_read)
+            Ok(end - start)
         } else {
             Err(Error::IsADirectory)
         }
     }
 
-    fn write(&mut self, buf: &mut [u8]) -> Result<usize> {
+    fn putpages(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
         if let TmpfsNodeData::Data(data) = &mut self.data {
-            data.resize(data.len() + buf.len(), 0);
+            let start = offset.min(data.len());
+            let end = offset.saturating_add(buf.len()).min(data.len());
 
-            let max_write = if buf.len() > data.len() { data.len() } else { buf.len() };
+            data[start..end].copy_from_slice(&buf[..end - start]);
+            self.touch();
 
-            data.reserve(max_write);
-
-            data[..max_write].copy_from_slice(&buf[..max_write]);
-
-            Ok(max_write)
+            Ok(end - start)
         } else {
             Err(Error::IsADirectory)
         }
     }
 
-    fn readdir(&self) {
-        todo!()
+    fn readdir(&self, cookie: usize) -> Result<Option<DirEntry>> {
+        // the entry at `cookie` may have been removed in the meantime, the listing continues with the next one
+        let entry = self
+            .children()?
+            .iter()
+            .find(|entry| entry.cookie >= cookie)
+            .map(|entry| DirEntry {
+                name: entry.name.clone(),
+                ino: entry.ino,
+                v_type: entry.node.lock().v_type(),
+                next_cookie: entry.cookie + 1,
+            });
+
+        Ok(entry)
     }
 
-    fn readlink(&self) {
-        todo!()
+    fn readlink(&self) -> Result<String> {
+        match &self.data {
+            TmpfsNodeData::Link(target) => Ok(target.clone()),
+            _ => Err(Error::InvalidArgument),
+        }
     }
 
-    fn reclaim(&self) {
-        todo!()
+    fn remove(&mut self, name: &str) -> Result<()> {
+        let index = self.position_of(name)?;
+
+        if self.children()?[index].node.lock().v_type() == VType::Directory {
+            return Err(Error::IsADirectory);
+        }
+
+        self.remove_child(index)?;
+
+        Ok(())
     }
 
-    fn remove(&self) {
-        todo!()
+    fn link(&mut self, name: &str, node: &Arc<Spinlock<VNode>>) -> Result<()> {
+        if self.children()?.iter().any(|entry| entry.name == name) {
+            return Err(Error::EntryExists);
+        }
+
+        let ino = {
+            let node = node.lock();
+
+            if node.v_type() == VType::Directory {
+                return Err(Error::NotPermitted);
+            }
+
+            node.downcast_data(|node: &mut TmpfsNode| {
+                node.nlink += 1;
+                node.ctime = rtc::now();
+                node.ino
+            })
+            .ok_or(Error::CrossDevice)?
+        };
+
+        self.push_child(name, ino, node.clone())?;
+
+        self.touch();
+
+        Ok(())
     }
 
-    fn rename(&self) {
-        todo!()
+    fn rename(&mut self, old_name: &str, new_dir: Option<&VNode>, new_name: &str) -> Result<()> {
+        let source = self.children()?[self.position_of(old_name)?].node.clone();
+
+        let Some(new_dir) = new_dir else {
+            if old_name == new_name {
+                return Ok(());
+            }
+
+            // renaming a file to another link of itself does nothing
+            if let Some(target) = self.children()?.iter().find(|entry| entry.name == new_name)
+                && Arc::ptr_eq(&target.node, &source)
+            {
+                return Ok(());
+            }
+
+            self.replace_target(new_name, &source)?;
+
+            let index = self.position_of(old_name)?;
+            self.children_mut()?[index].name = new_name.to_string();
+            self.touch();
+
+            return Ok(());
+        };
+
+        new_dir
+            .downcast_data(|new_dir: &mut TmpfsNode| {
+                if let Some(target) = new_dir.children()?.iter().find(|entry| entry.name == new_name)
+                    && Arc::ptr_eq(&target.node, &source)
+                {
+                    return Ok(());
+                }
+
+                new_dir.replace_target(new_name, &source)?;
+
+                let index = self.position_of(old_name)?;
+                let entry = self.children_mut()?.remove(index);
+
+                // a moved directory takes the link of its `..` entry with it
+                if source.lock().v_type() == VType::Directory {
+                    self.nlink -= 1;
+                    new_dir.nlink += 1;
+
+                    source.lock().downcast_data(|source: &mut TmpfsNode| {
+                        source.parent = new_dir.this.clone();
+                    });
+                }
+
+                new_dir.push_child(new_name, entry.ino, entry.node)?;
+
+                self.touch();
+                new_dir.touch();
+
+                Ok(())
+            })
+            .ok_or(Error::CrossDevice)?
     }
 
-    fn mkdir(&self) {
-        todo!()
+    fn mkdir(&mut self, name: &str, mode: u32, mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>> {
+        self.insert_child(name, VType::Directory, mode, mount)
     }
 
-    fn rmdir(&self) {
-        todo!()
+    fn rmdir(&mut self, name: &str) -> Result<()> {
+        let index = self.position_of(name)?;
+
+        {
+            let node = self.children()?[index].node.lock();
+
+            if node.v_type() != VType::Directory {
+                return Err(Error::NotADirectory);
+            }
+
+            if node.readdir(0)?.is_some() {
+                return Err(Error::NotEmpty);
+            }
+        }
+
+        self.remove_child(index)?;
+
+        Ok(())
     }
 
-    fn symlink(&self) {
-        todo!()
+    fn symlink(&mut self, name: &str, target: &str, mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>> {
+        let mut tmpfs_node = TmpfsNode::new(VType::SymbolicLink, 0o777);
+        tmpfs_node.data = TmpfsNodeData::Link(target.to_string());
+
+        self.insert_node(name, tmpfs_node, mount)
     }
 }
+
+/// Resizes the data of a file, failing with [`Error::NoSpace`] instead of aborting if the heap is exhausted
+fn resize(data: &mut Vec<u8>, size: usize) -> Result<()> {
+    data.try_reserve_exact(size.saturating_sub(data.len()))
+        .map_err(|_| Error::NoSpace)?;
+    data.resize(size, 0);
+
+    Ok(())
+}
@@ -1,25 +1,40 @@
 use alloc::{
+    collections::VecDeque,
     string::{String, ToString},
-    sync::Arc,
+    sync::{Arc, Weak},
     vec,
     vec::Vec,
 };
 use libxernel::boot::InitAtBoot;
 use libxernel::sync::Spinlock;
+use libxernel::syscall::{MountFlags, OpenFlags};
+
+use crate::drivers::block::BlockDevice;
 
 use super::{
+    devfs,
+    ext2::Ext2,
+    fat32::Fat32,
     mount::{Mount, VfsOps},
     pathbuf::PathBuf,
     tmpfs::Tmpfs,
-    vnode::VNode,
+    vnode::{SetAttr, VAttr, VNode, VType},
     {Error, Result},
 };
 
+/// Maximum number of symbolic links which are followed while resolving a single path
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Creates a new instance of a file system, which is then mounted
+///
+/// Disk file systems get the block device they are mounted from, virtual file systems get `None`.
+pub type FsConstructor = fn(Option<Arc<dyn BlockDevice>>) -> Result<Arc<Spinlock<dyn VfsOps>>>;
+
 pub static VFS: Spinlock<Vfs> = Spinlock::new(Vfs::new());
 
 pub struct Vfs {
     mount_point_list: Vec<(PathBuf, Arc<Spinlock<Mount>>)>,
-    drivers: Vec<(String, Arc<Spinlock<dyn VfsOps>>)>,
+    drivers: Vec<(String, FsConstructor)>,
     free_vnodes: Vec<Arc<VNode>>,
     root: InitAtBoot<Arc<Spinlock<VNode>>>,
 }
@@ -49,74 +64,306 @@ impl Vfs {
             .cloned()
     }
 
-    pub fn register_filesystem(&mut self, name: String, operations: Arc<Spinlock<dyn VfsOps>>) {
-        self.drivers.push((name, operations));
+    /// Registers a file system driver, `constructor` creates a new instance of the file system for each mount
+    pub fn register_filesystem(&mut self, name: String, constructor: FsConstructor) {
+        self.drivers.push((name, constructor));
+    }
+
+    /// Mounts the file system `name_of_fs` as the root file system
+    fn mount_root(&mut self, name_of_fs: &str) -> Result<()> {
+        let mount = self.new_mount(name_of_fs, None, None, "/".to_string(), MountFlags::empty())?;
+
+        self.root = InitAtBoot::Initialized(mount.lock().vfs_root()?);
+
+        Ok(())
     }
 
-    pub fn vn_mount(&mut self, name_of_fs: &str, where_to_mount: &str) -> Result<()> {
-        let driver = self
+    /// Mounts a new instance of the file system `name_of_fs` on the directory `where_to_mount`
+    ///
+    /// `source` is the path of the block device a disk file system is read from.
+    pub fn vn_mount(
+        &mut self,
+        name_of_fs: &str,
+        dir: &Arc<Spinlock<VNode>>,
+        where_to_mount: &str,
+        source: Option<&str>,
+        flags: MountFlags,
+    ) -> Result<()> {
+        let device = source
+            .map(|source| {
+                let node = self.namei(dir, source, true)?;
+                let node = node.lock();
+
+                devfs::block_device(&node).ok_or(Error::NotABlockDevice)
+            })
+            .transpose()?;
+
+        // get vnode to mount on
+        let node = self.namei(dir, where_to_mount, true)?;
+
+        if node.lock().v_type() != VType::Directory {
+            return Err(Error::NotADirectory);
+        }
+
+        let path = self.vn_getcwd(&node)?;
+
+        self.new_mount(name_of_fs, device, Some(node), path, flags)?;
+
+        Ok(())
+    }
+
+    fn new_mount(
+        &mut self,
+        name_of_fs: &str,
+        device: Option<Arc<dyn BlockDevice>>,
+        node_covered: Option<Arc<Spinlock<VNode>>>,
+        path: String,
+        flags: MountFlags,
+    ) -> Result<Arc<Spinlock<Mount>>> {
+        let constructor = self
             .drivers
             .iter()
             .find(|(name, _)| name == name_of_fs)
-            .map(|(_, driver)| driver)
+            .map(|(_, constructor)| constructor)
             .ok_or(Error::FileSystemNotFound)?;
 
-        let node_covered = if where_to_mount == "/" {
-            None
-        } else {
-            // get vnode to mount on
-            if let Ok(node) = self.lookuppn(where_to_mount.to_string()) {
-                Some(node)
-            } else {
-                return Err(Error::EntryNotFound);
-            }
-        };
+        // two instances of a disk file system would overwrite each other's changes
+        if device.as_ref().is_some_and(|device| self.is_device_mounted(device)) {
+            return Err(Error::Busy);
+        }
+
+        let driver = constructor(device.clone())?;
+        let root_node = driver.lock().vfs_root()?;
 
-        let mount = Arc::new(Spinlock::new(Mount::new(driver.clone(), node_covered)));
+        // file systems which only exist once, like devfs, can't be mounted twice at the same time
+        if root_node.lock().vfsp.upgrade().is_some() {
+            return Err(Error::Busy);
+        }
 
-        let root_node = mount.lock().vfs_root().expect("root node not found");
+        let mount = Arc::new(Spinlock::new(Mount::new(driver, node_covered.clone(), device, flags)));
+
+        if let Some(node) = node_covered {
+            node.lock().set_mounted_here(Some(Arc::downgrade(&mount)));
+        }
 
         root_node.lock().vfsp = Arc::downgrade(&mount);
 
-        mount.lock().vfs_mount(where_to_mount.to_string());
+        mount.lock().vfs_mount(path.clone());
 
         mount.lock().vfs_start();
 
-        self.mount_point_list.push((PathBuf::from(where_to_mount), mount));
+        self.mount_point_list.push((PathBuf::from(path), mount.clone()));
+
+        Ok(mount)
+    }
+
+    /// Unmounts the file system mounted on `where_to_unmount`
+    pub fn vn_unmount(&mut self, dir: &Arc<Spinlock<VNode>>, where_to_unmount: &str) -> Result<()> {
+        let root = self.namei(dir, where_to_unmount, true)?;
+
+        if Arc::ptr_eq(&root, &self.root_node()) {
+            return Err(Error::Busy);
+        }
+
+        let mount = root.lock().vfsp.upgrade().ok_or(Error::InvalidArgument)?;
+
+        // only the root of a mounted file system can be unmounted
+        if !Arc::ptr_eq(&mount.lock().vfs_root()?, &root) {
+            return Err(Error::InvalidArgument);
+        }
+
+        // the file system finds out whether it is busy by counting the references to its vnodes
+        drop(root);
+
+        mount.lock().vfs_unmount()?;
+
+        if let Some(node) = mount.lock().vnode_covered() {
+            node.lock().set_mounted_here(None);
+        }
+
+        self.mount_point_list.retain(|(_, other)| !Arc::ptr_eq(other, &mount));
 
         Ok(())
     }
 
+    /// Returns whether a file system is mounted from `device`
+    fn is_device_mounted(&self, device: &Arc<dyn BlockDevice>) -> bool {
+        self.mount_point_list
+            .iter()
+            .any(|(_, mount)| mount.lock().is_on_device(device))
+    }
+
     /// Lookup path name
     pub fn lookuppn(&self, path: String) -> Result<Arc<Spinlock<VNode>>> {
-        let path = PathBuf::from(path);
+        self.namei(&self.root_node(), &path, true)
+    }
 
-        let mnt_point = self.get_mount_point(&path)?;
+    /// Resolves `path` component by component
+    ///
+    /// Relative paths start at the directory `dir`, absolute ones at the root. Symbolic links are followed, the last
+    /// component only if `follow_last` is set.
+    pub fn namei(&self, dir: &Arc<Spinlock<VNode>>, path: &str, follow_last: bool) -> Result<Arc<Spinlock<VNode>>> {
+        if path.is_empty() {
+            return Err(Error::EntryNotFound);
+        }
 
-        let mnt = self
-            .mount_point_list
-            .iter()
-            .find(|(pt, _)| pt == mnt_point)
-            .map(|(_, mnt)| mnt)
-            .ok_or(Error::MountPointNotFound)?;
+        let mut current = if path.starts_with('/') {
+            self.root_node()
+        } else {
+            dir.clone()
+        };
+
+        let mut components = path_components(path);
+        let must_be_directory = path.ends_with('/');
+        let mut hops = 0;
+
+        while let Some(name) = components.pop_front() {
+            if current.lock().v_type() != VType::Directory {
+                return Err(Error::NotADirectory);
+            }
+
+            match name.as_str() {
+                "." => continue,
+                ".." => {
+                    current = self.parent_of(&current)?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let node = current.lock().lookup(&name)?;
+            let node = Self::cross_mount_points(node)?;
+
+            let is_last = components.is_empty();
 
-        mnt.lock().vfs_lookup(&path.strip_prefix(mnt_point))
+            if node.lock().v_type() == VType::SymbolicLink && (!is_last || follow_last || must_be_directory) {
+                hops += 1;
+
+                if hops > MAX_SYMLINK_HOPS {
+                    return Err(Error::TooManySymlinks);
+                }
+
+                let target = node.lock().readlink()?;
+
+                if target.is_empty() {
+                    return Err(Error::EntryNotFound);
+                }
+
+                // absolute targets are resolved from the root, relative ones from the directory of the link
+                if target.starts_with('/') {
+                    current = self.root_node();
+                }
+
+                for component in path_components(&target).into_iter().rev() {
+                    components.push_front(component);
+                }
+
+                continue;
+            }
+
+            current = node;
+        }
+
+        if must_be_directory && current.lock().v_type() != VType::Directory {
+            return Err(Error::NotADirectory);
+        }
+
+        Ok(current)
     }
 
-    fn get_mount_point(&self, path: &PathBuf) -> Result<&PathBuf> {
-        let mnt_point = self
-            .mount_point_list
-            .iter()
-            .filter(|(pt, _)| path.starts_with(pt))
-            .max_by_key(|(pt, _)| pt.len())
-            .map(|(pt, _)| pt)
-            .ok_or(Error::MountPointNotFound)?;
+    /// Returns the parent of the directory `dir`, the root is its own parent
+    ///
+    /// The parent of the root of a mounted file system is the parent of the directory it is mounted on.
+    fn parent_of(&self, dir: &Arc<Spinlock<VNode>>) -> Result<Arc<Spinlock<VNode>>> {
+        let mut dir = dir.clone();
 
-        Ok(mnt_point)
+        loop {
+            if Arc::ptr_eq(&dir, &self.root_node()) {
+                return Ok(dir);
+            }
+
+            let mount = dir.lock().vfsp.upgrade();
+
+            let covered = match mount {
+                Some(mount) if Arc::ptr_eq(&mount.lock().vfs_root()?, &dir) => mount.lock().vnode_covered(),
+                _ => return dir.lock().lookup(".."),
+            };
+
+            match covered {
+                Some(covered) => dir = covered,
+                None => return Ok(dir),
+            }
+        }
     }
 
-    pub fn vn_open(&self, path: String, _mode: u64) -> Result<Arc<Spinlock<VNode>>> {
-        let node = self.lookuppn(path)?;
+    /// Returns the root of the file system mounted on `node`, or `node` itself if nothing is mounted on it
+    fn cross_mount_points(node: Arc<Spinlock<VNode>>) -> Result<Arc<Spinlock<VNode>>> {
+        let mut node = node;
+
+        loop {
+            let mount = node.lock().mounted_here();
+
+            match mount {
+                Some(mount) => node = mount.lock().vfs_root()?,
+                None => return Ok(node),
+            }
+        }
+    }
+
+    /// Opens the file at `path`, with `O_CREAT` a missing file is created with the permission bits `mode`
+    pub fn vn_open(
+        &self,
+        dir: &Arc<Spinlock<VNode>>,
+        path: String,
+        flags: OpenFlags,
+        mode: u32,
+    ) -> Result<Arc<Spinlock<VNode>>> {
+        let node = if flags.contains(OpenFlags::CREATE) {
+            let exclusive = flags.contains(OpenFlags::EXCLUSIVE);
+
+            // an exclusive create fails for any existing entry, even a symbolic link
+            match self.namei(dir, &path, !exclusive) {
+                Ok(_) if exclusive => return Err(Error::EntryExists),
+                Ok(node) => node,
+                Err(Error::EntryNotFound) => self.vn_create(dir, path, VType::Regular, mode)?,
+                Err(err) => return Err(err),
+            }
+        } else {
+            self.namei(dir, &path, true)?
+        };
+
+        let v_type = node.lock().v_type();
+
+        if flags.contains(OpenFlags::DIRECTORY) && v_type != VType::Directory {
+            return Err(Error::NotADirectory);
+        }
+
+        if v_type == VType::Directory && (flags.is_writable() || flags.contains(OpenFlags::TRUNCATE)) {
+            return Err(Error::IsADirectory);
+        }
+
+        // the file system mounted from a block device caches its blocks, raw writes would bypass that cache
+        if v_type == VType::BlockDevice && flags.is_writable() {
+            let device = devfs::block_device(&node.lock());
+
+            if device.is_some_and(|device| self.is_device_mounted(&device)) {
+                return Err(Error::Busy);
+            }
+        }
+
+        // devices stay writable on a read-only file system
+        if v_type == VType::Regular && (flags.is_writable() || flags.contains(OpenFlags::TRUNCATE)) {
+            Self::check_writable(&node)?;
+        }
+
+        if flags.contains(OpenFlags::TRUNCATE) && flags.is_writable() && v_type == VType::Regular {
+            let attr = SetAttr {
+                size: Some(0),
+                ..Default::default()
+            };
+
+            node.lock().setattr(&attr)?;
+        }
 
         node.lock().open();
 
@@ -126,49 +373,309 @@ impl Vfs {
     pub fn vn_close(&mut self) {}
 
     // TODO: When available, replace node with filedescriptor
-    pub fn vn_read(&self, node: Arc<Spinlock<VNode>>, buf: &mut [u8]) -> Result<usize> {
-        node.lock().read(buf)
+    pub fn vn_read(&self, node: Arc<Spinlock<VNode>>, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        node.lock().read(offset, buf)
+    }
+
+    pub fn vn_write(&self, node: Arc<Spinlock<VNode>>, offset: usize, buf: &[u8]) -> Result<usize> {
+        node.lock().write(offset, buf)
+    }
+
+    /// Looks up the directory which contains `path` and returns it together with the name of the last component
+    fn lookup_parent(&self, dir: &Arc<Spinlock<VNode>>, path: String) -> Result<(Arc<Spinlock<VNode>>, String)> {
+        let (parent, name) = split_path(path)?;
+
+        let parent = self.namei(dir, &parent, true)?;
+
+        Ok((parent, name))
+    }
+
+    pub fn vn_create(
+        &self,
+        dir: &Arc<Spinlock<VNode>>,
+        path: String,
+        v_type: VType,
+        mode: u32,
+    ) -> Result<Arc<Spinlock<VNode>>> {
+        let (parent, name) = self.lookup_parent(dir, path)?;
+
+        Self::check_writable(&parent)?;
+
+        parent.lock().create(name, v_type, mode)
+    }
+
+    pub fn vn_remove(&self, dir: &Arc<Spinlock<VNode>>, path: String) -> Result<()> {
+        let (parent, name) = self.lookup_parent(dir, path)?;
+
+        Self::check_writable(&parent)?;
+
+        parent.lock().remove(&name)
+    }
+
+    pub fn vn_mkdir(&self, dir: &Arc<Spinlock<VNode>>, path: String, mode: u32) -> Result<Arc<Spinlock<VNode>>> {
+        let (parent, name) = self.lookup_parent(dir, path)?;
+
+        Self::check_writable(&parent)?;
+
+        parent.lock().mkdir(&name, mode)
+    }
+
+    pub fn vn_rmdir(&self, dir: &Arc<Spinlock<VNode>>, path: String) -> Result<()> {
+        if is_root(&path) {
+            return Err(Error::Busy);
+        }
+
+        let (parent, name) = self.lookup_parent(dir, path)?;
+
+        Self::check_writable(&parent)?;
+
+        // mount points can't be removed while something is mounted on them
+        if Self::is_covered(&parent, &name) {
+            return Err(Error::Busy);
+        }
+
+        parent.lock().rmdir(&name)
+    }
+
+    pub fn vn_link(&self, dir: &Arc<Spinlock<VNode>>, old_path: String, new_path: String) -> Result<()> {
+        let node = self.namei(dir, &old_path, false)?;
+
+        if node.lock().v_type() == VType::Directory {
+            return Err(Error::NotPermitted);
+        }
+
+        let (parent, name) = self.lookup_parent(dir, new_path)?;
+
+        Self::check_same_mount(&node, &parent)?;
+        Self::check_writable(&parent)?;
+
+        parent.lock().link(&name, &node)
+    }
+
+    pub fn vn_rename(&self, dir: &Arc<Spinlock<VNode>>, old_path: String, new_path: String) -> Result<()> {
+        if is_root(&old_path) || is_root(&new_path) {
+            return Err(Error::Busy);
+        }
+
+        let (old_dir, old_name) = self.lookup_parent(dir, old_path)?;
+        let (new_dir, new_name) = self.lookup_parent(dir, new_path)?;
+
+        Self::check_same_mount(&old_dir, &new_dir)?;
+        Self::check_writable(&old_dir)?;
+
+        if Self::is_covered(&old_dir, &old_name) || Self::is_covered(&new_dir, &new_name) {
+            return Err(Error::Busy);
+        }
+
+        // a directory can't be moved into one of its own subdirectories
+        let source = old_dir.lock().lookup(&old_name)?;
+
+        if self.is_ancestor(&source, &new_dir)? {
+            return Err(Error::InvalidArgument);
+        }
+
+        if Arc::ptr_eq(&old_dir, &new_dir) {
+            return old_dir.lock().rename(&old_name, None, &new_name);
+        }
+
+        // the directories are always locked in the same order, so that two renames in opposite directions can't
+        // deadlock
+        let (old_dir, new_dir) = if Arc::as_ptr(&old_dir) < Arc::as_ptr(&new_dir) {
+            let old_dir = old_dir.lock();
+            (old_dir, new_dir.lock())
+        } else {
+            let new_dir = new_dir.lock();
+            (old_dir.lock(), new_dir)
+        };
+
+        old_dir.rename(&old_name, Some(&new_dir), &new_name)
+    }
+
+    pub fn vn_symlink(&self, dir: &Arc<Spinlock<VNode>>, target: String, link_path: String) -> Result<()> {
+        let (parent, name) = self.lookup_parent(dir, link_path)?;
+
+        Self::check_writable(&parent)?;
+
+        parent.lock().symlink(&name, &target)?;
+
+        Ok(())
+    }
+
+    pub fn vn_readlink(&self, dir: &Arc<Spinlock<VNode>>, path: String) -> Result<String> {
+        let node = self.namei(dir, &path, false)?;
+
+        if node.lock().v_type() != VType::SymbolicLink {
+            return Err(Error::InvalidArgument);
+        }
+
+        node.lock().readlink()
+    }
+
+    pub fn vn_getattr(&self, dir: &Arc<Spinlock<VNode>>, path: String) -> Result<VAttr> {
+        self.namei(dir, &path, true)?.lock().getattr()
+    }
+
+    pub fn vn_setattr(&self, dir: &Arc<Spinlock<VNode>>, path: String, attr: &SetAttr) -> Result<()> {
+        let node = self.namei(dir, &path, true)?;
+
+        Self::check_writable(&node)?;
+
+        node.lock().setattr(attr)
     }
 
-    pub fn vn_write(&self, node: Arc<Spinlock<VNode>>, buf: &mut [u8]) -> Result<usize> {
-        node.lock().write(buf)
+    /// Returns the absolute path of the directory `dir`
+    ///
+    /// Each directory is searched for in its parent, so this fails for a directory which was removed.
+    pub fn vn_getcwd(&self, dir: &Arc<Spinlock<VNode>>) -> Result<String> {
+        let mut names = Vec::new();
+        let mut node = dir.clone();
+
+        loop {
+            let parent = self.parent_of(&node)?;
+
+            if Arc::ptr_eq(&parent, &node) {
+                break;
+            }
+
+            names.push(Self::name_in(&parent, &node)?);
+            node = parent;
+        }
+
+        if names.is_empty() {
+            return Ok("/".to_string());
+        }
+
+        Ok(names.iter().rev().fold(String::new(), |path, name| path + "/" + name))
     }
 
-    pub fn vn_create(&mut self) {}
+    /// Returns the name of the entry of `dir` which refers to `node`
+    fn name_in(dir: &Arc<Spinlock<VNode>>, node: &Arc<Spinlock<VNode>>) -> Result<String> {
+        let mut cookie = 0;
+
+        loop {
+            let entry = dir.lock().readdir(cookie)?;
+            let entry = entry.ok_or(Error::EntryNotFound)?;
 
-    pub fn vn_remove(&mut self) {}
+            let child = dir.lock().lookup(&entry.name)?;
 
-    pub fn vn_link(&mut self) {}
+            if Arc::ptr_eq(&Self::cross_mount_points(child)?, node) {
+                return Ok(entry.name);
+            }
+
+            cookie = entry.next_cookie;
+        }
+    }
+
+    /// Returns whether `node` is `dir` or one of the directories above it
+    fn is_ancestor(&self, node: &Arc<Spinlock<VNode>>, dir: &Arc<Spinlock<VNode>>) -> Result<bool> {
+        let mut dir = dir.clone();
+
+        loop {
+            if Arc::ptr_eq(&dir, node) {
+                return Ok(true);
+            }
+
+            let parent = self.parent_of(&dir)?;
+
+            if Arc::ptr_eq(&parent, &dir) {
+                return Ok(false);
+            }
+
+            dir = parent;
+        }
+    }
+
+    /// Returns whether a file system is mounted on the entry `name` of `dir`
+    fn is_covered(dir: &Arc<Spinlock<VNode>>, name: &str) -> bool {
+        let node = dir.lock().lookup(name);
+
+        node.is_ok_and(|node| node.lock().mounted_here().is_some())
+    }
 
-    pub fn vn_rename(&mut self) {}
+    /// Changes are refused on file systems which are mounted read-only
+    fn check_writable(node: &Arc<Spinlock<VNode>>) -> Result<()> {
+        if node.lock().mount_flags().contains(MountFlags::READ_ONLY) {
+            Err(Error::ReadOnlyFileSystem)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Links and renames only work within a single file system
+    fn check_same_mount(a: &Arc<Spinlock<VNode>>, b: &Arc<Spinlock<VNode>>) -> Result<()> {
+        if Weak::ptr_eq(&a.lock().vfsp, &b.lock().vfsp) {
+            Ok(())
+        } else {
+            Err(Error::CrossDevice)
+        }
+    }
+}
+
+/// Splits `path` into the path of the parent directory and the name of the last component
+fn split_path(path: String) -> Result<(String, String)> {
+    let (parent, name) = PathBuf::from(path).split_file_name().ok_or(Error::EntryExists)?;
+
+    if name == "." || name == ".." {
+        return Err(Error::InvalidArgument);
+    }
+
+    Ok((parent.into_string(), name))
+}
+
+fn is_root(path: &str) -> bool {
+    path.trim_end_matches('/').is_empty()
+}
+
+fn path_components(path: &str) -> VecDeque<String> {
+    path.split('/')
+        .filter(|component| !component.is_empty())
+        .map(|component| component.to_string())
+        .collect()
 }
 
 pub fn init() {
     let mut vfs = VFS.lock();
 
-    let tmpfs = Arc::new(Spinlock::new(Tmpfs::new()));
+    vfs.register_filesystem(String::from("tmpfs"), |_| {
+        let mut tmpfs = Tmpfs::new();
+        tmpfs.vfs_init();
+
+        Ok(Arc::new(Spinlock::new(tmpfs)))
+    });
 
-    tmpfs.lock().vfs_init();
+    vfs.register_filesystem(String::from("fat32"), |device| {
+        let mut fat32 = Fat32::new(device.ok_or(Error::InvalidArgument)?)?;
+        fat32.vfs_init();
 
-    vfs.register_filesystem(String::from("tmpfs"), tmpfs.clone());
+        Ok(Arc::new(Spinlock::new(fat32)))
+    });
 
-    vfs.root = InitAtBoot::Initialized(tmpfs.lock().vfs_root().unwrap());
+    vfs.register_filesystem(String::from("ext2"), |device| {
+        let mut ext2 = Ext2::new(device.ok_or(Error::InvalidArgument)?)?;
+        ext2.vfs_init();
 
-    vfs.vn_mount("tmpfs", "/").expect("Mounting tmpfs on / failed");
+        Ok(Arc::new(Spinlock::new(ext2)))
+    });
+
+    vfs.mount_root("tmpfs").expect("Mounting tmpfs on / failed");
 }
 
 pub fn test() {
-    let t = VFS.lock().vn_open("/test.txt".to_string(), 0).unwrap();
+    let root = VFS.lock().root_node();
+    let t = VFS
+        .lock()
+        .vn_open(&root, "/test.txt".to_string(), OpenFlags::READ_WRITE, 0)
+        .unwrap();
 
-    let mut write_buf: Vec<u8> = vec![5; 10];
+    let write_buf: Vec<u8> = vec![5; 10];
 
     VFS.lock()
-        .vn_write(t.clone(), &mut write_buf)
+        .vn_write(t.clone(), 0, &write_buf)
         .expect("write to file failed");
 
     let mut read_buf: Vec<u8> = vec![0; 5];
 
-    VFS.lock().vn_read(t.clone(), &mut read_buf).expect("read failed");
+    VFS.lock().vn_read(t.clone(), 0, &mut read_buf).expect("read failed");
 
     println!(
         "name of fs where node is mounted: {}",
@@ -177,3 +684,29 @@ pub fn test() {
     println!("{:?}", write_buf);
     println!("{:?}", read_buf);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(path: &str) -> Result<(String, String)> {
+        split_path(path.to_string())
+    }
+
+    #[test]
+    fn split_path_separates_the_last_component() {
+        assert_eq!(split("/a/b").unwrap(), ("/a".to_string(), "b".to_string()));
+        assert_eq!(split("/a/b//").unwrap(), ("/a".to_string(), "b".to_string()));
+        assert_eq!(split("/a").unwrap(), ("/".to_string(), "a".to_string()));
+        assert_eq!(split("a/b").unwrap(), ("a".to_string(), "b".to_string()));
+        assert_eq!(split("a").unwrap(), (".".to_string(), "a".to_string()));
+    }
+
+    #[test]
+    fn split_path_rejects_paths_without_a_name() {
+        assert!(matches!(split("/"), Err(Error::EntryExists)));
+        assert!(matches!(split(""), Err(Error::EntryExists)));
+        assert!(matches!(split("/a/."), Err(Error::InvalidArgument)));
+        assert!(matches!(split("a/.."), Err(Error::InvalidArgument)));
+    }
+}
@@ -1,63 +1,509 @@
 use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use libxernel::sync::Spinlock;
+use libxernel::syscall::{
+    AT_FDCWD, DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG, DT_SOCK, DT_UNKNOWN, F_DUPFD, F_DUPFD_CLOEXEC, F_GETFD,
+    F_SETFD, FD_CLOEXEC, MountFlags, OpenFlags, ProtectionFlags, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG,
+    S_IFSOCK, SEEK_CUR, SEEK_END, SEEK_SET, Stat, SyscallError, Timespec, UTIME_NOW, UTIME_OMIT,
+};
 
-use crate::{cpu::current_process, syscall::Result};
+use crate::{
+    cpu::current_process,
+    syscall::{
+        Result,
+        user_ptr::{CHUNK_SIZE, UserPtr, UserSlice},
+    },
+    utils::rtc,
+};
 
-use super::{file::File, vfs::VFS};
+use super::{
+    file::File,
+    vfs::VFS,
+    vnode::{DirEntry, SetAttr, VAttr, VNode, VType},
+};
 
-pub fn sys_open(path: String, mode: u64) -> Result<isize> {
-    let vfs = VFS.lock();
+pub fn sys_open(path: String, flags: usize, mode: u32) -> Result<isize> {
+    sys_openat(AT_FDCWD, path, flags, mode)
+}
 
-    let node = vfs.vn_open(path, mode)?;
+/// Opens `path` relative to the directory `dirfd` refers to, or to the working directory for `AT_FDCWD`
+pub fn sys_openat(dirfd: isize, path: String, flags: usize, mode: u32) -> Result<isize> {
+    let flags = OpenFlags::from_bits(flags).ok_or(SyscallError::InvalidArgument)?;
 
-    let file_handle = File::new(node);
+    if flags.contains(OpenFlags::WRITE_ONLY | OpenFlags::READ_WRITE) {
+        return Err(SyscallError::InvalidArgument);
+    }
 
-    let process = current_process();
-    let mut process = process.lock();
+    let dir = start_dir(dirfd, &path)?;
+    let node = VFS.lock().vn_open(&dir, path, flags, mode)?;
 
-    let fd = process.append_fd(file_handle);
+    let file = Arc::new(Spinlock::new(File::new(node, flags)));
+
+    let fd = current_process()
+        .lock()
+        .fds
+        .insert(file, flags.contains(OpenFlags::CLOEXEC))?;
 
     Ok(fd as isize)
 }
 
+pub fn sys_chdir(path: String) -> Result<isize> {
+    let cwd = cwd();
+    let node = VFS.lock().namei(&cwd, &path, true)?;
+
+    if node.lock().v_type() != VType::Directory {
+        return Err(SyscallError::NotADirectory);
+    }
+
+    // the old working directory must not be dropped with the process locked
+    let old_cwd = core::mem::replace(&mut current_process().lock().cwd, node);
+    drop(old_cwd);
+
+    Ok(0)
+}
+
+/// Copies the absolute path of the working directory with a terminating NUL into `buf`
+///
+/// Returns the length of the path including the NUL.
+pub fn sys_getcwd(buf: UserSlice) -> Result<isize> {
+    let cwd = cwd();
+    let mut path = VFS.lock().vn_getcwd(&cwd)?.into_bytes();
+    path.push(0);
+
+    if path.len() > buf.len() {
+        return Err(SyscallError::OutOfRange);
+    }
+
+    buf.write(&path)?;
+
+    Ok(path.len() as isize)
+}
+
 pub fn sys_close(fd: usize) -> Result<isize> {
+    let descriptor = current_process().lock().fds.remove(fd)?;
+
+    // the file is closed when the last descriptor referring to it is dropped, which must not happen with the
+    // process locked
+    drop(descriptor);
+
+    Ok(0)
+}
+
+pub fn sys_dup(fd: usize) -> Result<isize> {
+    let process = current_process();
+    let mut process = process.lock();
+
+    let file = process.fds.get(fd)?.clone();
+    let new_fd = process.fds.insert(file, false)?;
+
+    Ok(new_fd as isize)
+}
+
+pub fn sys_dup2(fd: usize, new_fd: usize) -> Result<isize> {
+    let replaced = {
+        let process = current_process();
+        let mut process = process.lock();
+
+        let file = process.fds.get(fd)?.clone();
+
+        if fd == new_fd {
+            return Ok(new_fd as isize);
+        }
+
+        process.fds.replace(new_fd, file, false)?
+    };
+
+    drop(replaced);
+
+    Ok(new_fd as isize)
+}
+
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> Result<isize> {
     let process = current_process();
-    let process = process.lock();
+    let mut process = process.lock();
+
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            let file = process.fds.get(fd)?.clone();
+            let new_fd = process.fds.insert_from(arg, file, cmd == F_DUPFD_CLOEXEC)?;
 
-    let file_handle = process.get_filehandle_from_fd(fd);
+            Ok(new_fd as isize)
+        }
+        F_GETFD => {
+            let descriptor = process.fds.get_descriptor(fd)?;
 
-    let node = file_handle.get_node();
+            Ok(if descriptor.close_on_exec {
+                FD_CLOEXEC as isize
+            } else {
+                0
+            })
+        }
+        F_SETFD => {
+            process.fds.get_descriptor_mut(fd)?.close_on_exec = arg & FD_CLOEXEC != 0;
+
+            Ok(0)
+        }
+        _ => Err(SyscallError::InvalidArgument),
+    }
+}
 
-    node.lock().close();
+pub fn sys_read(fd: usize, buf: UserSlice) -> Result<isize> {
+    let file = current_process().lock().fds.get(fd)?.clone();
+
+    read_chunked(buf, |_, data| Ok(file.lock().read(data)?))
+}
+
+pub fn sys_write(fd: usize, buf: UserSlice) -> Result<isize> {
+    let file = current_process().lock().fds.get(fd)?.clone();
+
+    write_chunked(buf, |_, data| Ok(file.lock().write(data)?))
+}
+
+pub fn sys_pread(fd: usize, buf: UserSlice, offset: isize) -> Result<isize> {
+    let offset = usize::try_from(offset).map_err(|_| SyscallError::InvalidArgument)?;
+    let file = current_process().lock().fds.get(fd)?.clone();
+
+    read_chunked(buf, |done, data| {
+        let offset = offset.checked_add(done).ok_or(SyscallError::InvalidArgument)?;
+        Ok(file.lock().read_at(offset, data)?)
+    })
+}
+
+pub fn sys_pwrite(fd: usize, buf: UserSlice, offset: isize) -> Result<isize> {
+    let offset = usize::try_from(offset).map_err(|_| SyscallError::InvalidArgument)?;
+    let file = current_process().lock().fds.get(fd)?.clone();
+
+    write_chunked(buf, |done, data| {
+        let offset = offset.checked_add(done).ok_or(SyscallError::InvalidArgument)?;
+        Ok(file.lock().write_at(offset, data)?)
+    })
+}
+
+/// Fills `buf` one [`CHUNK_SIZE`] part at a time, `read` gets the offset into `buf` and returns the bytes it read
+///
+/// The transfer stops at the first short read. An error after some bytes were copied returns their count instead.
+fn read_chunked(buf: UserSlice, mut read: impl FnMut(usize, &mut [u8]) -> Result<usize>) -> Result<isize> {
+    buf.check(ProtectionFlags::WRITE)?;
+
+    let mut data = vec![0; buf.len().min(CHUNK_SIZE)];
+    let mut total = 0;
+
+    for chunk in buf.chunks() {
+        let data = &mut data[..chunk.len()];
+
+        let read = match read(total, data).and_then(|read| chunk.write(&data[..read]).map(|_| read)) {
+            Ok(read) => read,
+            Err(_) if total > 0 => break,
+            Err(error) => return Err(error),
+        };
+
+        total += read;
+
+        if read < chunk.len() {
+            break;
+        }
+    }
+
+    Ok(total as isize)
+}
+
+/// Counterpart of [`read_chunked`], `write` gets the offset into `buf` and returns the bytes it wrote
+fn write_chunked(buf: UserSlice, mut write: impl FnMut(usize, &[u8]) -> Result<usize>) -> Result<isize> {
+    buf.check(ProtectionFlags::READ)?;
+
+    let mut total = 0;
+
+    for chunk in buf.chunks() {
+        let written = match chunk.read().and_then(|data| write(total, &data)) {
+            Ok(written) => written,
+            Err(_) if total > 0 => break,
+            Err(error) => return Err(error),
+        };
+
+        total += written;
+
+        if written < chunk.len() {
+            break;
+        }
+    }
+
+    Ok(total as isize)
+}
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> Result<isize> {
+    let file = current_process().lock().fds.get(fd)?.clone();
+    let mut file = file.lock();
+
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => file.offset(),
+        SEEK_END => file.get_node().lock().getattr()?.size,
+        _ => return Err(SyscallError::InvalidArgument),
+    };
+
+    // the resulting offset must not be negative and has to fit into the return value
+    let new_offset = base
+        .checked_add_signed(offset)
+        .filter(|&offset| offset <= isize::MAX as usize)
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    file.set_offset(new_offset);
+
+    Ok(new_offset as isize)
+}
+
+pub fn sys_mkdir(path: String, mode: u32) -> Result<isize> {
+    let cwd = cwd();
+    VFS.lock().vn_mkdir(&cwd, path, mode)?;
 
     Ok(0)
 }
 
-pub fn sys_read(fd: usize, buf: &mut [u8]) -> Result<isize> {
-    let vfs = VFS.lock();
+pub fn sys_rmdir(path: String) -> Result<isize> {
+    let cwd = cwd();
+    VFS.lock().vn_rmdir(&cwd, path)?;
 
-    let process = current_process();
-    let process = process.lock();
+    Ok(0)
+}
 
-    let file_handle = process.get_filehandle_from_fd(fd);
+pub fn sys_unlink(path: String) -> Result<isize> {
+    let cwd = cwd();
+    VFS.lock().vn_remove(&cwd, path)?;
 
-    let node = file_handle.get_node();
+    Ok(0)
+}
 
-    let res = vfs.vn_read(node, buf)?;
+pub fn sys_rename(old_path: String, new_path: String) -> Result<isize> {
+    let cwd = cwd();
+    VFS.lock().vn_rename(&cwd, old_path, new_path)?;
 
-    Ok(res as isize)
+    Ok(0)
 }
 
-pub fn sys_write(fd: usize, buf: &mut [u8]) -> Result<isize> {
-    let vfs = VFS.lock();
+pub fn sys_link(old_path: String, new_path: String) -> Result<isize> {
+    let cwd = cwd();
+    VFS.lock().vn_link(&cwd, old_path, new_path)?;
 
-    let process = current_process();
-    let process = process.lock();
+    Ok(0)
+}
+
+pub fn sys_symlink(target: String, link_path: String) -> Result<isize> {
+    let cwd = cwd();
+    VFS.lock().vn_symlink(&cwd, target, link_path)?;
+
+    Ok(0)
+}
+
+/// Copies the target of the link into `buf`, the target is truncated if it doesn't fit and not NUL terminated
+pub fn sys_readlink(path: String, buf: UserSlice) -> Result<isize> {
+    let cwd = cwd();
+    let target = VFS.lock().vn_readlink(&cwd, path)?;
+
+    let len = target.len().min(buf.len());
+    buf.write(&target.as_bytes()[..len])?;
+
+    Ok(len as isize)
+}
+
+/// Mounts the file system `fs_name` on `target`, disk file systems are read from the block device `source`
+pub fn sys_mount(fs_name: String, target: String, flags: usize, source: Option<String>) -> Result<isize> {
+    let flags = MountFlags::from_bits(flags).ok_or(SyscallError::InvalidArgument)?;
+
+    let cwd = cwd();
+    VFS.lock().vn_mount(&fs_name, &cwd, &target, source.as_deref(), flags)?;
+
+    Ok(0)
+}
+
+pub fn sys_umount(target: String) -> Result<isize> {
+    let cwd = cwd();
+    VFS.lock().vn_unmount(&cwd, &target)?;
+
+    Ok(0)
+}
+
+fn cwd() -> Arc<Spinlock<VNode>> {
+    current_process().lock().cwd.clone()
+}
+
+/// Returns the directory a relative `path` passed to an *at syscall starts at
+fn start_dir(dirfd: isize, path: &str) -> Result<Arc<Spinlock<VNode>>> {
+    if dirfd == AT_FDCWD || path.starts_with('/') {
+        return Ok(cwd());
+    }
+
+    let fd = usize::try_from(dirfd).map_err(|_| SyscallError::BadFileDescriptor)?;
+    let node = current_process().lock().fds.get(fd)?.lock().get_node();
+
+    if node.lock().v_type() != VType::Directory {
+        return Err(SyscallError::NotADirectory);
+    }
+
+    Ok(node)
+}
+
+/// Reads directory entries in the format of `struct linux_dirent64`
+///
+/// The offset of the file is the cookie of the next entry, so following calls continue where this one stopped.
+pub fn sys_getdents(fd: usize, buf: UserSlice) -> Result<isize> {
+    let file = current_process().lock().fds.get(fd)?.clone();
+    let mut file = file.lock();
+    let node = file.get_node();
+
+    let mut data = Vec::new();
+    let mut cookie = file.offset();
+
+    while let Some(entry) = node.lock().readdir(cookie)? {
+        let record = dirent_record(&entry);
+
+        if data.len() + record.len() > buf.len() {
+            // not even a single entry fits into the buffer
+            if data.is_empty() {
+                return Err(SyscallError::InvalidArgument);
+            }
+
+            break;
+        }
+
+        data.extend_from_slice(&record);
+        cookie = entry.next_cookie;
+    }
 
-    let file_handle = process.get_filehandle_from_fd(fd);
+    buf.write(&data)?;
+    file.set_offset(cookie);
 
-    let node = file_handle.get_node();
+    Ok(data.len() as isize)
+}
+
+fn dirent_record(entry: &DirEntry) -> Vec<u8> {
+    // header consisting of d_ino, d_off, d_reclen and d_type, followed by the NUL terminated name
+    const HEADER_SIZE: usize = 19;
+
+    let record_len = (HEADER_SIZE + entry.name.len() + 1).next_multiple_of(8);
+
+    let mut record = Vec::with_capacity(record_len);
+    record.extend_from_slice(&entry.ino.to_ne_bytes());
+    record.extend_from_slice(&(entry.next_cookie as i64).to_ne_bytes());
+    record.extend_from_slice(&(record_len as u16).to_ne_bytes());
+    record.push(dirent_type(entry.v_type));
+    record.extend_from_slice(entry.name.as_bytes());
+    record.resize(record_len, 0);
+
+    record
+}
+
+fn dirent_type(v_type: VType) -> u8 {
+    match v_type {
+        VType::Regular => DT_REG,
+        VType::Directory => DT_DIR,
+        VType::BlockDevice => DT_BLK,
+        VType::CharacterDevice => DT_CHR,
+        VType::SymbolicLink => DT_LNK,
+        VType::Socket => DT_SOCK,
+        VType::Fifo => DT_FIFO,
+        VType::Non | VType::Bad => DT_UNKNOWN,
+    }
+}
+
+pub fn sys_stat(path: String, buf: UserPtr<Stat>) -> Result<isize> {
+    let cwd = cwd();
+    let attr = VFS.lock().vn_getattr(&cwd, path)?;
+
+    buf.write(&stat_from_attr(&attr))?;
+
+    Ok(0)
+}
+
+pub fn sys_fstat(fd: usize, buf: UserPtr<Stat>) -> Result<isize> {
+    let node = current_process().lock().fds.get(fd)?.lock().get_node();
+
+    let attr = node.lock().getattr()?;
+
+    buf.write(&stat_from_attr(&attr))?;
+
+    Ok(0)
+}
+
+pub fn sys_chmod(path: String, mode: u32) -> Result<isize> {
+    let attr = SetAttr {
+        mode: Some(mode),
+        ..Default::default()
+    };
+
+    let cwd = cwd();
+    VFS.lock().vn_setattr(&cwd, path, &attr)?;
+
+    Ok(0)
+}
+
+/// Sets the access and modification time of a file
+///
+/// `times` points to the new access and modification time, a null pointer sets both to the current time. A time
+/// with `tv_nsec` set to `UTIME_NOW` is replaced by the current time and one set to `UTIME_OMIT` is left unchanged.
+pub fn sys_utimens(path: String, times: UserPtr<[Timespec; 2]>) -> Result<isize> {
+    let now = rtc::now();
+
+    let (atime, mtime) = if times.is_null() {
+        (Some(now), Some(now))
+    } else {
+        let [atime, mtime] = times.read()?;
+
+        (utime(atime, now)?, utime(mtime, now)?)
+    };
 
-    let res = vfs.vn_write(node, buf)?;
+    let attr = SetAttr {
+        atime,
+        mtime,
+        ..Default::default()
+    };
+
+    let cwd = cwd();
+    VFS.lock().vn_setattr(&cwd, path, &attr)?;
+
+    Ok(0)
+}
+
+/// Resolves a time passed to utimens, `None` means the time is left unchanged
+fn utime(time: Timespec, now: Timespec) -> Result<Option<Timespec>> {
+    match time.tv_nsec {
+        UTIME_NOW => Ok(Some(now)),
+        UTIME_OMIT => Ok(None),
+        0..1_000_000_000 => Ok(Some(time)),
+        _ => Err(SyscallError::InvalidArgument),
+    }
+}
+
+fn stat_from_attr(attr: &VAttr) -> Stat {
+    const BLOCK_SIZE: i64 = 4096;
+
+    Stat {
+        st_ino: attr.ino,
+        st_nlink: attr.nlink as u64,
+        st_mode: mode_type(attr.v_type) | attr.mode,
+        st_uid: attr.uid,
+        st_gid: attr.gid,
+        st_size: attr.size as i64,
+        st_blksize: BLOCK_SIZE,
+        // st_blocks is counted in units of 512 bytes
+        st_blocks: attr.size.div_ceil(512) as i64,
+        st_atim: attr.atime,
+        st_mtim: attr.mtime,
+        st_ctim: attr.ctime,
+        ..Default::default()
+    }
+}
 
-    Ok(res as isize)
+fn mode_type(v_type: VType) -> u32 {
+    match v_type {
+        VType::Regular => S_IFREG,
+        VType::Directory => S_IFDIR,
+        VType::BlockDevice => S_IFBLK,
+        VType::CharacterDevice => S_IFCHR,
+        VType::SymbolicLink => S_IFLNK,
+        VType::Socket => S_IFSOCK,
+        VType::Fifo => S_IFIFO,
+        VType::Non | VType::Bad => 0,
+    }
 }
@@ -1,9 +1,18 @@
-use super::Result;
 use super::mount::Mount;
-use super::pathbuf::PathBuf;
+use super::{Error, Result};
+use crate::mem::page_cache;
 use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 use alloc::{sync::Arc, sync::Weak};
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, Ordering};
 use libxernel::sync::Spinlock;
+use libxernel::syscall::{MountFlags, Timespec};
+use x86_64::structures::paging::PhysFrame;
+
+/// Ids identify the pages of a vnode in the page cache, they are never reused
+static NEXT_VNODE_ID: AtomicU64 = AtomicU64::new(0);
 
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub enum VType {
@@ -18,6 +27,48 @@ pub enum VType {
     Bad,
 }
 
+/// Entry of a directory as returned by [`VNodeOperations::readdir`]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub v_type: VType,
+    /// Cookie of the entry which follows this one
+    pub next_cookie: usize,
+}
+
+/// Attributes of a file as returned by [`VNodeOperations::getattr`]
+#[derive(Clone, Copy)]
+pub struct VAttr {
+    pub v_type: VType,
+    /// Permission bits of the file
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Size of the file in bytes
+    pub size: usize,
+    /// Number of hard links to the file
+    pub nlink: usize,
+    pub ino: u64,
+    /// Time of the last access
+    pub atime: Timespec,
+    /// Time of the last change of the contents
+    pub mtime: Timespec,
+    /// Time of the last change of the contents or the attributes
+    pub ctime: Timespec,
+}
+
+/// Attributes to change with [`VNodeOperations::setattr`], fields which are `None` are left untouched
+#[derive(Clone, Copy, Default)]
+pub struct SetAttr {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// New size of the file, which is truncated or extended with zeros
+    pub size: Option<usize>,
+    pub atime: Option<Timespec>,
+    pub mtime: Option<Timespec>,
+}
+
 // Each Vnode gets a field file system specific handler which is a struct given by the file system driver which implements the VNode Operations trait
 // since this struct can also be used for the file system to store file system specific data we combine the fields v_data and v_op of the mount struct from NetBSD.
 pub struct VNode {
@@ -29,10 +80,10 @@ pub struct VNode {
     v_data_op: Arc<Spinlock<dyn VNodeOperations>>,
     v_type: VType,
     flags: u64,
-    // TODO: add attributes
-    // maybe like netbsd, use union https://github.com/NetBSD/src/blob/trunk/sys/sys/vnode.h#L172
+    /// The contents of regular files are accessed through the page cache, where their pages are stored under this id
+    id: u64,
     // used if vnode is mountpoint, v_mounted_here points to the other file system
-    v_mounted_here: Option<Weak<Mount>>,
+    v_mounted_here: Option<Weak<Spinlock<Mount>>>,
 }
 
 impl VNode {
@@ -40,7 +91,7 @@ impl VNode {
         vfsp: Weak<Spinlock<Mount>>,
         data_op: Arc<Spinlock<dyn VNodeOperations>>,
         v_type: VType,
-        v_mounted_here: Option<Weak<Mount>>,
+        v_mounted_here: Option<Weak<Spinlock<Mount>>>,
     ) -> Self {
         VNode {
             vfsp,
@@ -48,11 +99,48 @@ impl VNode {
             v_type,
             v_mounted_here,
             flags: 0,
+            id: NEXT_VNODE_ID.fetch_add(1, Ordering::Relaxed),
         }
     }
 }
 
+impl Drop for VNode {
+    fn drop(&mut self) {
+        page_cache::release(self.id);
+        self.v_data_op.lock().inactive();
+    }
+}
+
 impl VNode {
+    pub fn v_type(&self) -> VType {
+        self.v_type
+    }
+
+    /// Returns the flags of the mount this vnode belongs to
+    pub fn mount_flags(&self) -> MountFlags {
+        self.vfsp
+            .upgrade()
+            .map(|mount| mount.lock().flags())
+            .unwrap_or_default()
+    }
+
+    /// Returns the file system which is mounted on this vnode
+    pub fn mounted_here(&self) -> Option<Arc<Spinlock<Mount>>> {
+        self.v_mounted_here.as_ref().and_then(Weak::upgrade)
+    }
+
+    pub fn set_mounted_here(&mut self, mount: Option<Weak<Spinlock<Mount>>>) {
+        self.v_mounted_here = mount;
+    }
+
+    /// Runs `f` with the private data of the file system, returns `None` if the node belongs to another file system
+    pub fn downcast_data<T: VNodeOperations, R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut data_op = self.v_data_op.lock();
+        let data_op: &mut dyn Any = &mut *data_op;
+
+        data_op.downcast_mut::<T>().map(f)
+    }
+
     pub fn close(&self) {
         self.v_data_op.lock().close();
     }
@@ -65,15 +153,15 @@ impl VNode {
         self.v_data_op.lock().bmap()
     }
 
-    pub fn create(&mut self, path: String, v_type: VType) -> Result<Arc<Spinlock<VNode>>> {
-        self.v_data_op.lock().create(path, v_type, self.vfsp.clone())
+    pub fn create(&mut self, path: String, v_type: VType, mode: u32) -> Result<Arc<Spinlock<VNode>>> {
+        self.v_data_op.lock().create(path, v_type, mode, self.vfsp.clone())
     }
 
     pub fn fsync(&self) {
         self.v_data_op.lock().fsync()
     }
 
-    pub fn getattr(&self) {
+    pub fn getattr(&self) -> Result<VAttr> {
         self.v_data_op.lock().getattr()
     }
 
@@ -81,19 +169,19 @@ impl VNode {
         self.v_data_op.lock().inactive()
     }
 
-    pub fn ioctl(&self) {
+    pub fn ioctl(&self) -> Result<()> {
         self.v_data_op.lock().ioctl()
     }
 
-    pub fn link(&self) {
-        self.v_data_op.lock().link()
+    pub fn link(&self, name: &str, node: &Arc<Spinlock<VNode>>) -> Result<()> {
+        self.v_data_op.lock().link(name, node)
     }
 
-    pub fn lookup(&self, path: &PathBuf) -> Result<Arc<Spinlock<VNode>>> {
-        self.v_data_op.lock().lookup(path)
+    pub fn lookup(&self, name: &str) -> Result<Arc<Spinlock<VNode>>> {
+        self.v_data_op.lock().lookup(name)
     }
 
-    pub fn mknod(&self) {
+    pub fn mknod(&self) -> Result<()> {
         self.v_data_op.lock().mknod()
     }
 
@@ -105,15 +193,42 @@ impl VNode {
         self.v_data_op.lock().pathconf()
     }
 
-    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
-        self.v_data_op.lock().read(buf)
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if self.v_type != VType::Regular {
+            return self.v_data_op.lock().read(offset, buf);
+        }
+
+        let size = self.getattr()?.size;
+
+        page_cache::read(self.id, &self.v_data_op, size, offset, buf)
+    }
+
+    /// Reads the whole file
+    pub fn read_all(&self) -> Result<Vec<u8>> {
+        let size = self.getattr()?.size;
+        let mut buf = vec![0; size];
+        let mut offset = 0;
+
+        while offset < size {
+            let read = self.read(offset, &mut buf[offset..])?;
+
+            if read == 0 {
+                break;
+            }
+
+            offset += read;
+        }
+
+        buf.truncate(offset);
+
+        Ok(buf)
     }
 
-    pub fn readdir(&self) {
-        self.v_data_op.lock().readdir()
+    pub fn readdir(&self, cookie: usize) -> Result<Option<DirEntry>> {
+        self.v_data_op.lock().readdir(cookie)
     }
 
-    pub fn readlink(&self) {
+    pub fn readlink(&self) -> Result<String> {
         self.v_data_op.lock().readlink()
     }
 
@@ -121,41 +236,79 @@ impl VNode {
         self.v_data_op.lock().reclaim()
     }
 
-    pub fn remove(&self) {
-        self.v_data_op.lock().remove()
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.v_data_op.lock().remove(name)
     }
 
-    pub fn rename(&self) {
-        self.v_data_op.lock().rename()
+    pub fn rename(&self, old_name: &str, new_dir: Option<&VNode>, new_name: &str) -> Result<()> {
+        self.v_data_op.lock().rename(old_name, new_dir, new_name)
     }
 
-    pub fn mkdir(&self) {
-        self.v_data_op.lock().mkdir()
+    pub fn mkdir(&self, name: &str, mode: u32) -> Result<Arc<Spinlock<VNode>>> {
+        self.v_data_op.lock().mkdir(name, mode, self.vfsp.clone())
     }
 
-    pub fn rmdir(&self) {
-        self.v_data_op.lock().rmdir()
+    pub fn rmdir(&self, name: &str) -> Result<()> {
+        self.v_data_op.lock().rmdir(name)
     }
 
-    pub fn setattr(&self) {
-        self.v_data_op.lock().setattr()
+    pub fn setattr(&self, attr: &SetAttr) -> Result<()> {
+        self.v_data_op.lock().setattr(attr)?;
+
+        if let (Some(size), VType::Regular) = (attr.size, self.v_type) {
+            page_cache::truncate(self.id, size);
+        }
+
+        Ok(())
     }
 
-    pub fn symlink(&self) {
-        self.v_data_op.lock().symlink()
+    pub fn symlink(&self, name: &str, target: &str) -> Result<Arc<Spinlock<VNode>>> {
+        self.v_data_op.lock().symlink(name, target, self.vfsp.clone())
     }
 
-    pub fn write(&self, buf: &mut [u8]) -> Result<usize> {
-        self.v_data_op.lock().write(buf)
+    pub fn write(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let written = self.v_data_op.lock().write(offset, buf)?;
+
+        // writes go through to the file system, the cached pages only have to be kept up to date
+        if self.v_type == VType::Regular {
+            page_cache::write(self.id, offset, &buf[..written]);
+        }
+
+        Ok(written)
     }
 
     pub fn kqfilter(&self) {
         self.v_data_op.lock().kqfilter()
     }
+
+    pub fn getpages(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.v_data_op.lock().getpages(offset, buf)
+    }
+
+    pub fn putpages(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.v_data_op.lock().putpages(offset, buf)
+    }
+
+    /// Returns the frame which caches the page of this regular file at `offset`
+    ///
+    /// The frame gets an additional reference, so it can be mapped into an address space.
+    pub fn get_page(&self, offset: usize) -> Result<PhysFrame> {
+        page_cache::get_page(self.id, &self.v_data_op, offset)
+    }
+
+    /// Marks the cached page at `offset` as changed through a mapping
+    pub fn dirty_page(&self, offset: usize) {
+        page_cache::mark_dirty(self.id, offset);
+    }
+
+    /// Writes the dirty cached pages between `start` and `end` back to the file system
+    pub fn sync_pages(&self, start: usize, end: usize) -> Result<()> {
+        page_cache::sync(self.id, start, end)
+    }
 }
 
 /// This trait maps logical operations to real functions. It is file system specific as the actions taken by each operation depend heavily on the file system where the file resides.
-pub trait VNodeOperations {
+pub trait VNodeOperations: Any {
     /// Aborts an in-progress operation.
     fn abortop(&self) {
         unimplemented!()
@@ -184,7 +337,13 @@ pub trait VNodeOperations {
     fn close(&self);
 
     /// Creates a new file.
-    fn create(&mut self, path: String, v_type: VType, mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>>;
+    fn create(
+        &mut self,
+        path: String,
+        v_type: VType,
+        mode: u32,
+        mount: Weak<Spinlock<Mount>>,
+    ) -> Result<Arc<Spinlock<VNode>>>;
 
     /// Synchronizes the file with on-disk contents.
     fn fsync(&self) {
@@ -192,28 +351,35 @@ pub trait VNodeOperations {
     }
 
     /// Gets a file's attributes.
-    fn getattr(&self) {
-        unimplemented!()
-    }
+    fn getattr(&self) -> Result<VAttr>;
 
     /// Marks the vnode as inactive.
-    fn inactive(&self) {
-        unimplemented!()
-    }
+    ///
+    /// Called when the last reference to the vnode is dropped, files without any links left are freed here.
+    fn inactive(&self) {}
 
     /// Performs an ioctl on a file.
-    fn ioctl(&self);
+    fn ioctl(&self) -> Result<()> {
+        Err(Error::NotPermitted)
+    }
 
     /// Creates a new hard link for a file.
-    fn link(&self) {
-        unimplemented!()
+    ///
+    /// Adds the entry `name` to this directory, which refers to `node`.
+    fn link(&mut self, _name: &str, _node: &Arc<Spinlock<VNode>>) -> Result<()> {
+        Err(Error::NotPermitted)
     }
 
     /// Performs a path name lookup.
-    fn lookup(&self, path: &PathBuf) -> Result<Arc<Spinlock<VNode>>>;
+    ///
+    /// Looks up a single component in this directory, the walk over the whole path is done by the VFS. `..` refers
+    /// to the parent directory, it is never looked up in the root of a file system.
+    fn lookup(&self, name: &str) -> Result<Arc<Spinlock<VNode>>>;
 
     /// Creates a new special file (a device or a named pipe).
-    fn mknod(&self);
+    fn mknod(&self) -> Result<()> {
+        Err(Error::NotPermitted)
+    }
 
     /// Opens a file.
     fn open(&self);
@@ -224,33 +390,41 @@ pub trait VNodeOperations {
     }
 
     /// Reads a chunk of data from a file.
-    fn read(&self, buf: &mut [u8]) -> Result<usize>;
+    ///
+    /// Reads up to `buf.len()` bytes starting at `offset`, returns 0 at the end of the file.
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize>;
 
     /// Reads directory entries from a directory.
-    fn readdir(&self);
+    ///
+    /// Returns the entry at `cookie`, which is 0 for the first entry and [`DirEntry::next_cookie`] for the following
+    /// ones, or `None` at the end of the directory.
+    fn readdir(&self, cookie: usize) -> Result<Option<DirEntry>>;
 
     /// Reads the contents of a symbolic link.
-    fn readlink(&self);
+    fn readlink(&self) -> Result<String>;
 
     /// Reclaims the vnode.
-    fn reclaim(&self);
+    fn reclaim(&self) {}
 
     /// Removes a file.
-    fn remove(&self);
+    fn remove(&mut self, name: &str) -> Result<()>;
 
     /// Renames a file.
-    fn rename(&self);
+    ///
+    /// Moves the entry `old_name` of this directory to `new_name` in `new_dir`, or in this directory if `new_dir` is
+    /// `None`. An existing entry `new_name` is replaced. The caller holds the locks of both directories.
+    fn rename(&mut self, old_name: &str, new_dir: Option<&VNode>, new_name: &str) -> Result<()>;
 
     /// Creates a new directory.
-    fn mkdir(&self);
+    fn mkdir(&mut self, name: &str, mode: u32, mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>>;
 
-    /// Removes a directory.
-    fn rmdir(&self);
+    /// Removes a directory, which has to be empty.
+    fn rmdir(&mut self, name: &str) -> Result<()>;
 
     /// Sets a file's attributes.
-    fn setattr(&self) {
-        unimplemented!()
-    }
+    ///
+    /// Changing any attribute also updates the time of the last change.
+    fn setattr(&mut self, attr: &SetAttr) -> Result<()>;
 
     /// Performs a file transfer between the file system's backing store and memory.
     fn strategy(&self) {
@@ -258,10 +432,14 @@ pub trait VNodeOperations {
     }
 
     /// Creates a new symbolic link for a file.
-    fn symlink(&self);
+    ///
+    /// Adds the entry `name` to this directory, which is a symbolic link pointing to `target`.
+    fn symlink(&mut self, name: &str, target: &str, mount: Weak<Spinlock<Mount>>) -> Result<Arc<Spinlock<VNode>>>;
 
     /// Writes a chunk of data to a file.
-    fn write(&mut self, buf: &mut [u8]) -> Result<usize>;
+    ///
+    /// Writes `buf` starting at `offset`, the file is extended if the data doesn't fit.
+    fn write(&mut self, offset: usize, buf: &[u8]) -> Result<usize>;
 
     fn kqfilter(&self) {
         unimplemented!()
@@ -300,11 +478,15 @@ pub trait VNodeOperations {
         unimplemented!()
     } // NetBSD has it, OpenBSD not?!
     /// Reads memory pages from the file.
-    fn getpages(&self) {
+    ///
+    /// Reads up to `buf.len()` bytes starting at `offset`, used to fill the pages of a file mapping.
+    fn getpages(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
         unimplemented!()
     } // NetBSD has it, OpenBSD not?!
     /// Writes memory pages to the file.
-    fn putpages(&self) {
+    ///
+    /// Writes back the pages of a shared file mapping, the file is never extended.
+    fn putpages(&mut self, _offset: usize, _buf: &[u8]) -> Result<usize> {
         unimplemented!()
     } // NetBSD has it, OpenBSD not?!
 }
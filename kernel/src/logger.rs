@@ -1,8 +1,26 @@
+use alloc::string::String;
 use core::fmt;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use x86_64::instructions::port::Port;
 
+use crate::fs::Result;
+use crate::fs::devfs::CharDevice;
+
+/// Whether every syscall and its result is logged
+static SYSCALL_TRACING: AtomicBool = AtomicBool::new(false);
+
+pub fn syscall_tracing() -> bool {
+    SYSCALL_TRACING.load(Ordering::Relaxed)
+}
+
+/// Switches syscall tracing on or off and logs the new state
+pub fn toggle_syscall_tracing() {
+    let enabled = !SYSCALL_TRACING.fetch_xor(true, Ordering::Relaxed);
+    info!("syscall tracing {}", if enabled { "enabled" } else { "disabled" });
+}
+
 struct Writer;
 
 impl core::fmt::Write for Writer {
@@ -19,6 +37,24 @@ impl core::fmt::Write for Writer {
     }
 }
 
+/// `/dev/kmsg`, writes are added to the kernel log on the debug port
+///
+/// Reads return end of file, since the kernel log is not kept in memory.
+pub struct KernelLog;
+
+impl CharDevice for KernelLog {
+    fn read(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        // UNWRAP: We always return `Ok(())` inside `write_str` so this is unreachable.
+        Writer.write_str(&String::from_utf8_lossy(buf)).unwrap();
+
+        Ok(buf.len())
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     let mut writer = Writer;
@@ -1,5 +1,6 @@
-#![no_std]
-#![no_main]
+// the unit tests run on the host, with the standard library and its test harness
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(abi_x86_interrupt)]
 #![allow(dead_code)]
 #![allow(clippy::fn_to_numeric_cast)]
@@ -31,12 +32,10 @@ mod userland;
 
 use alloc::sync::Arc;
 use core::arch::asm;
-use core::panic::PanicInfo;
 use core::time::Duration;
 use fs::initramfs;
 use libxernel::sync::Spinlock;
 use limine::*;
-use x86_64::instructions::interrupts;
 
 use arch::amd64::gdt;
 
@@ -47,6 +46,12 @@ use crate::arch::amd64::hcf;
 use crate::cpu::CPU_COUNT;
 use crate::cpu::wait_until_cpus_registered;
 use crate::cpu::{current_cpu, register_cpu};
+use crate::drivers::ps2::keyboard;
+use crate::drivers::ramdisk;
+use crate::drivers::serial;
+use crate::fs::devfs;
+use crate::fs::ext2;
+use crate::fs::fat32;
 use crate::fs::vfs;
 use crate::fs::vfs::VFS;
 use crate::mem::paging::KERNEL_PAGE_MAPPER;
@@ -61,10 +66,11 @@ use crate::utils::rtc::Rtc;
 static BOOTLOADER_INFO: BootInfoRequest = BootInfoRequest::new(0);
 static SMP_REQUEST: SmpRequest = SmpRequest::new(0);
 
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
+fn panic(info: &core::panic::PanicInfo) -> ! {
     // disable interrupts in panic handler to prevent getting scheduled again
-    interrupts::disable();
+    x86_64::instructions::interrupts::disable();
 
     // TODO: check which task paniced and kill it
 
@@ -80,7 +86,7 @@ fn panic(info: &PanicInfo) -> ! {
 
 // TODO: Proper Error handling across the whole kernel (error enums etc.)
 // TODO: Replace linked_list_allocator with a self written allocator
-// TODO: Implement VFS correctly and devFS
+// TODO: Implement VFS correctly
 // TODO: Implement tmpfs
 // TODO: Convenience functions for creating timer and directly adding it to the queue
 // TODO: Same for dpcs
@@ -109,17 +115,30 @@ extern "C" fn kernel_main() -> ! {
 
     hpet::init();
 
+    Rtc::read();
+
     apic::init();
 
     syscall::init();
 
     vfs::init();
 
+    devfs::init();
+    devfs::register_device("console", Arc::new(writer::Console)).expect("Registration of /dev/console failed");
+    devfs::register_device("kmsg", Arc::new(logger::KernelLog)).expect("Registration of /dev/kmsg failed");
+    keyboard::init();
+    serial::init();
+    ramdisk::init();
+    info!("devfs mounted");
+
     vfs::test();
 
     initramfs::load_initramfs();
     info!("initramfs loaded");
 
+    fat32::mount_boot_volume();
+    ext2::mount_data_disk();
+
     let bootloader_info = BOOTLOADER_INFO
         .get_response()
         .get()
@@ -131,8 +150,6 @@ extern "C" fn kernel_main() -> ! {
         bootloader_info.version.to_str().unwrap()
     );
 
-    Rtc::read();
-
     KERNEL_PROCESS.set_once(Arc::new(Spinlock::new(Process::new(None))));
 
     let smp_response = SMP_REQUEST.get_response().get_mut().unwrap();
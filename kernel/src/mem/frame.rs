@@ -1,6 +1,9 @@
 use core::ptr::NonNull;
 
-use crate::{allocator::buddy::BuddyAllocator, mem::HIGHER_HALF_OFFSET};
+use crate::{
+    allocator::{align_up, buddy::BuddyAllocator},
+    mem::{FRAME_SIZE, HIGHER_HALF_OFFSET},
+};
 use libxernel::sync::{Once, Spinlock};
 use limine::{MemmapEntry, MemmapRequest, MemoryMapEntryType, NonNullPtr};
 use x86_64::{
@@ -12,25 +15,52 @@ static MMAP_REQUEST: MemmapRequest = MemmapRequest::new(0);
 
 pub static MEMORY_MAP: Once<&'static [NonNullPtr<MemmapEntry>]> = Once::new();
 
-pub struct PhysFrameAllocator(BuddyAllocator<{ super::FRAME_SIZE as usize }, 12>); // maximum allocation size is 16mb
+pub struct PhysFrameAllocator {
+    buddy: BuddyAllocator<{ super::FRAME_SIZE as usize }, 12>, // maximum allocation size is 16mb
+    /// Number of references to each frame, indexed by the frame number
+    ///
+    /// Frames which are shared copy-on-write between processes have more than one reference.
+    /// The table lives at the start of the first usable memory region which is big enough for it.
+    ref_counts: *mut u16,
+    frame_count: usize,
+}
 
-pub static FRAME_ALLOCATOR: Spinlock<PhysFrameAllocator> = Spinlock::new(PhysFrameAllocator(BuddyAllocator::new()));
+pub static FRAME_ALLOCATOR: Spinlock<PhysFrameAllocator> = Spinlock::new(PhysFrameAllocator {
+    buddy: BuddyAllocator::new(),
+    ref_counts: core::ptr::null_mut(),
+    frame_count: 0,
+});
 
 impl PhysFrameAllocator {
     pub fn allocate_frame<P: PageSize>(&mut self) -> Option<PhysFrame<P>> {
-        let order = self.0.order_for_size(P::SIZE as usize);
+        let order = self.buddy.order_for_size(P::SIZE as usize);
 
-        let frame = self.0.allocate(order);
-        let start_addr = frame.unwrap().as_ptr() as u64 - *HIGHER_HALF_OFFSET;
+        let frame = self.buddy.allocate(order).ok()?;
+        let start_addr = frame.as_ptr() as u64 - *HIGHER_HALF_OFFSET;
         let pframe = PhysFrame::from_start_address(PhysAddr::new(start_addr));
+
+        if let Ok(pframe) = pframe {
+            *self.ref_count_mut(pframe) = 1;
+        }
+
         pframe.ok()
     }
 
+    /// Drops one reference to the frame and frees it once no references are left
     pub unsafe fn deallocate_frame<P: PageSize>(&mut self, frame: PhysFrame<P>) {
-        let order = self.0.order_for_size(P::SIZE as usize);
+        let ref_count = self.ref_count_mut(frame);
+
+        if *ref_count > 1 {
+            *ref_count -= 1;
+            return;
+        }
+
+        *ref_count = 0;
+
+        let order = self.buddy.order_for_size(P::SIZE as usize);
 
         unsafe {
-            self.0
+            self.buddy
                 .deallocate(
                     NonNull::new((frame.start_address().as_u64() + *HIGHER_HALF_OFFSET) as *mut u8).unwrap(),
                     order,
@@ -38,6 +68,39 @@ impl PhysFrameAllocator {
                 .unwrap();
         };
     }
+
+    /// Adds a reference to an allocated frame, which is then shared by multiple mappings
+    pub fn share_frame<P: PageSize>(&mut self, frame: PhysFrame<P>) {
+        let ref_count = self.ref_count_mut(frame);
+
+        assert!(*ref_count > 0, "share_frame: frame {:?} is not allocated", frame);
+
+        *ref_count = ref_count.checked_add(1).expect("share_frame: reference count overflow");
+    }
+
+    pub fn ref_count<P: PageSize>(&self, frame: PhysFrame<P>) -> u16 {
+        let index = self.frame_index(frame);
+
+        unsafe { *self.ref_counts.add(index) }
+    }
+
+    fn ref_count_mut<P: PageSize>(&mut self, frame: PhysFrame<P>) -> &mut u16 {
+        let index = self.frame_index(frame);
+
+        unsafe { &mut *self.ref_counts.add(index) }
+    }
+
+    fn frame_index<P: PageSize>(&self, frame: PhysFrame<P>) -> usize {
+        let index = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+
+        assert!(
+            index < self.frame_count,
+            "frame {:?} is outside of usable memory",
+            frame
+        );
+
+        index
+    }
 }
 
 pub fn init() {
@@ -51,19 +114,49 @@ pub fn init() {
             .memmap(),
     );
 
-    for entry in *MEMORY_MAP {
-        if entry.typ == MemoryMapEntryType::Usable {
-            unsafe {
-                buddy
-                    .0
-                    .add_region(
-                        NonNull::new((entry.base + *HIGHER_HALF_OFFSET) as *mut u8).unwrap(),
-                        NonNull::new((entry.base + *HIGHER_HALF_OFFSET + entry.len) as *mut u8).unwrap(),
-                    )
-                    .unwrap();
-            }
+    let usable_entries = || {
+        MEMORY_MAP
+            .iter()
+            .filter(|entry| entry.typ == MemoryMapEntryType::Usable)
+    };
+
+    let memory_end = usable_entries().map(|entry| entry.base + entry.len).max().unwrap_or(0);
+    let frame_count = (memory_end / FRAME_SIZE) as usize;
+    let table_size = align_up(frame_count * size_of::<u16>(), FRAME_SIZE as usize) as u64;
+
+    let table_base = usable_entries()
+        .find(|entry| entry.len >= table_size)
+        .expect("no memory region is big enough for the frame reference counts")
+        .base;
+
+    buddy.ref_counts = (table_base + *HIGHER_HALF_OFFSET) as *mut u16;
+    buddy.frame_count = frame_count;
+
+    unsafe {
+        core::ptr::write_bytes(buddy.ref_counts, 0, frame_count);
+    }
+
+    for entry in usable_entries() {
+        let mut base = entry.base;
+
+        if base == table_base {
+            base += table_size;
+        }
+
+        if base == entry.base + entry.len {
+            continue;
+        }
+
+        unsafe {
+            buddy
+                .buddy
+                .add_region(
+                    NonNull::new((base + *HIGHER_HALF_OFFSET) as *mut u8).unwrap(),
+                    NonNull::new((entry.base + *HIGHER_HALF_OFFSET + entry.len) as *mut u8).unwrap(),
+                )
+                .unwrap();
         }
     }
 
-    dbg!("{}", buddy.0.stats);
+    dbg!("{}", buddy.buddy.stats);
 }
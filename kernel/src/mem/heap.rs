@@ -9,55 +9,67 @@ use x86_64::structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size
 use crate::allocator::align_up;
 
 use super::HEAP_START_ADDR;
-use super::{frame::FRAME_ALLOCATOR, paging::KERNEL_PAGE_MAPPER};
+use super::{page_cache, paging::KERNEL_PAGE_MAPPER};
 
 // TODO: Replace heap by self written slab allocator
 static HEAP: Spinlock<Heap> = Spinlock::new(Heap::empty());
 
+/// Held while the heap is expanded, which happens without holding the heap itself, since evicting pages of the page
+/// cache to get frames frees heap memory
+static HEAP_EXPANSION: Spinlock<()> = Spinlock::new(());
+
 const HEAP_INITIAL_PAGE_COUNT: u64 = 2; // 4 MiB
 
 struct Allocator;
 
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 static ALLOCATOR: Allocator = Allocator;
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let mut heap = HEAP.lock();
+        if let Ok(ptr) = HEAP.lock().allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        let _expansion = HEAP_EXPANSION.lock();
+
+        let current_top = {
+            let mut heap = HEAP.lock();
 
-        if let Ok(ptr) = heap.allocate_first_fit(layout) {
-            ptr.as_ptr()
-        } else {
-            // expand heap
-            let expansion_size = align_up(layout.size(), Size2MiB::SIZE as usize);
+            // another CPU may have expanded the heap in the meantime
+            if let Ok(ptr) = heap.allocate_first_fit(layout) {
+                return ptr.as_ptr();
+            }
 
-            info!("expanding heap by {} MiB", expansion_size / 1024 / 1024);
+            align_up(heap.top() as usize, Size2MiB::SIZE as usize)
+        };
 
-            let current_top = align_up(heap.top() as usize, Size2MiB::SIZE as usize);
+        // expand heap
+        let expansion_size = align_up(layout.size(), Size2MiB::SIZE as usize);
 
-            for start_address in (current_top..current_top + expansion_size).step_by(Size2MiB::SIZE as usize) {
-                let page = {
-                    let mut allocator = FRAME_ALLOCATOR.lock();
-                    allocator.allocate_frame::<Size2MiB>().unwrap()
-                };
+        info!("expanding heap by {} MiB", expansion_size / 1024 / 1024);
 
-                KERNEL_PAGE_MAPPER.lock().map::<Size2MiB>(
-                    PhysFrame::containing_address(page.start_address()),
-                    Page::containing_address(VirtAddr::new(start_address as u64)),
-                    PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE | PageTableFlags::PRESENT,
-                    true,
-                );
+        for start_address in (current_top..current_top + expansion_size).step_by(Size2MiB::SIZE as usize) {
+            let page = page_cache::allocate_frame::<Size2MiB>().expect("out of memory while expanding the heap");
 
-                unsafe {
-                    heap.extend(Size2MiB::SIZE as usize);
-                };
-            }
+            KERNEL_PAGE_MAPPER.lock().map::<Size2MiB>(
+                PhysFrame::containing_address(page.start_address()),
+                Page::containing_address(VirtAddr::new(start_address as u64)),
+                PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE | PageTableFlags::PRESENT,
+                true,
+            );
 
-            // try to allocate again
-            heap.allocate_first_fit(layout)
-                .expect("heap allocation failed after expansion")
-                .as_ptr()
+            // the top only moves while the expansion lock is held
+            unsafe {
+                HEAP.lock().extend(Size2MiB::SIZE as usize);
+            };
         }
+
+        // try to allocate again
+        HEAP.lock()
+            .allocate_first_fit(layout)
+            .expect("heap allocation failed after expansion")
+            .as_ptr()
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
@@ -80,10 +92,7 @@ pub fn init() {
     for start_address in (HEAP_START_ADDR..HEAP_START_ADDR + (HEAP_INITIAL_PAGE_COUNT * Size2MiB::SIZE) as usize)
         .step_by(Size2MiB::SIZE as usize)
     {
-        let page = {
-            let mut allocator = FRAME_ALLOCATOR.lock();
-            allocator.allocate_frame::<Size2MiB>().unwrap()
-        };
+        let page = page_cache::allocate_frame::<Size2MiB>().unwrap();
 
         page_mapper.map::<Size2MiB>(
             PhysFrame::containing_address(page.start_address()),
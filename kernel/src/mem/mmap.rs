@@ -1,17 +1,22 @@
-use libxernel::syscall::{MapFlags, ProtectionFlags, SyscallError};
+use libxernel::syscall::{MapFlags, MountFlags, MsyncFlags, ProtectionFlags, SyscallError};
 use x86_64::{
     VirtAddr,
     structures::{
         idt::PageFaultErrorCode,
-        paging::{Page, PageSize, Size4KiB},
+        paging::{Page, PageSize, PageTableFlags, PhysFrame, Size4KiB},
     },
 };
 
-use crate::{allocator::align_up, cpu::current_process};
+use crate::{cpu::current_process, fs::vnode::VType};
 
-use super::{frame::FRAME_ALLOCATOR, vm::ptflags_from_protflags};
+use super::{
+    HIGHER_HALF_OFFSET, PROCESS_END,
+    frame::FRAME_ALLOCATOR,
+    page_cache,
+    paging::Pagemap,
+    vm::{MappedFile, ptflags_from_protflags},
+};
 
-#[allow(unused_variables)]
 pub fn mmap(
     addr: usize,
     len: usize,
@@ -20,46 +25,225 @@ pub fn mmap(
     fd: usize,
     offset: usize,
 ) -> Result<isize, SyscallError> {
-    let addr = VirtAddr::new(addr as u64);
     let prot = ProtectionFlags::from_bits(prot as u8).ok_or(SyscallError::InvalidArgument)?;
     let flags = MapFlags::from_bits(flags as u8).ok_or(SyscallError::InvalidArgument)?;
-    let len = align_up(len, Size4KiB::SIZE as usize);
+    let (addr, end) = user_range(addr, len)?;
+    let len = (end - addr) as usize;
+
+    if len == 0 || flags.contains(MapFlags::SHARED | MapFlags::PRIVATE) {
+        return Err(SyscallError::InvalidArgument);
+    }
 
     let process = current_process();
     let mut process = process.lock();
 
-    match flags {
-        MapFlags::ANONYMOUS => {
-            let start_address = process.vm().create_entry_at(addr, len, prot, flags);
+    // NOTE: an anonymous mapping without MAP_SHARED or MAP_PRIVATE is treated as private
+    if flags.contains(MapFlags::ANONYMOUS) {
+        // hints below the start of the process address space are moved up to it
+        let start_address = process.vm().create_entry_at(addr, len, prot, flags)?;
+
+        return Ok(start_address.as_u64() as isize);
+    }
+
+    if !flags.intersects(MapFlags::SHARED | MapFlags::PRIVATE)
+        || !offset.is_multiple_of(Size4KiB::SIZE as usize)
+        || offset.checked_add(len).is_none()
+    {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let file = process.fds.get(fd)?.lock();
+
+    // the pages are read from the file and shared writable mappings write them back to it
+    if !file.flags().is_readable()
+        || (flags.contains(MapFlags::SHARED) && prot.contains(ProtectionFlags::WRITE) && !file.flags().is_writable())
+    {
+        return Err(SyscallError::PermissionDenied);
+    }
+
+    let node = file.get_node();
+    let writable = file.flags().is_writable();
+    drop(file);
 
-            Ok(start_address.as_u64() as isize)
+    if prot.contains(ProtectionFlags::EXECUTE) && node.lock().mount_flags().contains(MountFlags::NO_EXEC) {
+        return Err(SyscallError::NoPermission);
+    }
+
+    // shared writable mappings write back to the file, devices stay writable on a read-only file system though
+    if flags.contains(MapFlags::SHARED) && prot.contains(ProtectionFlags::WRITE) {
+        let node = node.lock();
+
+        if node.v_type() == VType::Regular && node.mount_flags().contains(MountFlags::READ_ONLY) {
+            return Err(SyscallError::ReadOnlyFileSystem);
         }
-        _ => todo!("mmap: implement MAP_SHARED and MAP_PRIVATE"),
     }
+
+    // the pages are read from the file when they are accessed for the first time
+    let file = MappedFile { node, offset, writable };
+    let start_address = process.vm().create_file_entry_at(addr, len, prot, flags, file)?;
+
+    Ok(start_address.as_u64() as isize)
+}
+
+pub fn msync(addr: usize, len: usize, flags: usize) -> Result<isize, SyscallError> {
+    let flags = MsyncFlags::from_bits(flags).ok_or(SyscallError::InvalidArgument)?;
+    let (start, end) = user_range(addr, len)?;
+
+    if flags.contains(MsyncFlags::ASYNC | MsyncFlags::SYNC) {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let process = current_process();
+    let mut process = process.lock();
+    let process = &mut *process;
+
+    // NOTE: dirty pages are always written back synchronously
+    process.vm.sync(process.page_table.as_mut().unwrap(), start, end)?;
+
+    Ok(0)
+}
+
+pub fn munmap(addr: usize, len: usize) -> Result<isize, SyscallError> {
+    let (start, end) = user_range(addr, len)?;
+
+    if len == 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let process = current_process();
+    let mut process = process.lock();
+    let process = &mut *process;
+
+    process.vm.unmap(process.page_table.as_mut().unwrap(), start, end);
+
+    Ok(0)
+}
+
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> Result<isize, SyscallError> {
+    let prot = ProtectionFlags::from_bits(prot as u8).ok_or(SyscallError::InvalidArgument)?;
+    let (start, end) = user_range(addr, len)?;
+
+    let process = current_process();
+    let mut process = process.lock();
+    let process = &mut *process;
+
+    process
+        .vm
+        .protect(process.page_table.as_mut().unwrap(), start, end, prot)?;
+
+    Ok(0)
+}
+
+/// Returns the range of `len` bytes at `addr`, rounded up to whole pages
+///
+/// Fails with [`SyscallError::InvalidArgument`] if `addr` is not page aligned or the range doesn't fit into the user
+/// address space.
+fn user_range(addr: usize, len: usize) -> Result<(VirtAddr, VirtAddr), SyscallError> {
+    let start = VirtAddr::try_new(addr as u64).map_err(|_| SyscallError::InvalidArgument)?;
+
+    let end = (len as u64)
+        .checked_next_multiple_of(Size4KiB::SIZE)
+        .and_then(|len| start.as_u64().checked_add(len))
+        .filter(|end| *end <= PROCESS_END)
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    if !start.is_aligned(Size4KiB::SIZE) {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    Ok((start, VirtAddr::new(end)))
 }
 
 /// Handles a page fault and returns whether the fault was handled successfully
 pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    if addr.as_u64() >= PROCESS_END {
+        return false;
+    }
+
     let process = current_process();
     let mut process = process.lock();
 
-    let vm_entry = process.vm().get_entry_from_address(addr);
+    let vm_entry = process.vm().get_entry_from_address(addr).cloned();
 
     if let Some(vm_entry) = vm_entry {
-        if vm_entry.flags != MapFlags::ANONYMOUS {
-            todo!("handle_page_fault: implement non-anonymous mappings");
+        // the pages of an inaccessible entry keep their frames, every access is refused
+        if vm_entry.prot.is_empty() {
+            return false;
         }
 
-        // If the page is present we don't need to map it
-        // FIXME: this doesn't work when COW is implemented
+        let base_addr = addr.align_down(Size4KiB::SIZE);
+        let pt_flags = ptflags_from_protflags(vm_entry.prot, process.page_table.is_some());
+
+        // a write to a present page which the entry allows can only hit a page shared by fork
         if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+                && vm_entry.prot.contains(ProtectionFlags::WRITE)
+                && !vm_entry.flags.contains(MapFlags::SHARED)
+            {
+                let pt = process.get_page_table().as_mut().unwrap();
+
+                return copy_on_write(pt, base_addr, pt_flags);
+            }
+
             return false;
         }
 
-        let base_addr = addr.align_down(Size4KiB::SIZE);
-        let frame = FRAME_ALLOCATOR.lock().allocate_frame::<Size4KiB>().unwrap();
+        if let Some(file) = &vm_entry.file {
+            let node = file.node.lock();
+
+            // regular files map the frames of the page cache
+            if node.v_type() == VType::Regular {
+                // UNWRAP: the entry has a file and mmap checked that its offsets don't overflow
+                let offset = vm_entry.file_offset(addr).unwrap();
+
+                let Ok(frame) = node.get_page(offset) else {
+                    return false;
+                };
+
+                drop(node);
+
+                // private mappings share the cached page until they write to it
+                let pt_flags = if vm_entry.flags.contains(MapFlags::SHARED) {
+                    pt_flags
+                } else {
+                    pt_flags - PageTableFlags::WRITABLE
+                };
+
+                let pt = process.get_page_table().as_mut().unwrap();
+                pt.map::<Size4KiB>(frame, Page::from_start_address(base_addr).unwrap(), pt_flags, true);
+
+                return true;
+            }
+        }
+
+        let Some(frame) = page_cache::allocate_frame() else {
+            return false;
+        };
+
+        let page_data = unsafe {
+            core::slice::from_raw_parts_mut(
+                (frame.start_address().as_u64() + *HIGHER_HALF_OFFSET) as *mut u8,
+                Size4KiB::SIZE as usize,
+            )
+        };
+
+        page_data.fill(0);
+
+        // NOTE: pages of other files, like devices, aren't cached, every mapping gets its own copy
+        if let Some(file) = &vm_entry.file {
+            // UNWRAP: the entry has a file and mmap checked that its offsets don't overflow
+            let offset = vm_entry.file_offset(addr).unwrap();
+
+            // a page beyond the end of the file stays zeroed
+            if file.node.lock().getpages(offset, page_data).is_err() {
+                unsafe {
+                    FRAME_ALLOCATOR.lock().deallocate_frame(frame);
+                }
+
+                return false;
+            }
+        }
 
-        let pt_flags = ptflags_from_protflags(vm_entry.prot, process.page_table.is_some());
         let pt = process.get_page_table().as_mut().unwrap();
 
         pt.map::<Size4KiB>(frame, Page::from_start_address(base_addr).unwrap(), pt_flags, true);
@@ -69,3 +253,39 @@ pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool
         false
     }
 }
+
+/// Gives the faulting process its own writable copy of a copy-on-write page
+///
+/// If no other mapping or the page cache references the frame anymore, it is made writable without copying.
+/// Returns false if no memory is left for the copy.
+fn copy_on_write(pt: &mut Pagemap, base_addr: VirtAddr, pt_flags: PageTableFlags) -> bool {
+    let page = Page::<Size4KiB>::from_start_address(base_addr).unwrap();
+    // UNWRAP: a protection violation only happens on present pages
+    let old_frame = PhysFrame::<Size4KiB>::containing_address(pt.translate(base_addr).unwrap());
+
+    if FRAME_ALLOCATOR.lock().ref_count(old_frame) == 1 {
+        pt.map(old_frame, page, pt_flags, true);
+        return true;
+    }
+
+    let Some(new_frame) = page_cache::allocate_frame() else {
+        return false;
+    };
+
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            (old_frame.start_address().as_u64() + *HIGHER_HALF_OFFSET) as *const u8,
+            (new_frame.start_address().as_u64() + *HIGHER_HALF_OFFSET) as *mut u8,
+            Size4KiB::SIZE as usize,
+        );
+
+        frame_allocator.deallocate_frame(old_frame);
+    }
+
+    drop(frame_allocator);
+    pt.map(new_frame, page, pt_flags, true);
+
+    true
+}
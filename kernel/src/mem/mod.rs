@@ -1,6 +1,7 @@
 pub mod frame;
 pub mod heap;
 pub mod mmap;
+pub mod page_cache;
 pub mod paging;
 pub mod vm;
 
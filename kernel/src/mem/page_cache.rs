@@ -0,0 +1,315 @@
+//! Pages of regular files are cached in frames, which are shared by reads, writes and file mappings
+//!
+//! Writes go through to the file system and update the cached pages, so only pages which were changed through a
+//! shared mapping are dirty and have to be written back. Changes through a mapping are only noticed when the mapping is
+//! synced with msync, unmapped or torn down on exit, so that's also when they are written back.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use libxernel::sync::Spinlock;
+use x86_64::structures::paging::{PageSize, PhysFrame, Size4KiB};
+
+use crate::allocator::align_up;
+use crate::fs::{self, vnode::VNodeOperations};
+
+use super::{HIGHER_HALF_OFFSET, frame::FRAME_ALLOCATOR};
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// Number of pages which are evicted at once when no memory is left
+const RECLAIM_BATCH: usize = 16;
+
+pub static PAGE_CACHE: Spinlock<PageCache> = Spinlock::new(PageCache::new());
+
+struct CachedPage {
+    /// The cache owns one reference to the frame, every mapping of the page adds another one
+    frame: PhysFrame,
+    /// Set while a write back of the page is pending, a page stays dirty if writing it back failed
+    dirty: bool,
+    /// Value of the clock at the last access, the least recently used pages are evicted first
+    last_used: u64,
+    /// The file system's data of the file, which is used to write the page back
+    backing: Arc<Spinlock<dyn VNodeOperations>>,
+}
+
+pub struct PageCache {
+    /// Pages keyed by the id of their vnode and their offset in the file
+    pages: BTreeMap<(u64, usize), CachedPage>,
+    clock: u64,
+}
+
+impl PageCache {
+    pub const fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns the cached page with an additional reference for the caller
+    fn lookup(&mut self, id: u64, offset: usize) -> Option<PhysFrame> {
+        self.clock += 1;
+
+        let page = self.pages.get_mut(&(id, offset))?;
+        page.last_used = self.clock;
+
+        FRAME_ALLOCATOR.lock().share_frame(page.frame);
+
+        Some(page.frame)
+    }
+
+    fn insert(&mut self, id: u64, offset: usize, frame: PhysFrame, backing: Arc<Spinlock<dyn VNodeOperations>>) {
+        self.clock += 1;
+
+        self.pages.insert(
+            (id, offset),
+            CachedPage {
+                frame,
+                dirty: false,
+                last_used: self.clock,
+                backing,
+            },
+        );
+    }
+
+    fn pages_of(&mut self, id: u64, start: usize, end: usize) -> impl Iterator<Item = (&usize, &mut CachedPage)> {
+        self.pages
+            .range_mut((id, start)..(id, end))
+            .map(|((_, offset), page)| (offset, page))
+    }
+
+    /// Evicts up to `count` of the least recently used clean pages which aren't mapped anywhere
+    ///
+    /// Nothing is allocated or written back, so this also works on behalf of the heap or a file system. Returns the
+    /// number of evicted pages.
+    fn reclaim(&mut self, count: usize) -> usize {
+        let mut evicted = 0;
+
+        while evicted < count {
+            let frame_allocator = FRAME_ALLOCATOR.lock();
+
+            let victim = self
+                .pages
+                .iter()
+                .filter(|(_, page)| !page.dirty && frame_allocator.ref_count(page.frame) == 1)
+                .min_by_key(|(_, page)| page.last_used)
+                .map(|(key, _)| *key);
+
+            drop(frame_allocator);
+
+            let Some(key) = victim else {
+                break;
+            };
+
+            // UNWRAP: the key was just found in the map
+            let page = self.pages.remove(&key).unwrap();
+
+            unsafe {
+                FRAME_ALLOCATOR.lock().deallocate_frame(page.frame);
+            }
+
+            evicted += 1;
+        }
+
+        evicted
+    }
+}
+
+/// Allocates a frame, pages of the page cache are evicted if no memory is left
+///
+/// All frames of the kernel should be allocated here instead of with the frame allocator directly. The frame
+/// allocator must not be locked by the caller. The cache is not evicted from while it is locked, e.g. because the
+/// allocation happens on behalf of the cache itself.
+pub fn allocate_frame<P: PageSize>() -> Option<PhysFrame<P>> {
+    loop {
+        if let Some(frame) = FRAME_ALLOCATOR.lock().allocate_frame::<P>() {
+            return Some(frame);
+        }
+
+        if PAGE_CACHE.try_lock()?.reclaim(RECLAIM_BATCH) == 0 {
+            return None;
+        }
+    }
+}
+
+/// Returns the frame which caches the page of the file `id` at `offset`, the page is read from `backing` if it
+/// isn't cached yet
+///
+/// The frame gets an additional reference for the caller, which has to be dropped with
+/// [`deallocate_frame`](super::frame::PhysFrameAllocator::deallocate_frame).
+pub fn get_page(id: u64, backing: &Arc<Spinlock<dyn VNodeOperations>>, offset: usize) -> fs::Result<PhysFrame> {
+    if let Some(frame) = PAGE_CACHE.lock().lookup(id, offset) {
+        return Ok(frame);
+    }
+
+    let frame = allocate_frame().ok_or(fs::Error::NoSpace)?;
+    let data = unsafe { frame_data(frame) };
+
+    // a page beyond the end of the file stays zeroed
+    data.fill(0);
+
+    if let Err(err) = backing.lock().getpages(offset, data) {
+        unsafe {
+            FRAME_ALLOCATOR.lock().deallocate_frame(frame);
+        }
+
+        return Err(err);
+    }
+
+    let mut cache = PAGE_CACHE.lock();
+
+    // the file was read without holding the cache, so the page may have been cached in the meantime
+    if let Some(cached) = cache.lookup(id, offset) {
+        unsafe {
+            FRAME_ALLOCATOR.lock().deallocate_frame(frame);
+        }
+
+        return Ok(cached);
+    }
+
+    cache.insert(id, offset, frame, backing.clone());
+    FRAME_ALLOCATOR.lock().share_frame(frame);
+
+    Ok(frame)
+}
+
+/// Reads from the cached pages of the file `id`, which is `size` bytes long
+pub fn read(
+    id: u64,
+    backing: &Arc<Spinlock<dyn VNodeOperations>>,
+    size: usize,
+    offset: usize,
+    buf: &mut [u8],
+) -> fs::Result<usize> {
+    let end = offset.saturating_add(buf.len()).min(size);
+    let mut pos = offset;
+
+    while pos < end {
+        let page_offset = pos - pos % PAGE_SIZE;
+        let start = pos - page_offset;
+        let len = (PAGE_SIZE - start).min(end - pos);
+
+        let frame = get_page(id, backing, page_offset)?;
+        let data = unsafe { frame_data(frame) };
+
+        buf[pos - offset..pos - offset + len].copy_from_slice(&data[start..start + len]);
+
+        unsafe {
+            FRAME_ALLOCATOR.lock().deallocate_frame(frame);
+        }
+
+        pos += len;
+    }
+
+    Ok(pos.saturating_sub(offset))
+}
+
+/// Copies data which was written to the file `id` into its cached pages
+pub fn write(id: u64, offset: usize, buf: &[u8]) {
+    let mut cache = PAGE_CACHE.lock();
+    let end = offset + buf.len();
+
+    for (&page_offset, page) in cache.pages_of(id, offset - offset % PAGE_SIZE, end) {
+        let start = offset.max(page_offset);
+        let stop = end.min(page_offset + PAGE_SIZE);
+
+        let data = unsafe { frame_data(page.frame) };
+        data[start - page_offset..stop - page_offset].copy_from_slice(&buf[start - offset..stop - offset]);
+    }
+}
+
+/// Drops the cached pages of the file `id` which lie beyond `size` and zeroes the rest of its last page
+///
+/// Mappings of the dropped pages keep their frames, but they aren't connected to the file anymore.
+pub fn truncate(id: u64, size: usize) {
+    let mut cache = PAGE_CACHE.lock();
+
+    if !size.is_multiple_of(PAGE_SIZE) {
+        let page_offset = size - size % PAGE_SIZE;
+
+        if let Some(page) = cache.pages.get(&(id, page_offset)) {
+            let data = unsafe { frame_data(page.frame) };
+            data[size - page_offset..].fill(0);
+        }
+    }
+
+    let dropped: Vec<_> = cache
+        .pages_of(id, align_up(size, PAGE_SIZE), usize::MAX)
+        .map(|(&offset, _)| offset)
+        .collect();
+
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+
+    for offset in dropped {
+        // UNWRAP: the key was just collected from the map
+        let page = cache.pages.remove(&(id, offset)).unwrap();
+
+        unsafe {
+            frame_allocator.deallocate_frame(page.frame);
+        }
+    }
+}
+
+/// Marks the cached page of the file `id` at `offset` as changed, so it gets written back
+pub fn mark_dirty(id: u64, offset: usize) {
+    if let Some(page) = PAGE_CACHE.lock().pages.get_mut(&(id, offset)) {
+        page.dirty = true;
+    }
+}
+
+/// Writes the dirty pages of the file `id` between `start` and `end` back
+pub fn sync(id: u64, start: usize, end: usize) -> fs::Result<()> {
+    let mut cache = PAGE_CACHE.lock();
+
+    // the pages are written back without holding the cache, they keep an extra reference until then
+    let dirty: Vec<_> = cache
+        .pages_of(id, start, end)
+        .filter(|(_, page)| page.dirty)
+        .map(|(&offset, page)| {
+            page.dirty = false;
+            FRAME_ALLOCATOR.lock().share_frame(page.frame);
+
+            (offset, page.frame, page.backing.clone())
+        })
+        .collect();
+
+    drop(cache);
+
+    let mut result = Ok(());
+
+    for (offset, frame, backing) in dirty {
+        if let Err(err) = backing.lock().putpages(offset, unsafe { frame_data(frame) }) {
+            mark_dirty(id, offset);
+            result = Err(err);
+        }
+
+        unsafe {
+            FRAME_ALLOCATOR.lock().deallocate_frame(frame);
+        }
+    }
+
+    result
+}
+
+/// Writes back and drops all cached pages of the file `id`, called when its vnode goes away
+pub fn release(id: u64) {
+    if let Err(err) = sync(id, 0, usize::MAX) {
+        error!("failed to write back the cached pages of vnode {}: {:?}", id, err);
+    }
+
+    truncate(id, 0);
+}
+
+/// Returns the contents of a frame through the higher half direct map
+///
+/// # Safety
+/// The frame has to be allocated and the caller must not create overlapping references to its contents
+unsafe fn frame_data(frame: PhysFrame) -> &'static mut [u8] {
+    unsafe {
+        core::slice::from_raw_parts_mut(
+            (frame.start_address().as_u64() + *HIGHER_HALF_OFFSET) as *mut u8,
+            PAGE_SIZE,
+        )
+    }
+}
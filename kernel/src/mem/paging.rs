@@ -1,4 +1,4 @@
-use super::{HIGHER_HALF_OFFSET, frame::FRAME_ALLOCATOR};
+use super::{HIGHER_HALF_OFFSET, frame::FRAME_ALLOCATOR, page_cache};
 use crate::{
     allocator::align_up,
     mem::{KERNEL_OFFSET, frame::MEMORY_MAP},
@@ -8,7 +8,7 @@ use libxernel::sync::Spinlock;
 use limine::KernelAddressRequest;
 use x86_64::{
     PhysAddr, VirtAddr,
-    structures::paging::{PageTable, PageTableFlags, PhysFrame},
+    structures::paging::{PageTable, PageTableFlags, PhysFrame, page_table::PageTableEntry},
 };
 use x86_64::{
     align_down,
@@ -20,6 +20,11 @@ static KERNEL_ADDRESS_REQUEST: KernelAddressRequest = KernelAddressRequest::new(
 
 pub static KERNEL_PAGE_MAPPER: Spinlock<InitAtBoot<Pagemap>> = Spinlock::new(InitAtBoot::Uninitialized);
 
+/// Marks a page which was made inaccessible with `PROT_NONE`
+///
+/// The entry is not present, but it still refers to the frame of the page, so the page keeps its contents.
+pub const NO_ACCESS: PageTableFlags = PageTableFlags::BIT_9;
+
 #[derive(Debug)]
 pub struct Pagemap {
     page_table: *mut PageTable,
@@ -34,11 +39,7 @@ unsafe extern "C" {
 
 impl Pagemap {
     pub fn new(pt_frame: Option<PhysFrame>) -> Self {
-        let frame = pt_frame.unwrap_or_else(|| {
-            let mut frame_allocator = FRAME_ALLOCATOR.lock();
-
-            frame_allocator.allocate_frame().unwrap()
-        });
+        let frame = pt_frame.unwrap_or_else(|| page_cache::allocate_frame().unwrap());
 
         let pt_address = unsafe {
             let ptr = (frame.start_address().as_u64() + *HIGHER_HALF_OFFSET) as *mut PageTable;
@@ -73,23 +74,24 @@ impl Pagemap {
     pub fn map<P: PageSize>(&mut self, phys: PhysFrame<P>, virt: Page<P>, flags: PageTableFlags, flush_tlb: bool) {
         let pml4 = self.page_table;
 
-        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        // the page tables themselves are always present, even if the page is not
+        let table_flags = (flags | PageTableFlags::PRESENT) - NO_ACCESS;
 
         unsafe {
             let pml4_entry = &mut (&mut (*pml4))[virt.start_address().p4_index()];
 
             if !pml4_entry.flags().contains(PageTableFlags::PRESENT) {
-                let frame = frame_allocator.allocate_frame::<Size4KiB>().unwrap();
+                let frame = page_cache::allocate_frame::<Size4KiB>().unwrap();
 
                 let address = frame.start_address().as_u64();
 
                 let p_table: *mut PageTable = (address + *HIGHER_HALF_OFFSET) as *mut PageTable;
                 *p_table = PageTable::new();
 
-                pml4_entry.set_addr(PhysAddr::new(address), flags);
+                pml4_entry.set_addr(PhysAddr::new(address), table_flags);
             }
 
-            pml4_entry.set_flags(pml4_entry.flags() | flags);
+            pml4_entry.set_flags(pml4_entry.flags() | table_flags);
 
             let pml3 = (pml4_entry.addr().as_u64() + *HIGHER_HALF_OFFSET) as *mut PageTable;
 
@@ -112,17 +114,17 @@ impl Pagemap {
             }
 
             if !pml3_entry.flags().contains(PageTableFlags::PRESENT) {
-                let frame = frame_allocator.allocate_frame::<Size4KiB>().unwrap();
+                let frame = page_cache::allocate_frame::<Size4KiB>().unwrap();
 
                 let address = frame.start_address().as_u64();
 
                 let p_table: *mut PageTable = (address + *HIGHER_HALF_OFFSET) as *mut PageTable;
                 *p_table = PageTable::new();
 
-                pml3_entry.set_addr(PhysAddr::new(address), flags);
+                pml3_entry.set_addr(PhysAddr::new(address), table_flags);
             }
 
-            pml3_entry.set_flags(pml4_entry.flags() | flags);
+            pml3_entry.set_flags(pml4_entry.flags() | table_flags);
 
             let pml2 = (pml3_entry.addr().as_u64() + *HIGHER_HALF_OFFSET) as *mut PageTable;
 
@@ -144,17 +146,17 @@ impl Pagemap {
             }
 
             if !pml2_entry.flags().contains(PageTableFlags::PRESENT) {
-                let frame = frame_allocator.allocate_frame::<Size4KiB>().unwrap();
+                let frame = page_cache::allocate_frame::<Size4KiB>().unwrap();
 
                 let address = frame.start_address().as_u64();
 
                 let p_table: *mut PageTable = (address + *HIGHER_HALF_OFFSET) as *mut PageTable;
                 *p_table = PageTable::new();
 
-                pml2_entry.set_addr(PhysAddr::new(address), flags);
+                pml2_entry.set_addr(PhysAddr::new(address), table_flags);
             }
 
-            pml2_entry.set_flags(pml4_entry.flags() | flags);
+            pml2_entry.set_flags(pml4_entry.flags() | table_flags);
 
             let pml1 = (pml2_entry.addr().as_u64() + *HIGHER_HALF_OFFSET) as *mut PageTable;
 
@@ -274,26 +276,31 @@ impl Pagemap {
                 return;
             }
 
-            let pml3 = (&(*pml4))[virt.p4_index()].addr().as_u64() as *mut PageTable;
+            let pml3 = Self::get_pt(pml4, virt.p4_index());
 
             if !(&(*pml3))[virt.p3_index()].flags().contains(PageTableFlags::PRESENT) {
                 return;
             }
 
-            let pml2 = (&(*pml3))[virt.p3_index()].addr().as_u64() as *mut PageTable;
+            let pml2 = Self::get_pt(pml3, virt.p3_index());
 
             if !(&(*pml2))[virt.p2_index()].flags().contains(PageTableFlags::PRESENT) {
                 return;
             }
 
-            let pml1 = (&(*pml2))[virt.p2_index()].addr().as_u64() as *mut PageTable;
+            let pml1 = Self::get_pt(pml2, virt.p2_index());
 
-            if !(&(*pml1))[virt.p1_index()].flags().contains(PageTableFlags::PRESENT) {
+            if !(&(*pml1))[virt.p1_index()]
+                .flags()
+                .intersects(PageTableFlags::PRESENT | NO_ACCESS)
+            {
                 return;
             }
 
             (&mut (*pml1))[virt.p1_index()].set_unused();
         }
+
+        self.flush(virt);
     }
 
     unsafe fn get_pt(pt: *mut PageTable, pt_index: PageTableIndex) -> *mut PageTable {
@@ -302,6 +309,31 @@ impl Pagemap {
 
     /// Only works with 4KiB pages
     pub fn translate(&self, virt: VirtAddr) -> Option<PhysAddr> {
+        let entry = self.get_entry(virt)?;
+
+        unsafe { Some((*entry).addr() + u64::from(virt.page_offset())) }
+    }
+
+    /// Returns the flags of the page containing `virt`, only works with 4KiB pages
+    pub fn page_flags(&self, virt: VirtAddr) -> Option<PageTableFlags> {
+        let entry = self.get_entry(virt)?;
+
+        unsafe { Some((*entry).flags()) }
+    }
+
+    /// Replaces the flags of an already mapped page, only works with 4KiB pages
+    pub fn set_page_flags(&mut self, virt: VirtAddr, flags: PageTableFlags) {
+        if let Some(entry) = self.get_entry(virt) {
+            unsafe {
+                (*entry).set_flags(flags);
+            }
+
+            self.flush(virt);
+        }
+    }
+
+    /// Walks the page tables and returns the level 1 entry for `virt`, if it is present or inaccessible
+    fn get_entry(&self, virt: VirtAddr) -> Option<*mut PageTableEntry> {
         let pml4 = self.page_table;
 
         unsafe {
@@ -323,33 +355,26 @@ impl Pagemap {
 
             let pml1 = Self::get_pt(pml2, virt.p2_index());
 
-            if !(&(*pml1))[virt.p1_index()].flags().contains(PageTableFlags::PRESENT) {
+            if !(&(*pml1))[virt.p1_index()]
+                .flags()
+                .intersects(PageTableFlags::PRESENT | NO_ACCESS)
+            {
                 return None;
             }
 
-            Some((&(*pml1))[virt.p1_index()].addr() + u64::from(virt.page_offset()))
+            Some(&mut (&mut (*pml1))[virt.p1_index()] as *mut PageTableEntry)
         }
     }
 
+    /// Frees the given page table and all page tables below it
+    ///
+    /// The frames mapped by the page table are not freed, they belong to the Vm of the process.
+    /// For the PML4 only the lower half is freed, since the higher half is shared with the kernel.
     fn deallocate_pt(pt: *mut PageTable, level: u8) {
-        if level == 4 {
-            for i in 0..256 {
-                unsafe {
-                    if (&(*pt))[i].flags().contains(PageTableFlags::PRESENT) {
-                        let pt = ((&(*pt))[i].addr().as_u64() + *HIGHER_HALF_OFFSET) as *mut PageTable;
+        let entries = if level == 4 { 0..256 } else { 0..512 };
 
-                        Self::deallocate_pt(pt, level - 1);
-                    }
-                }
-            }
-
-            unsafe {
-                FRAME_ALLOCATOR.lock().deallocate_frame(
-                    PhysFrame::<Size4KiB>::from_start_address(PhysAddr::new(pt as u64 - *HIGHER_HALF_OFFSET)).unwrap(),
-                );
-            }
-        } else if level > 1 {
-            for i in 0..512 {
+        if level > 1 {
+            for i in entries {
                 unsafe {
                     if (&(*pt))[i].flags().contains(PageTableFlags::PRESENT) {
                         let pt = ((&(*pt))[i].addr().as_u64() + *HIGHER_HALF_OFFSET) as *mut PageTable;
@@ -358,12 +383,12 @@ impl Pagemap {
                     }
                 }
             }
+        }
 
-            unsafe {
-                FRAME_ALLOCATOR.lock().deallocate_frame(
-                    PhysFrame::<Size4KiB>::from_start_address(PhysAddr::new(pt as u64 - *HIGHER_HALF_OFFSET)).unwrap(),
-                );
-            }
+        unsafe {
+            FRAME_ALLOCATOR.lock().deallocate_frame(
+                PhysFrame::<Size4KiB>::from_start_address(PhysAddr::new(pt as u64 - *HIGHER_HALF_OFFSET)).unwrap(),
+            );
         }
     }
 }
@@ -1,24 +1,42 @@
 use alloc::collections::BTreeMap;
-use libxernel::syscall::{MapFlags, ProtectionFlags};
-use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use libxernel::sync::Spinlock;
+use libxernel::syscall::{MapFlags, MountFlags, ProtectionFlags, SyscallError};
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
 use x86_64::{
     VirtAddr,
     structures::paging::{PageSize, Size4KiB},
 };
 
-use crate::cpu::current_process;
+use crate::arch::amd64::tlb;
+use crate::fs::{
+    self,
+    vnode::{VNode, VType},
+};
 use crate::mem::PROCESS_END;
 
 use super::frame::FRAME_ALLOCATOR;
-use super::{PROCESS_START, STACK_SIZE};
+use super::paging::{NO_ACCESS, Pagemap};
+use super::{HIGHER_HALF_OFFSET, PROCESS_START, STACK_SIZE};
+
+/// File which backs a vm entry
+#[derive(Clone)]
+pub struct MappedFile {
+    pub node: Arc<Spinlock<VNode>>,
+    /// Offset in the file at which the entry starts
+    pub offset: usize,
+    /// Whether the file was opened for writing, shared mappings may only be made writable then
+    pub writable: bool,
+}
 
+#[derive(Clone)]
 pub struct VmEntry {
     pub start: VirtAddr,
     pub length: usize,
     pub prot: ProtectionFlags,
     pub flags: MapFlags,
-    // TODO: add something to represent to which file this entry belongs to
-    file: Option<()>,
+    pub file: Option<MappedFile>,
 }
 
 impl VmEntry {
@@ -26,26 +44,190 @@ impl VmEntry {
         self.start + self.length as u64
     }
 
-    pub fn unmap(&self) {
-        let process = current_process();
-        let mut process = process.lock();
+    /// Offset in the backing file of the page at `addr`
+    ///
+    /// Returns `None` if the entry has no file or the offset doesn't fit into a `usize`
+    pub fn file_offset(&self, addr: VirtAddr) -> Option<usize> {
+        self.file.as_ref().and_then(|file| {
+            file.offset
+                .checked_add((addr.align_down(Size4KiB::SIZE) - self.start) as usize)
+        })
+    }
 
-        // SAFETY: only userspace processes should have Vm mappings
-        let page_mapper = process.get_page_table().as_mut().unwrap();
-        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    /// Writes the dirty pages between `start` and `end` back to the file, if this is a shared file mapping
+    pub fn sync(&self, page_mapper: &mut Pagemap, start: VirtAddr, end: VirtAddr) -> fs::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        if !self.flags.contains(MapFlags::SHARED) {
+            return Ok(());
+        }
+
+        let start = start.max(self.start).align_down(Size4KiB::SIZE);
+        let end = end.min(self.end());
+
+        let node = file.node.lock();
+        // the pages of regular files are the frames of the page cache, which writes them back
+        let cached = node.v_type() == VType::Regular;
+
+        for page in (start..end).step_by(Size4KiB::SIZE as usize) {
+            let Some(flags) = page_mapper.page_flags(page) else {
+                continue;
+            };
+
+            if !flags.contains(PageTableFlags::DIRTY) {
+                continue;
+            }
+
+            // UNWRAP: the entry has a file and mmap checked that its offsets don't overflow
+            let offset = self.file_offset(page).unwrap();
+
+            if cached {
+                node.dirty_page(offset);
+            } else {
+                // UNWRAP: the page is mapped, since it has flags
+                let phys_addr = page_mapper.translate(page).unwrap();
+
+                let data = unsafe {
+                    core::slice::from_raw_parts(
+                        (phys_addr.as_u64() + *HIGHER_HALF_OFFSET) as *const u8,
+                        Size4KiB::SIZE as usize,
+                    )
+                };
+
+                node.putpages(offset, data)?;
+            }
+
+            page_mapper.set_page_flags(page, flags - PageTableFlags::DIRTY);
+        }
+
+        if cached && start < end {
+            // UNWRAP: the entry has a file and mmap checked that its offsets don't overflow
+            node.sync_pages(
+                self.file_offset(start).unwrap(),
+                self.file_offset(end - 1u64).unwrap() + 1,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn unmap(&self, page_mapper: &mut Pagemap) {
+        if let Err(e) = self.sync(page_mapper, self.start, self.end()) {
+            error!("failed to write back mapping at {:x}: {:?}", self.start, e);
+        }
+
+        let mut pages = Vec::new();
+        let mut frames = Vec::new();
 
         for page in (self.start..self.end()).step_by(Size4KiB::SIZE as usize) {
             if let Some(phys_addr) = page_mapper.translate(page) {
-                unsafe {
-                    frame_allocator.deallocate_frame(PhysFrame::<Size4KiB>::containing_address(phys_addr));
-                }
+                pages.push(page);
+                frames.push(PhysFrame::<Size4KiB>::containing_address(phys_addr));
             }
 
             page_mapper.unmap(page);
         }
+
+        // other CPUs may still access the frames through their TLB until the shootdown is done
+        tlb::shootdown(page_mapper.pml4(), &pages);
+
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+
+        for frame in frames {
+            unsafe {
+                frame_allocator.deallocate_frame(frame);
+            }
+        }
+    }
+
+    /// Checks that the backing file allows the protection `prot`, as mmap does when the entry is created
+    ///
+    /// Fails with [`SyscallError::PermissionDenied`] if the file is on a file system mounted without execute
+    /// permission, or if a shared mapping would write to a file which is read-only.
+    fn check_file_protection(&self, prot: ProtectionFlags) -> Result<(), SyscallError> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        let node = file.node.lock();
+        let mount_flags = node.mount_flags();
+
+        if prot.contains(ProtectionFlags::EXECUTE) && mount_flags.contains(MountFlags::NO_EXEC) {
+            return Err(SyscallError::PermissionDenied);
+        }
+
+        // devices stay writable on a read-only file system
+        let read_only =
+            !file.writable || (node.v_type() == VType::Regular && mount_flags.contains(MountFlags::READ_ONLY));
+
+        if self.flags.contains(MapFlags::SHARED) && prot.contains(ProtectionFlags::WRITE) && read_only {
+            return Err(SyscallError::PermissionDenied);
+        }
+
+        Ok(())
+    }
+
+    /// Changes the protection of the entry and of all its mapped pages, including inaccessible ones
+    fn protect(&mut self, page_mapper: &mut Pagemap, prot: ProtectionFlags) {
+        self.prot = prot;
+
+        let pt_flags = ptflags_from_protflags(prot, true);
+        let mut pages = Vec::new();
+
+        for page in (self.start..self.end()).step_by(Size4KiB::SIZE as usize) {
+            let (Some(old_flags), Some(phys_addr)) = (page_mapper.page_flags(page), page_mapper.translate(page)) else {
+                continue;
+            };
+
+            let mut flags = pt_flags | (old_flags & (PageTableFlags::ACCESSED | PageTableFlags::DIRTY));
+            let frame = PhysFrame::<Size4KiB>::containing_address(phys_addr);
+
+            // private pages shared with a forked process have to stay copy-on-write
+            if !self.flags.contains(MapFlags::SHARED) && FRAME_ALLOCATOR.lock().ref_count(frame) > 1 {
+                flags -= PageTableFlags::WRITABLE;
+            }
+
+            page_mapper.set_page_flags(page, flags);
+            pages.push(page);
+        }
+
+        tlb::shootdown(page_mapper.pml4(), &pages);
+    }
+
+    /// Splits the entry at `addr`, the entry keeps the lower part and the upper part is returned
+    fn split_off(&mut self, addr: VirtAddr) -> VmEntry {
+        let mut upper = self.clone();
+
+        upper.start = addr;
+        upper.length = (self.end() - addr) as usize;
+
+        if let Some(file) = &mut upper.file {
+            file.offset += (addr - self.start) as usize;
+        }
+
+        self.length = (addr - self.start) as usize;
+
+        upper
+    }
+
+    /// Whether `next` directly follows this entry and both can be described by a single entry
+    fn can_merge(&self, next: &VmEntry) -> bool {
+        let same_file = match (&self.file, &next.file) {
+            (None, None) => true,
+            (Some(file), Some(next_file)) => {
+                Arc::ptr_eq(&file.node, &next_file.node)
+                    && file.offset.checked_add(self.length) == Some(next_file.offset)
+            }
+            _ => false,
+        };
+
+        self.end() == next.start && self.prot == next.prot && self.flags == next.flags && same_file
     }
 }
 
+#[derive(Clone)]
 pub struct Vm {
     entries: BTreeMap<VirtAddr, VmEntry>,
 }
@@ -69,126 +251,314 @@ impl Vm {
         self.entries.insert(start, entry);
     }
 
+    /// Like [`Vm::create_entry_at`], but the new entry is backed by the given file
+    pub fn create_file_entry_at(
+        &mut self,
+        start: VirtAddr,
+        length: usize,
+        prot: ProtectionFlags,
+        flags: MapFlags,
+        file: MappedFile,
+    ) -> Result<VirtAddr, SyscallError> {
+        let start = self.create_entry_at(start, length, prot, flags)?;
+
+        // UNWRAP: the entry was just created
+        self.entries.get_mut(&start).unwrap().file = Some(file);
+
+        Ok(start)
+    }
+
+    /// Whether the range lies in the process address space and keeps a guard page of distance to every entry
     pub fn is_available(&self, start: VirtAddr, length: usize) -> bool {
         let start = start.as_u64();
 
-        !self.entries.iter().any(|(_, entry)| {
-            entry.start.as_u64() < start && entry.end().as_u64() + Size4KiB::SIZE > start
-                || start + length as u64 + Size4KiB::SIZE > entry.start.as_u64()
-                    && (start + length as u64 + Size4KiB::SIZE) < entry.end().as_u64() + Size4KiB::SIZE
-        })
+        let Some(end) = start.checked_add(length as u64).filter(|end| *end <= PROCESS_END) else {
+            return false;
+        };
+
+        start >= PROCESS_START
+            && !self.entries.values().any(|entry| {
+                entry.start.as_u64() < end + Size4KiB::SIZE && entry.end().as_u64() + Size4KiB::SIZE > start
+            })
     }
 
-    pub fn create_entry_low(&mut self, length: usize, prot: ProtectionFlags, flags: MapFlags) -> VirtAddr {
+    pub fn create_entry_low(
+        &mut self,
+        length: usize,
+        prot: ProtectionFlags,
+        flags: MapFlags,
+    ) -> Result<VirtAddr, SyscallError> {
         self.create_entry_at(VirtAddr::new(PROCESS_START), length, prot, flags)
     }
 
-    pub fn create_entry_high(&mut self, length: usize, prot: ProtectionFlags, flags: MapFlags) -> VirtAddr {
-        let mut start_address = VirtAddr::new(PROCESS_END - length as u64);
-
-        loop {
-            if self.is_available(start_address, length) {
-                if start_address.as_u64() < PROCESS_START {
-                    panic!(
-                        "create_entry_high: {:x}(length = {}) is out of bounds",
-                        start_address, length
-                    );
-                }
+    /// Creates a new entry as high as possible in the process address space
+    ///
+    /// Fails with [`SyscallError::NoMemory`] if no free range is left
+    pub fn create_entry_high(
+        &mut self,
+        length: usize,
+        prot: ProtectionFlags,
+        flags: MapFlags,
+    ) -> Result<VirtAddr, SyscallError> {
+        let mut start_address = PROCESS_END.checked_sub(length as u64).ok_or(SyscallError::NoMemory)?;
 
-                self.add_entry(start_address, length, prot, flags);
-                return start_address;
+        while start_address >= PROCESS_START {
+            if self.is_available(VirtAddr::new(start_address), length) {
+                self.add_entry(VirtAddr::new(start_address), length, prot, flags);
+                return Ok(VirtAddr::new(start_address));
             }
 
             // NOTE: at the moment only a stack should be create at the high end of the process address space
-            start_address -= STACK_SIZE;
+            start_address = start_address.checked_sub(STACK_SIZE).ok_or(SyscallError::NoMemory)?;
         }
+
+        Err(SyscallError::NoMemory)
     }
 
     /// A new entry is created at the given address or higher
+    ///
+    /// Fails with [`SyscallError::NoMemory`] if no free range is left above `start`
     pub fn create_entry_at(
         &mut self,
-        mut start: VirtAddr,
+        start: VirtAddr,
         length: usize,
         prot: ProtectionFlags,
         flags: MapFlags,
-    ) -> VirtAddr {
-        if start.as_u64() + length as u64 > PROCESS_END {
-            panic!("create_entry_at: {:x}(length = {}) is out of bounds", start, length);
-        }
-
+    ) -> Result<VirtAddr, SyscallError> {
         if !start.is_aligned(Size4KiB::SIZE) {
             panic!("create_entry_at: {:x} is not aligned", start);
         }
 
-        if start.as_u64() < PROCESS_START {
-            start = VirtAddr::new(PROCESS_START);
+        let mut start = start.as_u64().max(PROCESS_START);
+
+        // the entries are sorted by their start, so the first gap at or above `start` which is large enough is used
+        for entry in self.entries.values() {
+            if entry.end().as_u64() + Size4KiB::SIZE <= start {
+                continue;
+            }
+
+            if self.is_available(VirtAddr::new(start), length) {
+                break;
+            }
+
+            start = entry.end().as_u64() + Size4KiB::SIZE;
         }
 
-        if self.is_available(start, length) {
-            self.add_entry(start, length, prot, flags);
-            return start;
+        if !self.is_available(VirtAddr::new(start), length) {
+            return Err(SyscallError::NoMemory);
         }
 
-        let mut values_iter = self.entries.values();
-        let mut previous = values_iter.next().unwrap();
-        let current = values_iter.next();
+        self.add_entry(VirtAddr::new(start), length, prot, flags);
 
-        if current.is_none() {
-            let new_start = previous.end() + Size4KiB::SIZE;
-            let new_start = new_start.align_up(Size4KiB::SIZE);
+        Ok(VirtAddr::new(start))
+    }
 
-            self.add_entry(new_start, length, prot, flags);
-            return new_start;
+    /// Creates a new entry at exactly the given address
+    ///
+    /// Unlike [`Vm::create_entry_at`] no guard page is kept to the neighbouring entries. Fails with
+    /// [`SyscallError::NoMemory`] if the range is outside of the process address space or overlaps an entry.
+    pub fn create_entry_fixed(
+        &mut self,
+        start: VirtAddr,
+        length: usize,
+        prot: ProtectionFlags,
+        flags: MapFlags,
+    ) -> Result<(), SyscallError> {
+        if !start.is_aligned(Size4KiB::SIZE) {
+            panic!("create_entry_fixed: {:x} is not aligned", start);
         }
 
-        let mut current = current.unwrap();
+        let end = start
+            .as_u64()
+            .checked_add(length as u64)
+            .filter(|end| *end <= PROCESS_END)
+            .ok_or(SyscallError::NoMemory)?;
 
-        loop {
-            if current.start - previous.end() >= length as u64 + 2 * Size4KiB::SIZE {
-                let new_start = previous.end() + Size4KiB::SIZE;
-                let new_start = new_start.align_up(Size4KiB::SIZE);
+        if start.as_u64() < PROCESS_START || self.entries_in_range(start, VirtAddr::new(end)).next().is_some() {
+            return Err(SyscallError::NoMemory);
+        }
 
-                self.add_entry(new_start, length, prot, flags);
-                return new_start;
-            }
+        self.add_entry(start, length, prot, flags);
 
-            previous = current;
-            let current_opt = values_iter.next();
+        Ok(())
+    }
 
-            if current_opt.is_none() {
-                let new_start = previous.end() + Size4KiB::SIZE;
-                let new_start = new_start.align_up(Size4KiB::SIZE);
+    pub fn get_entry_from_address(&self, addr: VirtAddr) -> Option<&VmEntry> {
+        self.entries
+            .iter()
+            .find(|(_, entry)| entry.start <= addr && entry.end() > addr)
+            .map(|(_, entry)| entry)
+    }
 
-                if new_start.as_u64() + length as u64 > PROCESS_END {
-                    panic!(
-                        "create_entry_at: {:x}(length = {}) is out of bounds! Vm space is exhausted",
-                        new_start, length
-                    );
+    /// Duplicates the address space for a forked process
+    ///
+    /// Every page which is mapped in `src` gets mapped at the same address in `dst`, the frames are shared.
+    /// Pages of private entries are write protected in both address spaces and copied on the first write.
+    pub fn fork(&self, src: &mut Pagemap, dst: &mut Pagemap) -> Vm {
+        for entry in self.entries.values() {
+            let pt_flags = ptflags_from_protflags(entry.prot, true);
+
+            for addr in (entry.start..entry.end()).step_by(Size4KiB::SIZE as usize) {
+                if let Some(phys_addr) = src.translate(addr) {
+                    let frame = PhysFrame::<Size4KiB>::containing_address(phys_addr);
+                    let page = Page::from_start_address(addr).unwrap();
+
+                    FRAME_ALLOCATOR.lock().share_frame(frame);
+
+                    if entry.flags.contains(MapFlags::SHARED) {
+                        dst.map(frame, page, pt_flags, false);
+                    } else {
+                        let cow_flags = pt_flags - PageTableFlags::WRITABLE;
+
+                        src.map(frame, page, cow_flags, true);
+                        dst.map(frame, page, cow_flags, false);
+                    }
                 }
+            }
+        }
+
+        self.clone()
+    }
 
-                self.add_entry(new_start, length, prot, flags);
-                return new_start;
+    /// Whether every address between `start` and `end` belongs to an entry
+    pub fn is_mapped(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        let mut covered = start;
+
+        for entry in self.entries_in_range(start, end) {
+            if entry.start > covered {
+                return false;
             }
 
-            current = current_opt.unwrap();
+            covered = entry.end();
         }
+
+        covered >= end
     }
 
-    pub fn get_entry_from_address(&self, addr: VirtAddr) -> Option<&VmEntry> {
+    /// Whether every address between `start` and `end` belongs to an entry which allows the access `prot`
+    pub fn is_accessible(&self, start: VirtAddr, end: VirtAddr, prot: ProtectionFlags) -> bool {
+        self.is_mapped(start, end) && self.entries_in_range(start, end).all(|entry| entry.prot.contains(prot))
+    }
+
+    fn entries_in_range(&self, start: VirtAddr, end: VirtAddr) -> impl Iterator<Item = &VmEntry> {
         self.entries
-            .iter()
-            .find(|(_, entry)| entry.start <= addr && entry.end() > addr)
-            .map(|(_, entry)| entry)
+            .values()
+            .filter(move |entry| entry.start < end && entry.end() > start)
+    }
+
+    /// Writes the dirty pages of shared file mappings between `start` and `end` back to their files
+    ///
+    /// Fails with [`SyscallError::NoMemory`] if a part of the range is not mapped
+    pub fn sync(&self, page_mapper: &mut Pagemap, start: VirtAddr, end: VirtAddr) -> Result<(), SyscallError> {
+        if !self.is_mapped(start, end) {
+            return Err(SyscallError::NoMemory);
+        }
+
+        for entry in self.entries_in_range(start, end) {
+            entry.sync(page_mapper, start, end)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the range between `start` and `end` from the address space
+    ///
+    /// Entries which only partially overlap the range are split, the part outside of the range stays mapped
+    pub fn unmap(&mut self, page_mapper: &mut Pagemap, start: VirtAddr, end: VirtAddr) {
+        self.split_at(start);
+        self.split_at(end);
+
+        let starts: Vec<VirtAddr> = self.entries_in_range(start, end).map(|entry| entry.start).collect();
+
+        for start in starts {
+            // UNWRAP: the key was just collected from the map
+            let entry = self.entries.remove(&start).unwrap();
+            entry.unmap(page_mapper);
+        }
+    }
+
+    /// Changes the protection of the range between `start` and `end`
+    ///
+    /// Fails with [`SyscallError::NoMemory`] if a part of the range is not mapped and with
+    /// [`SyscallError::PermissionDenied`] if the file of a mapping doesn't allow the new protection
+    pub fn protect(
+        &mut self,
+        page_mapper: &mut Pagemap,
+        start: VirtAddr,
+        end: VirtAddr,
+        prot: ProtectionFlags,
+    ) -> Result<(), SyscallError> {
+        if !self.is_mapped(start, end) {
+            return Err(SyscallError::NoMemory);
+        }
+
+        for entry in self.entries_in_range(start, end) {
+            entry.check_file_protection(prot)?;
+        }
+
+        self.split_at(start);
+        self.split_at(end);
+
+        for entry in self
+            .entries
+            .values_mut()
+            .filter(|entry| entry.start < end && entry.end() > start)
+        {
+            entry.protect(page_mapper, prot);
+        }
+
+        self.merge_entries();
+
+        Ok(())
+    }
+
+    /// Splits the entry containing `addr`, so that an entry starts at `addr`
+    fn split_at(&mut self, addr: VirtAddr) {
+        let entry = self
+            .entries
+            .values_mut()
+            .find(|entry| entry.start < addr && entry.end() > addr);
+
+        if let Some(entry) = entry {
+            let upper = entry.split_off(addr);
+            self.entries.insert(upper.start, upper);
+        }
     }
 
-    pub fn clean_up(&mut self) {
-        self.entries.values().for_each(|value| value.unmap());
+    /// Merges neighbouring entries which only differ in their start address
+    fn merge_entries(&mut self) {
+        let entries = core::mem::take(&mut self.entries);
+        let mut merged: Option<VmEntry> = None;
+
+        for entry in entries.into_values() {
+            match merged.as_mut() {
+                Some(previous) if previous.can_merge(&entry) => previous.length += entry.length,
+                _ => {
+                    if let Some(previous) = merged.replace(entry) {
+                        self.entries.insert(previous.start, previous);
+                    }
+                }
+            }
+        }
+
+        if let Some(previous) = merged {
+            self.entries.insert(previous.start, previous);
+        }
+    }
+
+    pub fn clean_up(&mut self, page_mapper: &mut Pagemap) {
+        self.entries.values().for_each(|value| value.unmap(page_mapper));
         self.entries.clear();
     }
 }
 
 pub fn ptflags_from_protflags(flags: ProtectionFlags, user_accessible: bool) -> PageTableFlags {
-    let mut new_flags = PageTableFlags::PRESENT;
+    // inaccessible pages keep their frame, but any access to them faults
+    let mut new_flags = if flags.is_empty() {
+        NO_ACCESS
+    } else {
+        PageTableFlags::PRESENT
+    };
 
     if user_accessible {
         new_flags |= PageTableFlags::USER_ACCESSIBLE;
@@ -212,6 +582,13 @@ pub fn ptflags_from_protflags(flags: ProtectionFlags, user_accessible: bool) ->
 pub fn protflags_from_ptflags(flags: PageTableFlags) -> ProtectionFlags {
     let mut new_flags = ProtectionFlags::empty();
 
+    if !flags.contains(PageTableFlags::PRESENT) {
+        return new_flags;
+    }
+
+    // NOTE: present pages are always readable
+    new_flags |= ProtectionFlags::READ;
+
     if flags.contains(PageTableFlags::WRITABLE) {
         new_flags |= ProtectionFlags::WRITE;
     }
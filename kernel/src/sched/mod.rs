@@ -1,4 +1,6 @@
 pub mod context;
 pub mod process;
+pub mod process_syscalls;
 pub mod scheduler;
 pub mod thread;
+pub mod wait_queue;
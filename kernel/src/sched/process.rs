@@ -1,16 +1,20 @@
+use alloc::string::String;
 use alloc::sync::Weak;
 use core::sync::atomic::{AtomicUsize, Ordering};
-use libxernel::syscall::{MapFlags, ProtectionFlags};
-use x86_64::structures::paging::{Page, PageSize, PageTableFlags, Size4KiB};
+use elf::ParseError;
+use elf::endian::NativeEndian;
+use libxernel::syscall::{
+    AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, MapFlags, ProtectionFlags, SyscallError,
+};
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size4KiB};
 use x86_64::{VirtAddr, align_down, align_up};
 
 use crate::VFS;
-use crate::fs::file::File;
+use crate::fs::fd_table::FdTable;
 use crate::fs::vnode::VNode;
-use crate::mem::frame::FRAME_ALLOCATOR;
+use crate::mem::page_cache;
 use crate::mem::vm::{Vm, protflags_from_ptflags};
-use crate::mem::{HIGHER_HALF_OFFSET, KERNEL_THREAD_STACK_TOP, STACK_SIZE};
-use alloc::collections::BTreeMap;
+use crate::mem::{HIGHER_HALF_OFFSET, KERNEL_THREAD_STACK_TOP, PROCESS_END, PROCESS_START, STACK_SIZE};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
@@ -18,24 +22,46 @@ use libxernel::sync::{Once, Spinlock};
 
 use crate::mem::paging::{KERNEL_PAGE_MAPPER, Pagemap};
 use crate::sched::thread::Thread;
+use crate::sched::wait_queue::WaitQueue;
 
 /// Ongoing counter for the ProcessID
 static PROCESS_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
+/// Top of the next kernel stack
+///
+/// The kernel stacks of all processes live in the shared kernel address space, so they have to be allocated globally
+static KERNEL_STACK_TOP: AtomicUsize = AtomicUsize::new(KERNEL_THREAD_STACK_TOP as usize);
+
+/// Tops of kernel stacks whose threads were dropped, their address range is reused before a new one is taken
+static FREE_KERNEL_STACKS: Spinlock<Vec<usize>> = Spinlock::new(Vec::new());
+
 pub static KERNEL_PROCESS: Once<Arc<Spinlock<Process>>> = Once::new();
 
+/// The first user process, orphaned processes get reparented to it
+pub static INIT_PROCESS: Once<Arc<Spinlock<Process>>> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Current status of the process
+pub enum ProcessStatus {
+    Running,
+    /// The process exited, but its parent did not collect the exit status yet
+    Zombie(i32),
+}
+
 pub struct Process {
     pub pid: usize,
     /// A kernel process has no page table
     pub page_table: Option<Pagemap>,
     pub parent: Weak<Spinlock<Process>>,
     pub children: Vec<Arc<Spinlock<Process>>>,
-    pub threads: Vec<Arc<Spinlock<Thread>>>,
-    pub fds: BTreeMap<usize, File>,
-    pub kernel_thread_stack_top: usize,
+    pub threads: Vec<Arc<Thread>>,
+    pub fds: FdTable,
     pub thread_id_counter: usize,
     pub vm: Vm,
     pub cwd: Arc<Spinlock<VNode>>,
+    pub status: ProcessStatus,
+    /// Threads waiting in wait4 for a child to exit
+    pub child_wait_queue: WaitQueue,
 }
 
 impl Process {
@@ -54,24 +80,126 @@ impl Process {
             parent,
             children: Vec::new(),
             threads: Vec::new(),
-            fds: BTreeMap::new(),
-            kernel_thread_stack_top: KERNEL_THREAD_STACK_TOP as usize,
+            fds: FdTable::new(),
             thread_id_counter: 0,
             vm: Vm::new(),
             cwd: VFS.lock().root_node(),
+            status: ProcessStatus::Running,
+            child_wait_queue: WaitQueue::new(),
+        }
+    }
+
+    /// Releases the resources of the process and turns it into a zombie
+    ///
+    /// The page table is kept until the parent reaped the process, since the exiting thread may still run on it.
+    /// Returns the children of the process, which have to be reparented by the caller. The file descriptors are
+    /// left to the caller as well, since closing a file must not happen with the process locked.
+    pub fn exit(&mut self, status: i32) -> Vec<Arc<Spinlock<Process>>> {
+        if let Some(page_table) = self.page_table.as_mut() {
+            self.vm.clean_up(page_table);
+        }
+
+        self.threads.clear();
+        self.status = ProcessStatus::Zombie(status);
+
+        core::mem::take(&mut self.children)
+    }
+
+    /// Removes an exited child from the children of this process
+    ///
+    /// `pid` selects the child, -1 matches any child. Returns the pid and exit status of the reaped child,
+    /// `Ok(None)` if no matching child exited yet and an error if there is no matching child at all.
+    pub fn reap_child(&mut self, pid: isize) -> Result<Option<(usize, i32)>, SyscallError> {
+        let mut found = false;
+
+        for (i, child) in self.children.iter().enumerate() {
+            let child = child.lock();
+
+            if pid != -1 && child.pid != pid as usize {
+                continue;
+            }
+
+            found = true;
+
+            if let ProcessStatus::Zombie(status) = child.status {
+                let child_pid = child.pid;
+                drop(child);
+
+                // dropping the last reference frees the page table of the child
+                self.children.remove(i);
+                return Ok(Some((child_pid, status)));
+            }
+        }
+
+        if found {
+            Ok(None)
+        } else {
+            Err(SyscallError::NoChildProcess)
         }
     }
 
+    /// Creates a copy of this process, used by the fork syscall
+    ///
+    /// The address space gets duplicated and the file descriptors are inherited, threads are not copied
+    pub fn fork(&mut self, parent: Arc<Spinlock<Process>>) -> Self {
+        let mut child = Process::new(Some(parent));
+
+        // SAFETY: only user processes can fork and a user process always has a page table
+        child.vm = self
+            .vm
+            .fork(self.page_table.as_mut().unwrap(), child.page_table.as_mut().unwrap());
+        child.fds = self.fds.clone();
+        child.cwd = self.cwd.clone();
+
+        child
+    }
+
+    /// Replaces the program image of this process, used by the execve syscall
+    ///
+    /// Returns the entry point and the stack pointer the new program should start with. The caller closes the
+    /// descriptors marked as close-on-exec, since closing a file must not happen with the process locked.
+    ///
+    /// Only running out of memory can fail after the old image was torn down, the exec then fails with `ENOMEM`.
+    pub fn exec(
+        &mut self,
+        elf_data: &[u8],
+        argv: &[String],
+        envp: &[String],
+    ) -> Result<(VirtAddr, usize), SyscallError> {
+        // check the new image before the old one gets torn down, so a failed exec returns to the old program
+        let elf = Self::parse_elf(elf_data).map_err(|_| SyscallError::ExecFormatError)?;
+
+        let mut auxv = Vec::new();
+
+        if let Some(phdr) = Self::phdr_address(&elf) {
+            auxv.push((AT_PHDR, phdr));
+        }
+
+        auxv.push((AT_PHENT, elf.ehdr.e_phentsize as u64));
+        auxv.push((AT_PHNUM, elf.ehdr.e_phnum as u64));
+        auxv.push((AT_PAGESZ, Size4KiB::SIZE));
+        auxv.push((AT_ENTRY, elf.ehdr.e_entry));
+
+        // SAFETY: only user processes can exec and a user process always has a page table
+        self.vm.clean_up(self.page_table.as_mut().unwrap());
+
+        let entry_point = self.load_elf(elf_data)?;
+        let stack_pointer = self.new_user_stack_with_args(argv, envp, &auxv)?;
+
+        Ok((entry_point, stack_pointer))
+    }
+
     pub fn new_kernel_stack(&mut self) -> usize {
-        let stack_top = self.kernel_thread_stack_top;
-        self.kernel_thread_stack_top -= STACK_SIZE as usize;
-        let stack_bottom = self.kernel_thread_stack_top;
+        let free_stack = FREE_KERNEL_STACKS.lock().pop();
 
-        // create guard page
-        self.kernel_thread_stack_top -= Size4KiB::SIZE as usize;
+        // one extra page is reserved below the stack as guard page
+        let stack_top = free_stack.unwrap_or_else(|| {
+            KERNEL_STACK_TOP.fetch_sub(STACK_SIZE as usize + Size4KiB::SIZE as usize, Ordering::AcqRel)
+        });
+        let stack_bottom = stack_top - STACK_SIZE as usize;
 
         for addr in (stack_bottom..stack_top).step_by(Size4KiB::SIZE as usize) {
-            let phys_page = FRAME_ALLOCATOR.lock().allocate_frame::<Size4KiB>().unwrap();
+            let phys_page = page_cache::allocate_frame::<Size4KiB>().unwrap();
             let virt_page = Page::from_start_address(VirtAddr::new(addr as u64)).unwrap();
 
             KERNEL_PAGE_MAPPER.lock().map(
@@ -85,19 +213,24 @@ impl Process {
         stack_top
     }
 
-    pub fn new_user_stack(&mut self) -> usize {
+    /// Makes the address range of a kernel stack available again, after its pages were unmapped
+    pub fn release_kernel_stack(stack_top: usize) {
+        FREE_KERNEL_STACKS.lock().push(stack_top);
+    }
+
+    pub fn new_user_stack(&mut self) -> Result<usize, SyscallError> {
         let stack_bottom = self
             .vm
             .create_entry_high(
                 STACK_SIZE as usize,
                 ProtectionFlags::READ | ProtectionFlags::WRITE,
                 MapFlags::ANONYMOUS,
-            )
+            )?
             .as_u64() as usize;
         let stack_top = STACK_SIZE as usize + stack_bottom;
 
         for addr in (stack_bottom..stack_top).step_by(Size4KiB::SIZE as usize) {
-            let phys_page = FRAME_ALLOCATOR.lock().allocate_frame::<Size4KiB>().unwrap();
+            let phys_page = page_cache::allocate_frame::<Size4KiB>().ok_or(SyscallError::NoMemory)?;
             let virt_page = Page::from_start_address(VirtAddr::new(addr as u64)).unwrap();
 
             self.page_table.as_mut().unwrap().map(
@@ -111,17 +244,154 @@ impl Process {
             );
         }
 
-        stack_top
+        Ok(stack_top)
+    }
+
+    /// Creates a new user stack which holds the program arguments, the environment and the auxiliary vector
+    ///
+    /// Returns the stack pointer, which points to `argc` as expected by the System V ABI
+    pub fn new_user_stack_with_args(
+        &mut self,
+        argv: &[String],
+        envp: &[String],
+        auxv: &[(u64, u64)],
+    ) -> Result<usize, SyscallError> {
+        let mut stack_pointer = self.new_user_stack()?;
+
+        let mut argv_ptrs = Vec::new();
+        let mut envp_ptrs = Vec::new();
+
+        for (strings, ptrs) in [(argv, &mut argv_ptrs), (envp, &mut envp_ptrs)] {
+            for string in strings {
+                let mut bytes = string.clone().into_bytes();
+                bytes.push(0);
+
+                stack_pointer -= bytes.len();
+                self.write_user_memory(VirtAddr::new(stack_pointer as u64), &bytes);
+                ptrs.push(stack_pointer as u64);
+            }
+        }
+
+        stack_pointer = align_down(stack_pointer as u64, 16) as usize;
+
+        let mut words = Vec::new();
+
+        words.push(argv.len() as u64);
+        words.extend(argv_ptrs);
+        words.push(0);
+        words.extend(envp_ptrs);
+        words.push(0);
+
+        for (key, value) in auxv {
+            words.push(*key);
+            words.push(*value);
+        }
+
+        words.push(AT_NULL);
+        words.push(0);
+
+        // the stack pointer has to be 16 byte aligned when the program starts
+        if words.len() % 2 != 0 {
+            stack_pointer -= 8;
+        }
+
+        stack_pointer -= words.len() * 8;
+
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        self.write_user_memory(VirtAddr::new(stack_pointer as u64), &bytes);
+
+        Ok(stack_pointer)
+    }
+
+    /// Copies `data` into the user address space of this process
+    ///
+    /// The target pages have to be mapped already
+    pub fn write_user_memory(&self, addr: VirtAddr, data: &[u8]) {
+        let page_table = self.page_table.as_ref().unwrap();
+
+        let mut written = 0;
+
+        while written < data.len() {
+            let virt = addr + written as u64;
+            let page_offset = u64::from(virt.page_offset()) as usize;
+            let len = (data.len() - written).min(Size4KiB::SIZE as usize - page_offset);
+
+            let phys = page_table
+                .translate(virt)
+                .expect("write_user_memory: page is not mapped");
+
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data[written..].as_ptr(),
+                    (phys.as_u64() + *HIGHER_HALF_OFFSET) as *mut u8,
+                    len,
+                );
+            }
+
+            written += len;
+        }
+    }
+
+    /// Parses an ELF file and checks that all loadable segments are inside of the file and of the user address space
+    ///
+    /// The loadable segments also have to be sorted by their address without overlapping, as the ELF specification
+    /// requires.
+    fn parse_elf(elf_data: &[u8]) -> Result<elf::ElfBytes<'_, NativeEndian>, ParseError> {
+        let elf = elf::ElfBytes::<NativeEndian>::minimal_parse(elf_data)?;
+
+        let segments = elf.segments().ok_or(ParseError::BadOffset(elf.ehdr.e_phoff))?;
+
+        let mut previous_end = PROCESS_START;
+
+        for ph in segments.iter().filter(|ph| ph.p_type == elf::abi::PT_LOAD) {
+            let end = ph
+                .p_offset
+                .checked_add(ph.p_filesz)
+                .ok_or(ParseError::IntegerOverflow)?;
+
+            if end > elf_data.len() as u64 || ph.p_filesz > ph.p_memsz {
+                return Err(ParseError::SliceReadError((ph.p_offset as usize, end as usize)));
+            }
+
+            let mem_end = ph.p_vaddr.checked_add(ph.p_memsz).ok_or(ParseError::IntegerOverflow)?;
+
+            if ph.p_vaddr < previous_end || align_up(mem_end, Size4KiB::SIZE) > PROCESS_END {
+                return Err(ParseError::BadOffset(ph.p_vaddr));
+            }
+
+            previous_end = mem_end;
+        }
+
+        Ok(elf)
+    }
+
+    /// Returns the address at which the program headers are mapped
+    fn phdr_address(elf: &elf::ElfBytes<'_, NativeEndian>) -> Option<u64> {
+        let segments = elf.segments()?;
+
+        if let Some(phdr) = segments.iter().find(|ph| ph.p_type == elf::abi::PT_PHDR) {
+            return Some(phdr.p_vaddr);
+        }
+
+        segments
+            .iter()
+            .find(|ph| ph.p_type == elf::abi::PT_LOAD && ph.p_offset == 0)
+            .map(|ph| ph.p_vaddr + elf.ehdr.e_phoff)
     }
 
     /// Load an ELF file into the process memory
     ///
     /// Returns the entry point of the ELF file
-    pub fn load_elf(&mut self, elf_data: &[u8]) -> VirtAddr {
-        let elf = elf::ElfBytes::<elf::endian::NativeEndian>::minimal_parse(elf_data).expect("Failed to parse ELF");
+    pub fn load_elf(&mut self, elf_data: &[u8]) -> Result<VirtAddr, SyscallError> {
+        let elf = Self::parse_elf(elf_data).map_err(|_| SyscallError::ExecFormatError)?;
 
-        for ph in elf.segments().expect("Failed to get program headers") {
+        // end of the pages of the previous segment, the first page of a segment may be shared with it
+        let mut mapped_end = 0;
+
+        // UNWRAP: parse_elf already checked that the program headers exist
+        for ph in elf.segments().unwrap() {
             if ph.p_type == elf::abi::PT_LOAD {
+                // parse_elf checked that the segment lies inside of the user address space, so this can't overflow
                 let start = ph.p_vaddr;
                 let end = start + ph.p_memsz;
 
@@ -138,41 +408,65 @@ impl Process {
                 }
 
                 for addr in (page_start..page_end).step_by(Size4KiB::SIZE as usize) {
-                    let phys_page = FRAME_ALLOCATOR.lock().allocate_frame::<Size4KiB>().unwrap();
-                    let virt_page = Page::from_start_address(VirtAddr::new(addr)).unwrap();
-
-                    self.page_table
-                        .as_mut()
-                        .unwrap()
-                        .map(phys_page, virt_page, flags, false);
-
-                    // write data to the page
-                    let page_offset = if addr.overflowing_sub(start).1 { start - addr } else { 0 };
-                    let data_len = Size4KiB::SIZE - page_offset;
-                    let segment_offset: u64 = addr + page_offset - start;
+                    let page_table = self.page_table.as_mut().unwrap();
+
+                    // segments which share a page also share the frame
+                    let phys_page = if let Some(phys_addr) = page_table.translate(VirtAddr::new(addr)) {
+                        PhysFrame::<Size4KiB>::containing_address(phys_addr)
+                    } else {
+                        let phys_page = page_cache::allocate_frame::<Size4KiB>().ok_or(SyscallError::NoMemory)?;
+                        let virt_page = Page::from_start_address(VirtAddr::new(addr)).unwrap();
+
+                        // the part of the segment which is not backed by the file (.bss) has to be zeroed
+                        unsafe {
+                            core::ptr::write_bytes(
+                                (phys_page.start_address().as_u64() + *HIGHER_HALF_OFFSET) as *mut u8,
+                                0,
+                                Size4KiB::SIZE as usize,
+                            );
+                        }
+
+                        page_table.map(phys_page, virt_page, flags, false);
+
+                        phys_page
+                    };
+
+                    // write the part of the segment's file data which lies in this page
+                    let copy_start = addr.max(start);
+                    let copy_end = (addr + Size4KiB::SIZE).min(start + ph.p_filesz);
+
+                    if copy_start >= copy_end {
+                        continue;
+                    }
 
-                    let data = &elf_data
-                        [(ph.p_offset + segment_offset) as usize..(ph.p_offset + segment_offset + data_len) as usize];
+                    let file_offset = (ph.p_offset + copy_start - start) as usize;
+                    let data = &elf_data[file_offset..file_offset + (copy_end - copy_start) as usize];
 
                     unsafe {
                         core::ptr::copy(
                             data.as_ptr(),
-                            (phys_page.start_address().as_u64() + page_offset + *HIGHER_HALF_OFFSET) as *mut u8,
-                            data_len as usize,
+                            (phys_page.start_address().as_u64() + copy_start - addr + *HIGHER_HALF_OFFSET) as *mut u8,
+                            data.len(),
                         );
                     }
                 }
 
-                self.vm.create_entry_at(
-                    VirtAddr::new(page_start),
-                    (page_end - page_start) as usize,
-                    protflags_from_ptflags(flags),
-                    MapFlags::ANONYMOUS,
-                );
+                let entry_start = page_start.max(mapped_end);
+
+                if entry_start < page_end {
+                    self.vm.create_entry_fixed(
+                        VirtAddr::new(entry_start),
+                        (page_end - entry_start) as usize,
+                        protflags_from_ptflags(flags),
+                        MapFlags::ANONYMOUS,
+                    )?;
+                }
+
+                mapped_end = page_end;
             }
         }
 
-        VirtAddr::new(elf.ehdr.e_entry)
+        Ok(VirtAddr::new(elf.ehdr.e_entry))
     }
 
     pub fn next_tid(&mut self) -> usize {
@@ -182,25 +476,6 @@ impl Process {
         tid
     }
 
-    pub fn append_fd(&mut self, file_handle: File) -> u32 {
-        let mut counter = 0;
-
-        let fd = loop {
-            if let alloc::collections::btree_map::Entry::Vacant(e) = self.fds.entry(counter) {
-                e.insert(file_handle);
-                break counter;
-            }
-
-            counter += 1;
-        };
-
-        fd as u32
-    }
-
-    pub fn get_filehandle_from_fd(&self, fd: usize) -> &File {
-        self.fds.get(&fd).expect("Failed to get FileHandle for fd")
-    }
-
     pub fn get_page_table(&mut self) -> &mut Option<Pagemap> {
         &mut self.page_table
     }
@@ -212,6 +487,8 @@ impl Process {
 
 impl Drop for Process {
     fn drop(&mut self) {
-        self.vm.clean_up();
+        if let Some(page_table) = self.page_table.as_mut() {
+            self.vm.clean_up(page_table);
+        }
     }
 }
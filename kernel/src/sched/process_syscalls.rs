@@ -0,0 +1,198 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::Ordering;
+use libxernel::{
+    sync::Spinlock,
+    syscall::{MountFlags, OpenFlags, SyscallError, WaitOptions},
+};
+
+use crate::{
+    arch::amd64::hcf,
+    cpu::{current_cpu, current_process, current_thread},
+    fs::vfs::VFS,
+    mem::paging::KERNEL_PAGE_MAPPER,
+    syscall::{Result, SyscallData, user_ptr::UserPtr},
+};
+
+use super::{
+    context::TrapFrame,
+    process::{INIT_PROCESS, ProcessStatus},
+    thread::{Thread, ThreadStatus},
+    wait_queue::sleep,
+};
+
+pub fn sys_fork(data: &SyscallData) -> Result<isize> {
+    let parent = current_process();
+
+    let child = parent.lock().fork(parent.clone());
+    let child_pid = child.pid;
+    let child = Arc::new(Spinlock::new(child));
+
+    parent.lock().children.push(child.clone());
+
+    let thread = Arc::new(Thread::user_thread_from_trap_frame(
+        child.clone(),
+        fork_trap_frame(data),
+    ));
+    child.lock().threads.push(thread.clone());
+
+    current_cpu().enqueue_thread(thread);
+
+    Ok(child_pid as isize)
+}
+
+pub fn sys_execve(path: String, argv: Vec<String>, envp: Vec<String>, data: &mut SyscallData) -> Result<isize> {
+    let elf_data = read_file(path)?;
+
+    let process = current_process();
+
+    let (entry_point, stack_pointer, closed) = {
+        let mut process = process.lock();
+        let (entry_point, stack_pointer) = process.exec(&elf_data, &argv, &envp)?;
+
+        (entry_point, stack_pointer, process.fds.remove_close_on_exec())
+    };
+
+    // the files are closed once no other process refers to them anymore, which must not happen with the process
+    // locked
+    drop(closed);
+
+    // the syscall returns directly into the new program
+    data.return_address = entry_point.as_u64() as usize;
+    data.user_rsp = stack_pointer;
+
+    Ok(0)
+}
+
+pub fn sys_exit(status: usize) -> ! {
+    let process = current_process();
+
+    if Arc::ptr_eq(&process, &INIT_PROCESS) {
+        panic!("init process exited with status {}", status as i32);
+    }
+
+    // the page table of the process gets freed as soon as the parent reaped it, so it can't stay loaded
+    {
+        let kernel_page_mapper = KERNEL_PAGE_MAPPER.lock();
+
+        unsafe {
+            kernel_page_mapper.load_pt();
+        }
+
+        current_cpu()
+            .loaded_pml4
+            .store(kernel_page_mapper.pml4().as_u64(), Ordering::Release);
+    }
+
+    let (parent, children, closed) = {
+        let mut process = process.lock();
+        let closed = process.fds.clear();
+        let children = process.exit(status as i32);
+
+        (process.parent.upgrade(), children, closed)
+    };
+
+    // see sys_execve, the files must not be closed with the process locked
+    drop(closed);
+
+    let mut zombie_reparented = false;
+
+    for child in children {
+        {
+            let mut child = child.lock();
+            child.parent = Arc::downgrade(&INIT_PROCESS);
+            zombie_reparented |= matches!(child.status, ProcessStatus::Zombie(_));
+        }
+
+        INIT_PROCESS.lock().children.push(child);
+    }
+
+    if zombie_reparented {
+        INIT_PROCESS.lock().child_wait_queue.wake_all();
+    }
+
+    if let Some(parent) = parent {
+        parent.lock().child_wait_queue.wake_all();
+    }
+
+    // the scheduler drops the thread once it switched away from it
+    current_thread().status.set(ThreadStatus::Done);
+    drop(process);
+
+    hcf();
+}
+
+pub fn sys_wait4(pid: isize, status: UserPtr<i32>, options: usize) -> Result<isize> {
+    let options = WaitOptions::from_bits(options).ok_or(SyscallError::InvalidArgument)?;
+
+    if pid != -1 && pid <= 0 {
+        // process groups are not supported
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let process = current_process();
+
+    let (child_pid, exit_status) = loop {
+        let thread = {
+            let mut process = process.lock();
+
+            match process.reap_child(pid)? {
+                Some(child) => break child,
+                None if options.contains(WaitOptions::NOHANG) => return Ok(0),
+                None => process.child_wait_queue.prepare_to_wait(),
+            }
+        };
+
+        sleep(&thread);
+    };
+
+    if !status.is_null() {
+        status.write(&((exit_status & 0xff) << 8))?;
+    }
+
+    Ok(child_pid as isize)
+}
+
+/// Builds the register state of a forked child, which returns from the syscall with 0
+fn fork_trap_frame(data: &SyscallData) -> TrapFrame {
+    let mut trap_frame = TrapFrame::new();
+
+    trap_frame.ss = 0x2b; // user stack segment
+    trap_frame.cs = 0x33; // user code segment
+    trap_frame.rip = data.return_address as u64;
+    trap_frame.rsp = data.user_rsp as u64;
+    trap_frame.rflags = data.eflags as u64;
+
+    trap_frame.rax = 0;
+    trap_frame.rdi = data.arg0 as u64;
+    trap_frame.rsi = data.arg1 as u64;
+    trap_frame.rdx = data.arg2 as u64;
+    trap_frame.r10 = data.arg3 as u64;
+    trap_frame.r8 = data.arg4 as u64;
+    trap_frame.r9 = data.arg5 as u64;
+
+    // syscall clobbers rcx and r11 with the return address and rflags
+    trap_frame.rcx = data.return_address as u64;
+    trap_frame.r11 = data.eflags as u64;
+
+    trap_frame.rbx = data.rbx as u64;
+    trap_frame.rbp = data.rbp as u64;
+    trap_frame.r12 = data.r12 as u64;
+    trap_frame.r13 = data.r13 as u64;
+    trap_frame.r14 = data.r14 as u64;
+    trap_frame.r15 = data.r15 as u64;
+
+    trap_frame
+}
+
+fn read_file(path: String) -> Result<Vec<u8>> {
+    let cwd = current_process().lock().cwd.clone();
+    let node = VFS.lock().vn_open(&cwd, path, OpenFlags::empty(), 0)?;
+
+    if node.lock().mount_flags().contains(MountFlags::NO_EXEC) {
+        return Err(SyscallError::PermissionDenied);
+    }
+
+    let data = node.lock().read_all()?;
+
+    Ok(data)
+}
@@ -3,6 +3,7 @@ use crate::arch::amd64::switch_context;
 use crate::cpu::current_cpu;
 use crate::timer::timer_event::TimerEvent;
 use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
 use core::time::Duration;
 use x86_64::registers::control::Cr3;
 use x86_64::registers::segmentation::{DS, Segment};
@@ -12,7 +13,11 @@ use super::thread::{Thread, ThreadStatus};
 pub fn reschedule(_: ()) {
     let cpu = current_cpu();
 
-    let next_ref = cpu.run_queue.aquire().pop_front();
+    // the threads in this list were switched away from for the last time, so their stacks can be freed now
+    let dead_threads = core::mem::take(&mut **cpu.dead_threads.aquire());
+    drop(dead_threads);
+
+    let next_ref = next_runnable_thread();
 
     let current_ref = cpu.current_thread.aquire().clone();
 
@@ -24,9 +29,7 @@ pub fn reschedule(_: ()) {
     };
 
     let new = if let Some(next_thread) = next_ref {
-        cpu.run_queue.aquire().push_back(next_thread.clone());
-
-        next_thread.clone()
+        next_thread
     } else {
         cpu.idle_thread.clone()
     };
@@ -40,6 +43,29 @@ pub fn reschedule(_: ()) {
     **cpu.next.aquire() = Some(new);
 }
 
+/// Rotates the run queue until a runnable thread is found
+///
+/// Sleeping threads stay in the run queue but are skipped, exited threads are removed from it
+fn next_runnable_thread() -> Option<Arc<Thread>> {
+    let cpu = current_cpu();
+    let mut run_queue = cpu.run_queue.aquire();
+
+    for _ in 0..run_queue.len() {
+        let thread = run_queue.pop_front()?;
+
+        match thread.status.get() {
+            ThreadStatus::Done => continue,
+            ThreadStatus::Sleeping | ThreadStatus::BlockingOnIo => run_queue.push_back(thread),
+            _ => {
+                run_queue.push_back(thread.clone());
+                return Some(thread);
+            }
+        }
+    }
+
+    None
+}
+
 pub fn enqueue_thread(thread: Thread) {
     current_cpu().run_queue.aquire().push_back(Arc::new(thread));
 }
@@ -60,7 +86,10 @@ pub fn dequeue_thread(thread: Arc<Thread>) -> Option<Arc<Thread>> {
 }
 
 pub fn switch_threads(old: Arc<Thread>, new: Arc<Thread>) {
-    old.status.set(ThreadStatus::Ready);
+    // sleeping and exited threads keep their status, so they are not picked again
+    if old.status.get() == ThreadStatus::Running {
+        old.status.set(ThreadStatus::Ready);
+    }
 
     new.status.set(ThreadStatus::Running);
 
@@ -78,6 +107,7 @@ pub fn switch_threads(old: Arc<Thread>, new: Arc<Thread>) {
 
             if cr3 != pt.pml4().as_u64() {
                 pt.load_pt();
+                current_cpu().loaded_pml4.store(pt.pml4().as_u64(), Ordering::Release);
             }
 
             DS::set_reg(GDT_BSP.1.user_data_selector);
@@ -92,8 +122,15 @@ pub fn switch_threads(old: Arc<Thread>, new: Arc<Thread>) {
 
     **current_cpu().current_thread.aquire() = Some(new.clone());
 
+    let old_context = old.context.get();
+
+    // an exited thread never returns from switch_context, so it can't drop its own reference
+    if old.status.get() == ThreadStatus::Done {
+        current_cpu().dead_threads.aquire().push(old);
+    }
+
     unsafe {
-        switch_context(old.context.get(), *new.context.get());
+        switch_context(old_context, *new.context.get());
     }
 }
 
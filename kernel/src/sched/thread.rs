@@ -53,6 +53,8 @@ impl ThreadPriority {
 pub struct KernelStack {
     pub user_space_stack: usize,
     pub kernel_stack_top: usize,
+    /// Highest address of the kernel stack, as returned by [`Process::new_kernel_stack`]
+    pub kernel_stack_end: usize,
 }
 
 pub struct Thread {
@@ -137,8 +139,10 @@ impl Thread {
     }
 
     pub fn new_user_thread(parent_process: Arc<Spinlock<Process>>, entry_point: VirtAddr) -> Self {
-        let thread_stack = parent_process.lock().new_user_stack();
-        let kernel_stack_end = parent_process.lock().new_kernel_stack();
+        let thread_stack = parent_process
+            .lock()
+            .new_user_stack()
+            .expect("Allocating the user stack failed");
 
         let mut trap_frame = TrapFrame::new();
 
@@ -148,6 +152,15 @@ impl Thread {
         trap_frame.rsp = thread_stack as u64;
         trap_frame.rflags = 0x202;
 
+        Self::user_thread_from_trap_frame(parent_process, trap_frame)
+    }
+
+    /// Creates a user thread which starts executing with the register state of the given trap frame
+    ///
+    /// The user stack the trap frame points to has to exist already, e.g. in the copied address space of a forked process
+    pub fn user_thread_from_trap_frame(parent_process: Arc<Spinlock<Process>>, trap_frame: TrapFrame) -> Self {
+        let kernel_stack_end = parent_process.lock().new_kernel_stack();
+
         let mut context = Context::new();
 
         context.rip = thread_trampoline as *const () as u64;
@@ -158,7 +171,7 @@ impl Thread {
 
         Self {
             id: parent.next_tid(),
-            thread_stack,
+            thread_stack: trap_frame.rsp as usize,
             process: Arc::downgrade(&parent_process),
             status: Cell::new(ThreadStatus::Initial),
             priority: ThreadPriority::Normal,
@@ -167,6 +180,7 @@ impl Thread {
             kernel_stack: Some(Box::pin(KernelStack {
                 user_space_stack: 0,
                 kernel_stack_top: kernel_stack_end - 27,
+                kernel_stack_end,
             })),
         }
     }
@@ -243,19 +257,31 @@ impl Thread {
 
 impl Drop for Thread {
     fn drop(&mut self) {
-        if self.is_kernel_thread() {
-            let mut page_mapper = KERNEL_PAGE_MAPPER.lock();
-            let mut frame_allocator = FRAME_ALLOCATOR.lock();
-
-            for addr in (self.thread_stack..self.thread_stack + STACK_SIZE as usize).step_by(Size4KiB::SIZE as usize) {
-                unsafe {
-                    let page = Page::<Size4KiB>::from_start_address(VirtAddr::new(addr as u64)).unwrap();
-                    let phys_addr = page_mapper.translate(page.start_address()).unwrap();
-
-                    frame_allocator.deallocate_frame(PhysFrame::<Size4KiB>::containing_address(phys_addr));
-                    page_mapper.unmap(page.start_address());
-                }
+        // the user stack of a user thread belongs to the Vm of its process, only the kernel stack is freed here
+        let stack_top = if let Some(kernel_stack) = &self.kernel_stack {
+            kernel_stack.kernel_stack_end
+        } else if self.is_kernel_thread() {
+            self.thread_stack
+        } else {
+            return;
+        };
+
+        let mut page_mapper = KERNEL_PAGE_MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+
+        for addr in (stack_top - STACK_SIZE as usize..stack_top).step_by(Size4KiB::SIZE as usize) {
+            unsafe {
+                let page = Page::<Size4KiB>::from_start_address(VirtAddr::new(addr as u64)).unwrap();
+                let phys_addr = page_mapper.translate(page.start_address()).unwrap();
+
+                frame_allocator.deallocate_frame(PhysFrame::<Size4KiB>::containing_address(phys_addr));
+                page_mapper.unmap(page.start_address());
             }
         }
+
+        drop(frame_allocator);
+        drop(page_mapper);
+
+        Process::release_kernel_stack(stack_top);
     }
 }
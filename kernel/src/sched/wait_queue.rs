@@ -0,0 +1,57 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use x86_64::instructions::hlt;
+
+use crate::cpu::current_thread;
+
+use super::thread::{Thread, ThreadStatus};
+
+/// List of threads which sleep until an event happens
+///
+/// A sleeping thread stays in the run queue of its CPU, but the scheduler skips it until it gets woken up.
+pub struct WaitQueue {
+    threads: Vec<Arc<Thread>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { threads: Vec::new() }
+    }
+
+    /// Marks the current thread as sleeping and adds it to the queue
+    ///
+    /// The caller has to hold the lock protecting the awaited condition, otherwise a wake up can get lost.
+    /// After releasing that lock the returned thread has to be passed to [`sleep`].
+    pub fn prepare_to_wait(&mut self) -> Arc<Thread> {
+        let thread = current_thread();
+
+        thread.status.set(ThreadStatus::Sleeping);
+        self.threads.push(thread.clone());
+
+        thread
+    }
+
+    /// Wakes up all threads in the queue
+    pub fn wake_all(&mut self) {
+        for thread in self.threads.drain(..) {
+            if thread.status.get() == ThreadStatus::Sleeping {
+                thread.status.set(ThreadStatus::Ready);
+            }
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Waits until the given thread, which has to be the current one, got woken up
+///
+/// Interrupts have to be enabled, since the thread only gets switched away on the next reschedule
+pub fn sleep(thread: &Thread) {
+    while thread.status.get() == ThreadStatus::Sleeping {
+        hlt();
+    }
+}
@@ -1,9 +1,8 @@
-use alloc::string::{String, ToString};
-use core::{
-    arch::naked_asm,
-    ffi::{CStr, c_char},
-};
-use libxernel::syscall::{SYS_CLOSE, SYS_LOG, SYS_MMAP, SYS_OPEN, SYS_READ, SYS_WRITE, SyscallError};
+pub mod table;
+pub mod user_ptr;
+
+use core::arch::naked_asm;
+use libxernel::syscall::SyscallError;
 use x86_64::{
     VirtAddr,
     registers::{
@@ -12,23 +11,31 @@ use x86_64::{
     },
 };
 
-use crate::{
-    arch::amd64::gdt::GDT_BSP,
-    fs::{self, vfs_syscalls},
-    mem::mmap::mmap,
-};
+use crate::{arch::amd64::gdt::GDT_BSP, cpu::current_process, fs, logger::syscall_tracing};
+
+use table::SyscallEntry;
 
 impl From<fs::Error> for SyscallError {
     fn from(err: fs::Error) -> SyscallError {
         match err {
-            fs::Error::VNodeNotFound => SyscallError::VNodeNotFound,
+            fs::Error::VNodeNotFound => SyscallError::EntryNotFound,
             fs::Error::NotADirectory => SyscallError::NotADirectory,
             fs::Error::IsADirectory => SyscallError::IsADirectory,
             fs::Error::NoSpace => SyscallError::NoSpace,
             fs::Error::NotEmpty => SyscallError::NotEmpty,
             fs::Error::EntryNotFound => SyscallError::EntryNotFound,
-            fs::Error::MountPointNotFound => SyscallError::MountPointNotFound,
-            fs::Error::FileSystemNotFound => SyscallError::FileSystemNotFound,
+            fs::Error::MountPointNotFound => SyscallError::EntryNotFound,
+            fs::Error::FileSystemNotFound => SyscallError::NoSuchDevice,
+            fs::Error::EntryExists => SyscallError::AlreadyExists,
+            fs::Error::Busy => SyscallError::Busy,
+            fs::Error::CrossDevice => SyscallError::CrossDeviceLink,
+            fs::Error::NotPermitted => SyscallError::NoPermission,
+            fs::Error::InvalidArgument => SyscallError::InvalidArgument,
+            fs::Error::TooManySymlinks => SyscallError::TooManySymlinks,
+            fs::Error::BadFileDescriptor => SyscallError::BadFileDescriptor,
+            fs::Error::ReadOnlyFileSystem => SyscallError::ReadOnlyFileSystem,
+            fs::Error::NotABlockDevice => SyscallError::NotABlockDevice,
+            fs::Error::FileTooLarge => SyscallError::FileTooLarge,
         }
     }
 }
@@ -56,18 +63,26 @@ pub fn init() {
     x86_64::registers::model_specific::SFMask::write(RFlags::INTERRUPT_FLAG);
 }
 
+/// The user register state which is saved on the kernel stack on every syscall
 #[derive(Debug)]
 #[repr(C)]
-struct SyscallData {
-    syscall_number: usize,
-    arg0: usize,
-    arg1: usize,
-    arg2: usize,
-    arg3: usize,
-    arg4: usize,
-    arg5: usize,
-    eflags: usize,
-    return_address: usize,
+pub struct SyscallData {
+    pub syscall_number: usize,
+    pub arg0: usize,
+    pub arg1: usize,
+    pub arg2: usize,
+    pub arg3: usize,
+    pub arg4: usize,
+    pub arg5: usize,
+    pub eflags: usize,
+    pub return_address: usize,
+    pub r15: usize,
+    pub r14: usize,
+    pub r13: usize,
+    pub r12: usize,
+    pub rbx: usize,
+    pub rbp: usize,
+    pub user_rsp: usize,
 }
 
 /*
@@ -92,10 +107,12 @@ extern "C" fn asm_syscall_handler() {
     mov gs:0, rsp # save the stackpointer for this task
     mov rsp, gs:8 # load the kernel stackpointer for this task
 
-    swapgs # TODO: fix the kernel to not rely on the KERNEL_GS_BASE MSR containing the cpu_data
-
     and rsp, -16 # align stack to 16 bytes
 
+    push qword ptr gs:0 # keep the user stackpointer on the kernel stack, gs:0 is overwritten by other threads
+
+    swapgs # TODO: fix the kernel to not rely on the KERNEL_GS_BASE MSR containing the cpu_data
+
     # backup registers for sysretq
     push rbp
     push rbx # save callee-saved registers
@@ -141,64 +158,41 @@ extern "C" fn asm_syscall_handler() {
     pop rbx
     pop rbp # restore stack and registers for sysretq
 
-    swapgs # TODO: fix the kernel to not rely on the KERNEL_GS_BASE MSR containing the cpu_data
-
-    mov rsp, gs:0 # load the stackpointer for this task
+    pop rsp # load the stackpointer for this task
 
-    swapgs
     sysretq
     "
     );
 }
 
-fn syscall_arg_to_slice<'a, T>(ptr: usize, len: usize) -> &'a mut [T] {
-    unsafe { core::slice::from_raw_parts_mut(ptr as *mut T, len) }
-}
+#[unsafe(no_mangle)]
+extern "sysv64" fn general_syscall_handler(data: *mut SyscallData) -> i64 {
+    let data = unsafe { &mut *data };
 
-fn syscall_arg_to_reference<'a, T>(ptr: usize) -> &'a mut T {
-    unsafe { &mut *(ptr as *mut T) }
-}
+    let result = match table::syscall_entry(data.syscall_number) {
+        Some(entry) => {
+            if syscall_tracing() {
+                trace_syscall(entry, data);
+            }
 
-fn syscall_arg_to_string(ptr: usize) -> Option<String> {
-    unsafe {
-        CStr::from_ptr(ptr as *const c_char)
-            .to_str()
-            .ok()
-            .map(|s| s.to_string())
-    }
-}
+            let result = (entry.handler)(data);
 
-#[unsafe(no_mangle)]
-extern "sysv64" fn general_syscall_handler(data: *const SyscallData) -> i64 {
-    let data = unsafe { &*data };
-    // println!("general_syscall_handler: {:#x?}", data);
-
-    let result = match data.syscall_number {
-        SYS_READ => vfs_syscalls::sys_read(data.arg0, syscall_arg_to_slice(data.arg1, data.arg2)),
-        SYS_WRITE => vfs_syscalls::sys_write(data.arg0, syscall_arg_to_slice(data.arg1, data.arg2)),
-        SYS_OPEN => {
-            let path = syscall_arg_to_string(data.arg0);
-
-            match path {
-                Some(path) => vfs_syscalls::sys_open(path, data.arg1 as u64),
-                None => Err(SyscallError::MalformedPath),
+            if syscall_tracing() {
+                dbg!("[{}] {} = {:?}", current_process().lock().pid, entry.name, result);
             }
+
+            result
         }
-        SYS_CLOSE => vfs_syscalls::sys_close(data.arg0),
-        SYS_MMAP => mmap(data.arg0, data.arg1, data.arg2, data.arg3, data.arg4, data.arg5),
-        SYS_LOG => {
-            let message = syscall_arg_to_string(data.arg0);
-
-            match message {
-                Some(message) => {
-                    dbg!("{}", message);
-                    Ok(0)
-                }
-                None => Err(SyscallError::InvalidArgument),
+        None => {
+            if syscall_tracing() {
+                dbg!(
+                    "[{}] unknown syscall {}",
+                    current_process().lock().pid,
+                    data.syscall_number
+                );
             }
-        }
-        _ => {
-            unimplemented!("unknown syscall: {:x?}", data);
+
+            Err(SyscallError::NotImplemented)
         }
     };
 
@@ -207,3 +201,14 @@ extern "sysv64" fn general_syscall_handler(data: *const SyscallData) -> i64 {
         Err(error) => error as i64,
     }
 }
+
+fn trace_syscall(entry: &SyscallEntry, data: &SyscallData) {
+    let args = [data.arg0, data.arg1, data.arg2, data.arg3, data.arg4, data.arg5];
+
+    dbg!(
+        "[{}] {}({:#x?})",
+        current_process().lock().pid,
+        entry.name,
+        &args[..entry.arg_count]
+    );
+}
@@ -0,0 +1,304 @@
+use libxernel::syscall::{
+    SYS_CHDIR, SYS_CHMOD, SYS_CLOSE, SYS_DUP, SYS_DUP2, SYS_EXECVE, SYS_EXIT, SYS_FCNTL, SYS_FORK, SYS_FSTAT,
+    SYS_GETCWD, SYS_GETDENTS, SYS_LINK, SYS_LOG, SYS_LSEEK, SYS_MKDIR, SYS_MMAP, SYS_MOUNT, SYS_MPROTECT, SYS_MSYNC,
+    SYS_MUNMAP, SYS_OPEN, SYS_OPENAT, SYS_PREAD, SYS_PWRITE, SYS_READ, SYS_READLINK, SYS_RENAME, SYS_RMDIR, SYS_STAT,
+    SYS_SYMLINK, SYS_UMOUNT, SYS_UNLINK, SYS_UTIMENS, SYS_WAIT4, SYS_WRITE,
+};
+
+use crate::{fs::vfs_syscalls, mem::mmap, sched::process_syscalls};
+
+use super::{
+    Result, SyscallData,
+    user_ptr::{UserPtr, UserSlice, read_user_string, read_user_string_array},
+};
+
+/// Conversion of a raw argument register into a typed syscall argument
+pub trait SyscallArg {
+    fn from_syscall_arg(arg: usize) -> Self;
+}
+
+impl SyscallArg for usize {
+    fn from_syscall_arg(arg: usize) -> Self {
+        arg
+    }
+}
+
+impl SyscallArg for isize {
+    fn from_syscall_arg(arg: usize) -> Self {
+        arg as isize
+    }
+}
+
+impl SyscallArg for u32 {
+    fn from_syscall_arg(arg: usize) -> Self {
+        arg as u32
+    }
+}
+
+impl SyscallArg for u64 {
+    fn from_syscall_arg(arg: usize) -> Self {
+        arg as u64
+    }
+}
+
+impl<T: Copy> SyscallArg for UserPtr<T> {
+    fn from_syscall_arg(arg: usize) -> Self {
+        UserPtr::new(arg)
+    }
+}
+
+impl SyscallData {
+    /// Decodes the argument with the given index
+    pub fn arg<T: SyscallArg>(&self, index: usize) -> T {
+        let arg = match index {
+            0 => self.arg0,
+            1 => self.arg1,
+            2 => self.arg2,
+            3 => self.arg3,
+            4 => self.arg4,
+            5 => self.arg5,
+            _ => panic!("syscalls only have 6 arguments"),
+        };
+
+        T::from_syscall_arg(arg)
+    }
+}
+
+type SyscallHandler = fn(&mut SyscallData) -> Result<isize>;
+
+pub struct SyscallEntry {
+    pub name: &'static str,
+    /// Number of arguments, only used for tracing
+    pub arg_count: usize,
+    pub handler: SyscallHandler,
+}
+
+impl SyscallEntry {
+    const fn new(name: &'static str, arg_count: usize, handler: SyscallHandler) -> Option<Self> {
+        Some(Self {
+            name,
+            arg_count,
+            handler,
+        })
+    }
+}
+
+/// `SYS_UMOUNT` is the highest syscall number
+const SYSCALL_COUNT: usize = SYS_UMOUNT + 1;
+
+static SYSCALL_TABLE: [Option<SyscallEntry>; SYSCALL_COUNT] = {
+    let mut table = [const { None }; SYSCALL_COUNT];
+
+    table[SYS_READ] = SyscallEntry::new("read", 3, read);
+    table[SYS_WRITE] = SyscallEntry::new("write", 3, write);
+    table[SYS_OPEN] = SyscallEntry::new("open", 3, open);
+    table[SYS_CLOSE] = SyscallEntry::new("close", 1, close);
+    table[SYS_MMAP] = SyscallEntry::new("mmap", 6, mmap);
+    table[SYS_LOG] = SyscallEntry::new("log", 1, log);
+    table[SYS_FORK] = SyscallEntry::new("fork", 0, fork);
+    table[SYS_EXECVE] = SyscallEntry::new("execve", 3, execve);
+    table[SYS_EXIT] = SyscallEntry::new("exit", 1, exit);
+    table[SYS_WAIT4] = SyscallEntry::new("wait4", 3, wait4);
+    table[SYS_MSYNC] = SyscallEntry::new("msync", 3, msync);
+    table[SYS_MUNMAP] = SyscallEntry::new("munmap", 2, munmap);
+    table[SYS_MPROTECT] = SyscallEntry::new("mprotect", 3, mprotect);
+    table[SYS_LSEEK] = SyscallEntry::new("lseek", 3, lseek);
+    table[SYS_PREAD] = SyscallEntry::new("pread", 4, pread);
+    table[SYS_PWRITE] = SyscallEntry::new("pwrite", 4, pwrite);
+    table[SYS_DUP] = SyscallEntry::new("dup", 1, dup);
+    table[SYS_DUP2] = SyscallEntry::new("dup2", 2, dup2);
+    table[SYS_FCNTL] = SyscallEntry::new("fcntl", 3, fcntl);
+    table[SYS_MKDIR] = SyscallEntry::new("mkdir", 2, mkdir);
+    table[SYS_RMDIR] = SyscallEntry::new("rmdir", 1, rmdir);
+    table[SYS_GETDENTS] = SyscallEntry::new("getdents", 3, getdents);
+    table[SYS_UNLINK] = SyscallEntry::new("unlink", 1, unlink);
+    table[SYS_RENAME] = SyscallEntry::new("rename", 2, rename);
+    table[SYS_LINK] = SyscallEntry::new("link", 2, link);
+    table[SYS_SYMLINK] = SyscallEntry::new("symlink", 2, symlink);
+    table[SYS_READLINK] = SyscallEntry::new("readlink", 3, readlink);
+    table[SYS_STAT] = SyscallEntry::new("stat", 2, stat);
+    table[SYS_FSTAT] = SyscallEntry::new("fstat", 2, fstat);
+    table[SYS_CHMOD] = SyscallEntry::new("chmod", 2, chmod);
+    table[SYS_UTIMENS] = SyscallEntry::new("utimens", 2, utimens);
+    table[SYS_CHDIR] = SyscallEntry::new("chdir", 1, chdir);
+    table[SYS_GETCWD] = SyscallEntry::new("getcwd", 2, getcwd);
+    table[SYS_OPENAT] = SyscallEntry::new("openat", 4, openat);
+    table[SYS_MOUNT] = SyscallEntry::new("mount", 4, mount);
+    table[SYS_UMOUNT] = SyscallEntry::new("umount", 1, umount);
+
+    table
+};
+
+pub fn syscall_entry(number: usize) -> Option<&'static SyscallEntry> {
+    SYSCALL_TABLE.get(number)?.as_ref()
+}
+
+fn read(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_read(data.arg(0), UserSlice::new(data.arg(1), data.arg(2)))
+}
+
+fn write(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_write(data.arg(0), UserSlice::new(data.arg(1), data.arg(2)))
+}
+
+fn open(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_open(read_user_string(data.arg(0))?, data.arg(1), data.arg(2))
+}
+
+fn openat(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_openat(data.arg(0), read_user_string(data.arg(1))?, data.arg(2), data.arg(3))
+}
+
+fn chdir(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_chdir(read_user_string(data.arg(0))?)
+}
+
+fn getcwd(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_getcwd(UserSlice::new(data.arg(0), data.arg(1)))
+}
+
+fn close(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_close(data.arg(0))
+}
+
+fn dup(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_dup(data.arg(0))
+}
+
+fn dup2(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_dup2(data.arg(0), data.arg(1))
+}
+
+fn fcntl(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_fcntl(data.arg(0), data.arg(1), data.arg(2))
+}
+
+fn mkdir(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_mkdir(read_user_string(data.arg(0))?, data.arg(1))
+}
+
+fn rmdir(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_rmdir(read_user_string(data.arg(0))?)
+}
+
+fn getdents(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_getdents(data.arg(0), UserSlice::new(data.arg(1), data.arg(2)))
+}
+
+fn unlink(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_unlink(read_user_string(data.arg(0))?)
+}
+
+fn rename(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_rename(read_user_string(data.arg(0))?, read_user_string(data.arg(1))?)
+}
+
+fn link(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_link(read_user_string(data.arg(0))?, read_user_string(data.arg(1))?)
+}
+
+fn symlink(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_symlink(read_user_string(data.arg(0))?, read_user_string(data.arg(1))?)
+}
+
+fn readlink(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_readlink(read_user_string(data.arg(0))?, UserSlice::new(data.arg(1), data.arg(2)))
+}
+
+fn stat(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_stat(read_user_string(data.arg(0))?, data.arg(1))
+}
+
+fn fstat(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_fstat(data.arg(0), data.arg(1))
+}
+
+fn chmod(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_chmod(read_user_string(data.arg(0))?, data.arg(1))
+}
+
+fn utimens(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_utimens(read_user_string(data.arg(0))?, data.arg(1))
+}
+
+fn mount(data: &mut SyscallData) -> Result<isize> {
+    // virtual file systems are mounted without a source
+    let source = match data.arg::<usize>(3) {
+        0 => None,
+        source => Some(read_user_string(source)?),
+    };
+
+    vfs_syscalls::sys_mount(
+        read_user_string(data.arg(0))?,
+        read_user_string(data.arg(1))?,
+        data.arg(2),
+        source,
+    )
+}
+
+fn umount(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_umount(read_user_string(data.arg(0))?)
+}
+
+fn lseek(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_lseek(data.arg(0), data.arg(1), data.arg(2))
+}
+
+fn pread(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_pread(data.arg(0), UserSlice::new(data.arg(1), data.arg(2)), data.arg(3))
+}
+
+fn pwrite(data: &mut SyscallData) -> Result<isize> {
+    vfs_syscalls::sys_pwrite(data.arg(0), UserSlice::new(data.arg(1), data.arg(2)), data.arg(3))
+}
+
+fn mmap(data: &mut SyscallData) -> Result<isize> {
+    mmap::mmap(
+        data.arg(0),
+        data.arg(1),
+        data.arg(2),
+        data.arg(3),
+        data.arg(4),
+        data.arg(5),
+    )
+}
+
+fn log(data: &mut SyscallData) -> Result<isize> {
+    let message = read_user_string(data.arg(0))?;
+
+    dbg!("{}", message);
+
+    Ok(0)
+}
+
+fn fork(data: &mut SyscallData) -> Result<isize> {
+    process_syscalls::sys_fork(data)
+}
+
+fn execve(data: &mut SyscallData) -> Result<isize> {
+    let path = read_user_string(data.arg(0))?;
+    let argv = read_user_string_array(data.arg(1))?;
+    let envp = read_user_string_array(data.arg(2))?;
+
+    process_syscalls::sys_execve(path, argv, envp, data)
+}
+
+fn exit(data: &mut SyscallData) -> Result<isize> {
+    process_syscalls::sys_exit(data.arg(0))
+}
+
+fn wait4(data: &mut SyscallData) -> Result<isize> {
+    process_syscalls::sys_wait4(data.arg(0), data.arg(1), data.arg(2))
+}
+
+fn msync(data: &mut SyscallData) -> Result<isize> {
+    mmap::msync(data.arg(0), data.arg(1), data.arg(2))
+}
+
+fn munmap(data: &mut SyscallData) -> Result<isize> {
+    mmap::munmap(data.arg(0), data.arg(1))
+}
+
+fn mprotect(data: &mut SyscallData) -> Result<isize> {
+    mmap::mprotect(data.arg(0), data.arg(1), data.arg(2))
+}
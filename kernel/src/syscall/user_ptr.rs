@@ -0,0 +1,227 @@
+//! Access to the memory of the calling process
+//!
+//! Every access is checked against the [`Vm`](crate::mem::vm::Vm) of the process first. Faults which still happen
+//! during the copy, e.g. because another thread unmapped the memory in the meantime, are caught by the
+//! exception fixup of the page fault handler. The process must not be locked while copying, since resolving a
+//! page fault needs the lock.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{MaybeUninit, size_of};
+
+use libxernel::syscall::{ProtectionFlags, SyscallError};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+use crate::arch::amd64::user_copy::copy_user;
+use crate::cpu::current_process;
+use crate::mem::PROCESS_END;
+
+use super::Result;
+
+/// Maximum length of a string which is read from the calling process, including the terminating NUL
+const MAX_STRING_LENGTH: usize = 4096;
+
+/// Maximum number of entries in an argument or environment array
+const MAX_STRING_ARRAY_LENGTH: usize = 1024;
+
+/// Size of the parts a [`UserSlice`] is split into by [`UserSlice::chunks`]
+pub const CHUNK_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// Pointer to a value of type `T` in the address space of the calling process
+#[derive(Debug, Clone, Copy)]
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(addr: usize) -> Self {
+        Self {
+            addr,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.addr == 0
+    }
+
+    /// Returns a pointer to the `count`th element after this one
+    pub fn add(&self, count: usize) -> Result<Self> {
+        let addr = count
+            .checked_mul(size_of::<T>())
+            .and_then(|offset| self.addr.checked_add(offset))
+            .ok_or(SyscallError::BadAddress)?;
+
+        Ok(Self::new(addr))
+    }
+
+    pub fn read(&self) -> Result<T> {
+        let mut value = MaybeUninit::<T>::uninit();
+
+        check_access(self.addr, size_of::<T>(), ProtectionFlags::READ)?;
+        copy_in(value.as_mut_ptr() as *mut u8, self.addr, size_of::<T>())?;
+
+        // SAFETY: all bytes were copied from the process
+        Ok(unsafe { value.assume_init() })
+    }
+
+    pub fn write(&self, value: &T) -> Result<()> {
+        check_access(self.addr, size_of::<T>(), ProtectionFlags::WRITE)?;
+        copy_out(self.addr, value as *const T as *const u8, size_of::<T>())
+    }
+}
+
+/// Byte buffer in the address space of the calling process
+#[derive(Debug, Clone, Copy)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    pub fn new(addr: usize, len: usize) -> Self {
+        Self { addr, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies the whole buffer into kernel memory
+    ///
+    /// The kernel buffer has the size of the user buffer, large buffers should be copied with [`Self::chunks`].
+    pub fn read(&self) -> Result<Vec<u8>> {
+        check_access(self.addr, self.len, ProtectionFlags::READ)?;
+
+        let mut buf = vec![0; self.len];
+        copy_in(buf.as_mut_ptr(), self.addr, self.len)?;
+
+        Ok(buf)
+    }
+
+    /// Copies `data` to the start of the buffer, fails if it doesn't fit
+    pub fn write(&self, data: &[u8]) -> Result<()> {
+        if data.len() > self.len {
+            return Err(SyscallError::InvalidArgument);
+        }
+
+        check_access(self.addr, data.len(), ProtectionFlags::WRITE)?;
+        copy_out(self.addr, data.as_ptr(), data.len())
+    }
+
+    /// Checks that the whole buffer is mapped with the protection `prot`, without copying anything
+    pub fn check(&self, prot: ProtectionFlags) -> Result<()> {
+        check_access(self.addr, self.len, prot)
+    }
+
+    /// Splits the buffer into consecutive parts of at most [`CHUNK_SIZE`] bytes
+    pub fn chunks(&self) -> impl Iterator<Item = UserSlice> {
+        let this = *self;
+
+        (0..self.len)
+            .step_by(CHUNK_SIZE)
+            .map(move |offset| UserSlice::new(this.addr.wrapping_add(offset), CHUNK_SIZE.min(this.len - offset)))
+    }
+}
+
+/// Reads a NUL terminated UTF-8 string from the calling process
+pub fn read_user_string(addr: usize) -> Result<String> {
+    let mut bytes = Vec::new();
+    let mut addr = addr;
+
+    loop {
+        // read up to the end of the page, the next page may not be mapped
+        let chunk_len = Size4KiB::SIZE as usize - addr % Size4KiB::SIZE as usize;
+        let chunk = UserSlice::new(addr, chunk_len).read()?;
+
+        if let Some(nul) = chunk.iter().position(|&byte| byte == 0) {
+            bytes.extend_from_slice(&chunk[..nul]);
+            break;
+        }
+
+        bytes.extend_from_slice(&chunk);
+        addr += chunk_len;
+
+        if bytes.len() >= MAX_STRING_LENGTH {
+            return Err(SyscallError::InvalidArgument);
+        }
+    }
+
+    if bytes.len() >= MAX_STRING_LENGTH {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    String::from_utf8(bytes).map_err(|_| SyscallError::InvalidArgument)
+}
+
+/// Reads a NULL terminated array of string pointers, a null pointer is treated as an empty array
+pub fn read_user_string_array(addr: usize) -> Result<Vec<String>> {
+    let mut strings = Vec::new();
+
+    if addr == 0 {
+        return Ok(strings);
+    }
+
+    let array = UserPtr::<usize>::new(addr);
+
+    for i in 0..MAX_STRING_ARRAY_LENGTH {
+        let string_ptr = array.add(i)?.read()?;
+
+        if string_ptr == 0 {
+            return Ok(strings);
+        }
+
+        strings.push(read_user_string(string_ptr)?);
+    }
+
+    Err(SyscallError::InvalidArgument)
+}
+
+/// Checks that the range belongs to the user address space and is mapped with the protection `prot`
+fn check_access(addr: usize, len: usize, prot: ProtectionFlags) -> Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    let end = addr.checked_add(len).ok_or(SyscallError::BadAddress)?;
+
+    if addr == 0 || end as u64 > PROCESS_END {
+        return Err(SyscallError::BadAddress);
+    }
+
+    let process = current_process();
+    let process = process.lock();
+
+    if process
+        .vm
+        .is_accessible(VirtAddr::new(addr as u64), VirtAddr::new(end as u64), prot)
+    {
+        Ok(())
+    } else {
+        Err(SyscallError::BadAddress)
+    }
+}
+
+fn copy_in(dst: *mut u8, src: usize, len: usize) -> Result<()> {
+    if unsafe { copy_user(dst, src as *const u8, len) } {
+        Ok(())
+    } else {
+        Err(SyscallError::BadAddress)
+    }
+}
+
+fn copy_out(dst: usize, src: *const u8, len: usize) -> Result<()> {
+    if unsafe { copy_user(dst as *mut u8, src, len) } {
+        Ok(())
+    } else {
+        Err(SyscallError::BadAddress)
+    }
+}
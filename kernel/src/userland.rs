@@ -1,24 +1,34 @@
-use alloc::sync::Arc;
+use alloc::{string::ToString, sync::Arc};
 use libxernel::sync::Spinlock;
 
 use crate::{
     cpu::current_cpu,
-    fs::initramfs::initramfs_read,
+    fs::vfs::VFS,
     sched::{
-        process::{KERNEL_PROCESS, Process},
+        process::{INIT_PROCESS, KERNEL_PROCESS, Process},
         thread::Thread,
     },
 };
 
 pub fn init() {
-    let init_elf = initramfs_read("init").expect("init process not found in initramfs");
+    let init = VFS
+        .lock()
+        .lookuppn("/init".to_string())
+        .expect("init process not found in initramfs");
+    let init_elf = init.lock().read_all().expect("Reading init failed");
+
     let init_process = Arc::new(Spinlock::new(Process::new(Some(KERNEL_PROCESS.clone()))));
     KERNEL_PROCESS.lock().children.push(init_process.clone());
+    INIT_PROCESS.set_once(init_process.clone());
 
-    let entry_point = init_process.aquire().load_elf(&init_elf);
+    let entry_point = init_process
+        .aquire()
+        .load_elf(&init_elf)
+        .expect("init is not a valid ELF file");
 
     dbg!("init process entry point: {:#x}", entry_point);
 
-    let init_thread = Thread::new_user_thread(init_process.clone(), entry_point);
-    current_cpu().enqueue_thread(Arc::new(init_thread));
+    let init_thread = Arc::new(Thread::new_user_thread(init_process.clone(), entry_point));
+    init_process.lock().threads.push(init_thread.clone());
+    current_cpu().enqueue_thread(init_thread);
 }
@@ -2,22 +2,23 @@ use limine::{File, ModuleRequest};
 
 static MODULE_REQUEST: ModuleRequest = ModuleRequest::new(0);
 
-pub fn get_limine_module(name: &str) -> Option<&File> {
+/// Returns all modules together with their name, which is the cmdline given in `limine.conf`
+pub fn limine_modules() -> impl Iterator<Item = (&'static str, &'static File)> {
     let modules = MODULE_REQUEST.get_response().get().unwrap().modules();
 
-    for m in modules {
+    modules.iter().map(|m| {
         // NOTE: the cmdline is wrapped in quotes, so we need to remove them
         let cmdline = m.cmdline.to_str().unwrap().to_str().unwrap();
         let mut cmd_chars = cmdline.chars();
         cmd_chars.next();
         cmd_chars.next_back();
 
-        let cmdline_name = cmd_chars.as_str();
-
-        if cmdline_name == name {
-            return Some(unsafe { &*m.as_ptr() });
-        }
-    }
+        (cmd_chars.as_str(), unsafe { &*m.as_ptr() })
+    })
+}
 
-    None
+pub fn get_limine_module(name: &str) -> Option<&File> {
+    limine_modules()
+        .find(|(module_name, _)| *module_name == name)
+        .map(|(_, module)| module)
 }
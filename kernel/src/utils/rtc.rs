@@ -1,15 +1,23 @@
+use crate::acpi::hpet;
 use crate::arch::amd64::ports::{inb, outb};
 use core::arch::asm;
+use libxernel::sync::Once;
+use libxernel::syscall::Timespec;
+
 const CMOSAddress: u16 = 0x70;
 const CMOSData: u16 = 0x71;
 
+/// Wall clock time at boot in seconds since the Unix epoch, together with the hpet uptime in microseconds it was
+/// read at
+static BOOT_TIME: Once<(i64, u64)> = Once::new();
+
 pub struct Rtc;
 
 impl Rtc {
     pub fn read() {
         let status: u8 = Rtc::read_cmos(0x0b);
 
-        let bcd: bool = !(status & 0x04) > 0;
+        let bcd: bool = status & 0x04 == 0;
 
         while Rtc::read_cmos(0x0A) & 0x80 > 0 {
             unsafe {
@@ -24,6 +32,8 @@ impl Rtc {
         let month = Rtc::decode(Rtc::read_cmos(0x08), bcd);
         let year = Rtc::decode(Rtc::read_cmos(0x09), bcd) + 2000;
 
+        BOOT_TIME.set_once((unix_time(year, month, day, hour, minute, second), hpet::microseconds()));
+
         println!(
             "Booted at: {}-{}-{} {}:{}:{} GMT",
             year, month, day, hour, minute, second
@@ -45,3 +55,86 @@ impl Rtc {
         }
     }
 }
+
+/// Returns the current wall clock time, the time read from the RTC at boot plus the uptime since then
+///
+/// Before the RTC has been read the time is counted from the Unix epoch.
+pub fn now() -> Timespec {
+    let (boot_seconds, boot_micros) = if BOOT_TIME.is_completed() { *BOOT_TIME } else { (0, 0) };
+
+    let uptime = hpet::microseconds().saturating_sub(boot_micros);
+
+    Timespec {
+        tv_sec: boot_seconds + (uptime / 1_000_000) as i64,
+        tv_nsec: ((uptime % 1_000_000) * 1000) as i64,
+    }
+}
+
+/// Converts a date in UTC to seconds since the Unix epoch
+pub fn unix_time(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64) -> i64 {
+    // days since the epoch, counted in eras of 400 years starting at March 1st so leap days come last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    days * 86400 + hour * 3600 + minute * 60 + second
+}
+
+/// Converts seconds since the Unix epoch to a date in UTC, returned as (year, month, day, hour, minute, second)
+pub fn date_time(time: i64) -> (i64, i64, i64, i64, i64, i64) {
+    let days = time.div_euclid(86400);
+    let seconds = time.rem_euclid(86400);
+
+    // inverse of unix_time, the eras start at March 1st
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_time_of_known_dates() {
+        assert_eq!(unix_time(1970, 1, 1, 0, 0, 0), 0);
+        assert_eq!(unix_time(2000, 3, 1, 0, 0, 0), 951868800);
+        assert_eq!(unix_time(2024, 2, 29, 12, 34, 56), 1709210096);
+        assert_eq!(unix_time(2038, 1, 19, 3, 14, 8), 2147483648);
+        assert_eq!(unix_time(1900, 1, 1, 0, 0, 0), -2208988800);
+    }
+
+    #[test]
+    fn date_time_of_known_times() {
+        assert_eq!(date_time(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(date_time(-1), (1969, 12, 31, 23, 59, 59));
+        assert_eq!(date_time(951868799), (2000, 2, 29, 23, 59, 59));
+        assert_eq!(date_time(1709210096), (2024, 2, 29, 12, 34, 56));
+        assert_eq!(date_time(-2208988800), (1900, 1, 1, 0, 0, 0));
+    }
+
+    #[test]
+    fn date_time_is_the_inverse_of_unix_time() {
+        // steps of a bit more than a day cover every day of the year and every time of the day over the years
+        for time in (-2208988800..4102444800).step_by(86399 + 3600) {
+            let (year, month, day, hour, minute, second) = date_time(time);
+
+            assert_eq!(unix_time(year, month, day, hour, minute, second), time);
+        }
+    }
+}
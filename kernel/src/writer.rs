@@ -1,9 +1,12 @@
+use alloc::string::String;
 use core::fmt;
 use core::fmt::Write;
 
 use libxernel::sync::SpinlockIRQ;
 
 use crate::framebuffer::FRAMEBUFFER;
+use crate::fs::Result;
+use crate::fs::devfs::CharDevice;
 
 struct Writer;
 
@@ -17,6 +20,23 @@ impl core::fmt::Write for Writer {
     }
 }
 
+/// `/dev/console`, writes are printed on the framebuffer
+///
+/// Reads return end of file, since keyboard input is not translated into characters yet.
+pub struct Console;
+
+impl CharDevice for Console {
+    fn read(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        _print(format_args!("{}", String::from_utf8_lossy(buf)));
+
+        Ok(buf.len())
+    }
+}
+
 #[macro_export]
 macro_rules! println {
     ($($arg:tt)*) => ($crate::writer::_println(format_args!($($arg)*)));